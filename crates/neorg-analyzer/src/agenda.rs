@@ -0,0 +1,264 @@
+//! Todo items across the workspace, for editors to render agenda views.
+//!
+//! Backs the `neorg/todos` request and the `neorg.listTodos` command. Dates
+//! come from the `(< due)` and `(> start)` todo extensions and are compared
+//! by day, written either as `2024-02-05` or like `Mon, 5th Feb 2024`.
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::{Range, Url};
+
+use crate::index::WorkspaceIndex;
+use crate::link::contains;
+use crate::structure::{Structure, Tag, TagKind, Todo, TodoStatus};
+
+/// Which todos to list. Every filter left out matches everything.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TodoFilter {
+    pub(crate) statuses: Option<Vec<TodoStatus>>,
+    /// Only todos due or starting on this day or later.
+    pub(crate) after: Option<String>,
+    /// Only todos due or starting on this day or earlier.
+    pub(crate) before: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TodoItem {
+    pub(crate) uri: Url,
+    /// The todo extension.
+    pub(crate) range: Range,
+    pub(crate) status: TodoStatus,
+    pub(crate) title: String,
+    /// Titles of the headings the item is under, outermost first.
+    pub(crate) heading_path: Vec<String>,
+    pub(crate) due: Option<String>,
+    pub(crate) start: Option<String>,
+    /// `#tag` and `+tag` lines applying to the item, without the prefix.
+    pub(crate) tags: Vec<String>,
+}
+
+/// A day, comparable in calendar order.
+type Day = (i32, u32, u32);
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// The day `text` names, if it names one.
+pub(crate) fn parse_day(text: &str) -> Option<Day> {
+    let text = text.trim();
+    let iso = text.get(..10).and_then(|date| {
+        let mut parts = date.splitn(3, '-');
+        let year = parts.next().filter(|year| year.len() == 4)?.parse().ok()?;
+        let month = parts.next()?.parse().ok()?;
+        let day = parts.next()?.parse().ok()?;
+        Some((year, month, day))
+    });
+    if iso.is_some() {
+        return iso.filter(|(_, month, day)| (1..=12).contains(month) && (1..=31).contains(day));
+    }
+
+    let (mut year, mut month, mut day) = (None, None, None);
+    for word in text.split([' ', ',']).filter(|word| !word.is_empty()) {
+        let digits = word.trim_end_matches(|c: char| c.is_alphabetic());
+        if digits.is_empty() {
+            let word = word.to_lowercase();
+            if let Some(i) = MONTHS.iter().position(|name| word.starts_with(name)) {
+                month = Some(i as u32 + 1);
+            }
+        } else if digits.len() == 4 {
+            year = digits.parse().ok();
+        } else if digits.len() <= 2 {
+            day = digits.parse().ok().filter(|day| (1..=31).contains(day));
+        }
+    }
+    Some((year?, month?, day?))
+}
+
+/// Tag lines right above `line`, nearest last.
+fn tags_above(structure: &Structure, line: u32) -> Vec<&Tag> {
+    let mut tags = Vec::new();
+    let mut above = line;
+    while let Some(prev) = above.checked_sub(1)
+        && let Some(tag) = structure.tags.iter().find(|tag| {
+            matches!(tag.kind, TagKind::Weak | TagKind::Strong) && tag.range.start.line == prev
+        })
+    {
+        tags.push(tag);
+        above = prev;
+    }
+    tags.reverse();
+    tags
+}
+
+fn tag_text(tag: &Tag) -> String {
+    std::iter::once(tag.name.as_str())
+        .chain(tag.parameters.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The headings `todo` is under, its tags and those carried over to it
+/// from the headings and list items it is nested in.
+fn context(structure: &Structure, todo: &Todo) -> (Vec<String>, Vec<String>) {
+    let start = todo.range.start;
+    let headings: Vec<_> = structure
+        .headings
+        .iter()
+        .filter(|heading| heading.range.start.line < start.line && contains(heading.section, start))
+        .collect();
+
+    let items = structure.todos.iter().filter(|parent| {
+        parent.range.start.line < start.line
+            && structure
+                .todo_extent(parent)
+                .is_some_and(|extent| contains(extent, start))
+    });
+    let parents: BTreeSet<u32> = headings
+        .iter()
+        .map(|heading| heading.range.start.line)
+        .chain(items.map(|parent| parent.range.start.line))
+        .collect();
+
+    let mut tags = Vec::new();
+    for line in parents {
+        let strong = tags_above(structure, line).into_iter();
+        tags.extend(
+            strong
+                .filter(|tag| tag.kind == TagKind::Strong)
+                .map(tag_text),
+        );
+    }
+    tags.extend(tags_above(structure, start.line).into_iter().map(tag_text));
+
+    let path = headings
+        .iter()
+        .map(|heading| heading.title.clone())
+        .collect();
+    (path, tags)
+}
+
+/// Whether `todo` is due or starts within the days of `filter`.
+fn in_period(todo: &Todo, after: Option<Day>, before: Option<Day>) -> bool {
+    if after.is_none() && before.is_none() {
+        return true;
+    }
+    [&todo.due, &todo.start]
+        .into_iter()
+        .flatten()
+        .filter_map(|date| parse_day(date))
+        .any(|day| {
+            after.is_none_or(|after| day >= after) && before.is_none_or(|before| day <= before)
+        })
+}
+
+/// Every todo of the index matching `filter`, by file and line. Fails with
+/// the offending text when a date of the filter can not be read.
+pub(crate) fn todos(index: &WorkspaceIndex, filter: &TodoFilter) -> Result<Vec<TodoItem>, String> {
+    let day = |date: &Option<String>| match date {
+        Some(date) => parse_day(date).map(Some).ok_or_else(|| date.clone()),
+        None => Ok(None),
+    };
+    let (after, before) = (day(&filter.after)?, day(&filter.before)?);
+
+    let mut files = index.files();
+    files.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+    let mut items = Vec::new();
+    for (uri, structure) in files {
+        for todo in &structure.todos {
+            let wanted = filter
+                .statuses
+                .as_ref()
+                .is_none_or(|statuses| statuses.contains(&todo.status));
+            if !wanted || !in_period(todo, after, before) {
+                continue;
+            }
+            let (heading_path, tags) = context(&structure, todo);
+            items.push(TodoItem {
+                uri: uri.clone(),
+                range: todo.range,
+                status: todo.status,
+                title: todo.title.clone(),
+                heading_path,
+                due: todo.due.clone(),
+                start: todo.start.clone(),
+                tags,
+            });
+        }
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn days_are_read_in_both_forms() {
+        assert_eq!(parse_day("2024-02-05"), Some((2024, 2, 5)));
+        assert_eq!(parse_day("2024-02-05T10:00"), Some((2024, 2, 5)));
+        assert_eq!(parse_day("Mon, 5th Feb 2024"), Some((2024, 2, 5)));
+        assert_eq!(parse_day("12 october 2023 10:30"), Some((2023, 10, 12)));
+        assert_eq!(parse_day("tomorrow"), None);
+        assert_eq!(parse_day("2024-13-01"), None);
+    }
+
+    #[test]
+    fn todos_carry_their_context() {
+        let index = WorkspaceIndex::default();
+        let Ok(uri) = Url::parse("file:///notes/a.norg") else {
+            panic!("invalid uri");
+        };
+        index.update(
+            uri,
+            "+project neorg\n* Work\n** ( ) Release\n#contexts office\n- (x|< 2024-02-01) Tag\n- (!|< 5 Mar 2024) Announce\n* (?) Someday\n",
+        );
+
+        let all = todos(&index, &TodoFilter::default()).unwrap_or_default();
+        let summary: Vec<_> = all
+            .iter()
+            .map(|item| {
+                (
+                    item.title.as_str(),
+                    item.heading_path.join("/"),
+                    item.tags.join(","),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("Release", "Work".to_owned(), "project neorg".to_owned()),
+                (
+                    "Tag",
+                    "Work/Release".to_owned(),
+                    "project neorg,contexts office".to_owned()
+                ),
+                (
+                    "Announce",
+                    "Work/Release".to_owned(),
+                    "project neorg".to_owned()
+                ),
+                ("Someday", String::new(), String::new()),
+            ]
+        );
+
+        let filter = TodoFilter {
+            statuses: Some(vec![TodoStatus::Done, TodoStatus::Urgent]),
+            after: Some("2024-02-02".to_owned()),
+            before: None,
+        };
+        let filtered = todos(&index, &filter).unwrap_or_default();
+        let titles: Vec<_> = filtered.iter().map(|item| item.title.as_str()).collect();
+        assert_eq!(titles, ["Announce"]);
+
+        let filter = TodoFilter {
+            before: Some("someday".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(todos(&index, &filter), Err("someday".to_owned()));
+    }
+}
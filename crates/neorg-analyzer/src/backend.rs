@@ -1,53 +1,211 @@
-use tower_lsp::lsp_types::Position;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
 
 use dashmap::DashMap;
 
 use ropey::Rope;
 use serde_json::Value;
-use tower_lsp::jsonrpc::Result;
+use tokio::sync::RwLock;
+use tokio::task::AbortHandle;
+use tower_lsp::jsonrpc::{Error, ErrorCode, Result};
 use tower_lsp::lsp_types::notification::Notification;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer};
 
-#[derive(Debug)]
+use crate::agenda::TodoFilter;
+use crate::config::Settings;
+use crate::dictionary::Dictionary;
+use crate::handle::ServerCommand;
+use crate::handle::semantic_tokens::TokenCache;
+use crate::index::WorkspaceIndex;
+use crate::link::Target;
+use crate::span::{self, PositionEncoding};
+use crate::spelling::{self, Hunspell, WordList};
+use crate::syntax::Syntax;
+
+/// Every field is shared, so that clones handed to background tasks see
+/// the same state.
+#[derive(Debug, Clone)]
 pub struct Backend {
     pub client: Client,
-    pub document_map: DashMap<String, Rope>,
-    pub cst_map: DashMap<String, neorg_syntax::SyntaxNode>,
+    pub document_map: Arc<DashMap<String, Document>>,
+    pub(crate) settings: Arc<RwLock<Arc<Settings>>>,
+    pub(crate) dictionary: Arc<RwLock<Arc<Dictionary>>>,
+    /// `None` when spell checking is off or no dictionary was found.
+    pub(crate) speller: Arc<RwLock<Option<Arc<Hunspell>>>>,
+    /// `.neorg-words` lists by path. Each applies to the documents below its
+    /// directory, the user's to every document.
+    pub(crate) word_lists: Arc<DashMap<PathBuf, WordList>>,
+    /// Words accepted in a single document.
+    pub(crate) ignored_words: Arc<DashMap<Url, HashSet<String>>>,
+    /// Whether the client answers `workspace/configuration` requests.
+    pub(crate) pull_configuration: Arc<AtomicBool>,
+    /// Whether the client pulls diagnostics instead of waiting for them.
+    pub(crate) pull_diagnostics: Arc<AtomicBool>,
+    /// Whether the client watches files outside the workspace folders.
+    pub(crate) watch_relative: Arc<AtomicBool>,
+    /// Whether the server may create progress tokens with
+    /// `window/workDoneProgress/create`.
+    pub(crate) work_done_progress: Arc<AtomicBool>,
+    /// Syntax diagnostics of files that are not open, by modification time.
+    pub(crate) disk_diagnostics: Arc<DashMap<Url, (SystemTime, Vec<Diagnostic>)>>,
+    /// Semantic tokens last sent for each open document.
+    pub(crate) semantic_tokens: Arc<DashMap<String, TokenCache>>,
+    /// Reparse waiting for typing to pause, for each open document.
+    pub(crate) pending_reparse: Arc<DashMap<String, AbortHandle>>,
+    /// What each open document declared when diagnostics were last pushed
+    /// for the edits to it.
+    pub(crate) declared: Arc<DashMap<String, Vec<Target>>>,
+    pub(crate) index: Arc<WorkspaceIndex>,
 }
 
-/*
-    /// The [`textDocument/prepareRename`] request is sent from the client to the server to setup
-    /// and test the validity of a rename operation at a given location.
-    ///
-    /// [`textDocument/prepareRename`]: https://microsoft.github.io/language-server-protocol/specification#textDocument_prepareRename
-    ///
-    /// # Compatibility
-    ///
-    /// This request was introduced in specification version 3.12.0.
-    #[rpc(name = "textDocument/prepareRename")]
-    async fn prepare_rename(
-        &self,
-        params: TextDocumentPositionParams,
-    ) -> Result<Option<PrepareRenameResponse>> {
-        let _ = params;
-        error!("Got a textDocument/prepareRename request, but it is not implemented");
-        Err(Error::method_not_found())
+/// An open document and the version the client gave it.
+#[derive(Debug)]
+pub struct Document {
+    pub rope: Rope,
+    pub version: i32,
+    pub(crate) syntax: Syntax,
+    /// Version `syntax` was parsed from.
+    pub(crate) parsed: i32,
+}
+
+/// How long typing has to pause before the sections edited since the last
+/// parse are parsed again and diagnostics published.
+const REPARSE_DELAY: Duration = Duration::from_millis(150);
+
+impl Backend {
+    pub fn new(client: Client) -> Self {
+        let settings = Settings::default();
+        Self {
+            client,
+            document_map: Arc::default(),
+            // loaded once `initialize` brings the settings
+            dictionary: Arc::new(RwLock::new(Arc::new(Dictionary::empty(
+                &settings.dictionary,
+            )))),
+            speller: Arc::new(RwLock::new(
+                spelling::load(&settings.spelling).map(Arc::new),
+            )),
+            word_lists: Arc::default(),
+            ignored_words: Arc::default(),
+            settings: Arc::new(RwLock::new(Arc::new(settings))),
+            pull_configuration: Arc::default(),
+            pull_diagnostics: Arc::default(),
+            watch_relative: Arc::default(),
+            work_done_progress: Arc::default(),
+            disk_diagnostics: Arc::default(),
+            semantic_tokens: Arc::default(),
+            pending_reparse: Arc::default(),
+            declared: Arc::default(),
+            index: Arc::default(),
+        }
+    }
+
+    pub(crate) async fn settings(&self) -> Arc<Settings> {
+        Arc::clone(&*self.settings.read().await)
     }
-*/
+
+    pub(crate) async fn dictionary(&self) -> Arc<Dictionary> {
+        Arc::clone(&*self.dictionary.read().await)
+    }
+
+    /// Version of the open document `uri`.
+    pub(crate) fn version(&self, uri: &str) -> Option<i32> {
+        self.document_map.get(uri).map(|doc| doc.version)
+    }
+
+    /// Fails with `ContentModified` once `uri` is no longer at `version`, for
+    /// requests whose answer was computed from an older text.
+    pub(crate) fn ensure_version(&self, uri: &str, version: Option<i32>) -> Result<()> {
+        if self.version(uri) == version {
+            return Ok(());
+        }
+        Err(Error {
+            code: ErrorCode::ContentModified,
+            message: "document changed while handling the request".into(),
+            data: None,
+        })
+    }
+
+    /// Handles the `neorg/todos` request: every todo item of the workspace,
+    /// with its status, file, headings, dates and tags. Takes a filter
+    /// `{ statuses, after, before }`, where `{}` matches every item. The
+    /// params can not be left out, tower-lsp rejects those requests.
+    pub async fn todos(&self, filter: TodoFilter) -> Result<Value> {
+        self.filter_todos(&filter)
+    }
+
+    /// Brings the CST of `uri` up to date with its text, parsing only the
+    /// sections edited since it last was. Returns the version it was parsed
+    /// from.
+    pub(crate) fn reparse(&self, uri: &str) -> Option<i32> {
+        let mut doc = self.document_map.get_mut(uri)?;
+        if doc.parsed != doc.version {
+            // a few sections at most, so the lock is not held for long
+            let Document { rope, syntax, .. } = &mut *doc;
+            syntax.parse(rope);
+            doc.parsed = doc.version;
+        }
+        Some(doc.parsed)
+    }
+}
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
-    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        let pull = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.configuration)
+            .unwrap_or(false);
+        self.pull_configuration.store(pull, Ordering::Relaxed);
+        let pull = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .is_some_and(|text_document| text_document.diagnostic.is_some());
+        self.pull_diagnostics.store(pull, Ordering::Relaxed);
+        let relative = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.did_change_watched_files.as_ref())
+            .and_then(|watched| watched.relative_pattern_support)
+            .unwrap_or(false);
+        self.watch_relative.store(relative, Ordering::Relaxed);
+        let progress = params
+            .capabilities
+            .window
+            .as_ref()
+            .and_then(|window| window.work_done_progress)
+            .unwrap_or(false);
+        self.work_done_progress.store(progress, Ordering::Relaxed);
+        let encoding = PositionEncoding::negotiate(&params.capabilities);
+        self.index.set_encoding(encoding);
+        self.add_workspace_roots(&params);
+        let loaded = match params.initialization_options {
+            Some(options) => self.update_settings(Settings::from_value(options)).await,
+            None => false,
+        };
+        if !loaded {
+            self.load_dictionary(&self.settings().await.dictionary)
+                .await;
+        }
+
         Ok(InitializeResult {
             server_info: None,
             capabilities: ServerCapabilities {
                 document_formatting_provider: Some(OneOf::Left(true)),
+                document_range_formatting_provider: Some(OneOf::Left(true)),
                 document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
-                    first_trigger_character: ';'.to_string(), // not working
-                    more_trigger_character: Some(vec!["\n".to_owned()]),
+                    first_trigger_character: "\n".to_owned(),
+                    more_trigger_character: None,
                 }),
-                position_encoding: Some(PositionEncodingKind::UTF16),
+                position_encoding: Some(encoding.kind()),
                 inlay_hint_provider: Some(OneOf::Left(true)),
                 hover_provider: Some(HoverProviderCapability::Simple(true)),
                 code_action_provider: Some(CodeActionProviderCapability::Options(
@@ -71,14 +229,22 @@ impl LanguageServer for Backend {
                 )),
                 completion_provider: Some(CompletionOptions {
                     resolve_provider: Some(false),
-                    trigger_characters: Some(vec![".".to_owned()]),
+                    trigger_characters: Some(
+                        ["{", "*", ":", "#", "@", "+", "("]
+                            .map(str::to_owned)
+                            .to_vec(),
+                    ),
                     work_done_progress_options: Default::default(),
                     all_commit_characters: None,
                     completion_item: None,
                 }),
                 execute_command_provider: Some(ExecuteCommandOptions {
-                    commands: vec!["dummy.do_something".to_owned()],
-                    work_done_progress_options: Default::default(),
+                    commands: ServerCommand::ALL
+                        .map(|command| command.name().to_owned())
+                        .to_vec(),
+                    work_done_progress_options: WorkDoneProgressOptions {
+                        work_done_progress: Some(true),
+                    },
                 }),
 
                 workspace: Some(WorkspaceServerCapabilities {
@@ -88,9 +254,22 @@ impl LanguageServer for Backend {
                     }),
                     file_operations: None,
                 }),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
                 definition_provider: Some(OneOf::Left(true)),
                 references_provider: Some(OneOf::Left(true)),
-                rename_provider: Some(OneOf::Left(true)),
+                diagnostic_provider: Some(DiagnosticServerCapabilities::Options(
+                    DiagnosticOptions {
+                        identifier: Some("neorg".to_owned()),
+                        inter_file_dependencies: true,
+                        workspace_diagnostics: true,
+                        work_done_progress_options: Default::default(),
+                    },
+                )),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: Default::default(),
+                })),
                 semantic_tokens_provider: Some(
                     SemanticTokensServerCapabilities::SemanticTokensRegistrationOptions(
                         SemanticTokensRegistrationOptions {
@@ -105,12 +284,9 @@ impl LanguageServer for Backend {
                             },
                             semantic_tokens_options: SemanticTokensOptions {
                                 work_done_progress_options: WorkDoneProgressOptions::default(),
-                                legend: SemanticTokensLegend {
-                                    token_types: crate::neorg::LEGEND_TYPE.into(),
-                                    token_modifiers: vec![],
-                                },
-                                range: None,
-                                full: Some(SemanticTokensFullOptions::Bool(true)),
+                                legend: crate::highlight::legend(),
+                                range: Some(true),
+                                full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
                             },
                             static_registration_options: StaticRegistrationOptions::default(),
                         },
@@ -122,6 +298,14 @@ impl LanguageServer for Backend {
     }
     async fn initialized(&self, _: InitializedParams) {
         eprintln!("initialized!");
+
+        if let Some(settings) = self.pull_settings().await {
+            _ = self.update_settings(settings).await;
+        }
+        self.prewarm_dictionary().await;
+        self.load_word_lists();
+        self.watch_files().await;
+        self.index_workspace();
     }
 
     async fn shutdown(&self) -> Result<()> {
@@ -130,24 +314,30 @@ impl LanguageServer for Backend {
     }
 
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        let (key, text) = (
+        let (key, text, version) = (
             params.text_document.uri.to_string(),
             params.text_document.text,
+            params.text_document.version,
         );
 
-        // insert new doc into document map
-        self.document_map.insert(key.clone(), text.into());
+        self.index.update(params.text_document.uri.clone(), &text);
+        let declared = self.declarations(&params.text_document.uri);
+        self.declared.insert(key.clone(), declared);
 
-        // Update the CST map with the new CST
-        if let Some(ctx) = self.document_map.get(&key) {
-            let cst = neorg_syntax::cst!(&ctx.to_string());
-            self.cst_map.insert(key, cst);
-        }
+        // insert new doc into document map
+        let rope = Rope::from(text);
+        let mut syntax = Syntax::new(self.index.section_lens(&params.text_document.uri));
+        syntax.parse(&rope);
+        let document = Document {
+            rope,
+            version,
+            syntax,
+            parsed: version,
+        };
+        self.document_map.insert(key, document);
 
         // == diagnostics ==
-        let diagnostics = self.get_diagnostics(params.text_document.uri.as_str());
-        self.client
-            .publish_diagnostics(params.text_document.uri.clone(), diagnostics, None)
+        self.publish_diagnostics(params.text_document.uri, Some(version))
             .await;
 
         //  if let Ok(diagnostics) = self.provide_diagnostics(params.text_document.uri.clone()) {
@@ -169,64 +359,39 @@ impl LanguageServer for Backend {
     }
 
     async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
-        let mut res = Vec::new();
-        let key = params.text_document.uri.to_string();
+        Ok(self.provide_formatting(params).await)
+    }
 
-        if let Some(text) = self.document_map.get(&key)
-            && let Some(new_text) = neorg_syntax::cst!(&text.to_string()).format()
-        {
-            let lines_count = text.lines().count();
-            let start = Position {
-                line: 0,
-                character: 0,
-            };
-            let end = if let Some(last_line) = text.lines().last() {
-                Position {
-                    line: lines_count as u32 - 1,
-                    character: last_line.chars().count() as u32,
-                }
-            } else {
-                start
-            };
-            let range = Range { start, end };
-            res.push(TextEdit { range, new_text });
-        }
-        dbg!(&res);
-        Ok(Some(res))
+    async fn range_formatting(
+        &self,
+        params: DocumentRangeFormattingParams,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        Ok(self.provide_range_formatting(params).await)
     }
 
     async fn on_type_formatting(
         &self,
-        p: DocumentOnTypeFormattingParams,
+        params: DocumentOnTypeFormattingParams,
     ) -> Result<Option<Vec<TextEdit>>> {
-        eprintln!("pressed trigger characters!");
-        let p = DocumentFormattingParams {
-            text_document: p.text_document_position.text_document,
-            options: p.options,
-            work_done_progress_params: WorkDoneProgressParams {
-                work_done_token: None,
-            },
-        };
-        self.formatting(p).await
+        Ok(self.provide_on_type_formatting(params).await)
     }
 
     async fn did_save(&self, params: DidSaveTextDocumentParams) {
-        if let Some(_text) = params.text {
-            //  self.on_change(item).await;
-            _ = self.client.semantic_tokens_refresh().await;
-        }
-
         // == diagnostics ==
-        let diagnostics = self.get_diagnostics(params.text_document.uri.as_str());
-        self.client
-            .publish_diagnostics(params.text_document.uri.clone(), diagnostics, None)
+        let version = self.reparse(params.text_document.uri.as_str());
+        self.publish_diagnostics(params.text_document.uri, version)
             .await;
         eprintln!("file saved!");
     }
     async fn did_close(&self, params: DidCloseTextDocumentParams) {
         let key = params.text_document.uri.to_string();
+        if let Some((_, pending)) = self.pending_reparse.remove(&key) {
+            pending.abort();
+        }
         self.document_map.remove(&key);
-        self.cst_map.remove(&key);
+        self.declared.remove(&key);
+        self.semantic_tokens.remove(&key);
+        self.index.close(&params.text_document.uri);
     }
 
     async fn goto_definition(
@@ -240,88 +405,99 @@ impl LanguageServer for Backend {
         }
     }
 
-    async fn references(&self, _params: ReferenceParams) -> Result<Option<Vec<Location>>> {
-        Ok(None)
+    async fn diagnostic(
+        &self,
+        params: DocumentDiagnosticParams,
+    ) -> Result<DocumentDiagnosticReportResult> {
+        Ok(self.provide_document_diagnostics(params).await)
+    }
+
+    async fn workspace_diagnostic(
+        &self,
+        params: WorkspaceDiagnosticParams,
+    ) -> Result<WorkspaceDiagnosticReportResult> {
+        Ok(self.provide_workspace_diagnostics(params).await)
+    }
+
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        Ok(self.provide_references(params))
     }
 
     async fn semantic_tokens_full(
         &self,
         params: SemanticTokensParams,
     ) -> Result<Option<SemanticTokensResult>> {
-        let key = params.text_document.uri.to_string();
-        let tokens = self
-            .cst_map
-            .get(&key)
-            .map_or(SemanticTokens::default(), |node| {
-                tower_lsp::lsp_types::SemanticTokens {
-                    result_id: None,
-                    data: node.collect_semantic_tokens(),
-                }
-            });
+        Ok(self.provide_semantic_tokens_full(params).await)
+    }
 
-        Ok(Some(SemanticTokensResult::Tokens(tokens)))
+    async fn semantic_tokens_full_delta(
+        &self,
+        params: SemanticTokensDeltaParams,
+    ) -> Result<Option<SemanticTokensFullDeltaResult>> {
+        Ok(self.provide_semantic_tokens_delta(params).await)
     }
 
     async fn semantic_tokens_range(
         &self,
-        _params: SemanticTokensRangeParams,
+        params: SemanticTokensRangeParams,
     ) -> Result<Option<SemanticTokensRangeResult>> {
-        Ok(None)
+        Ok(self.provide_semantic_tokens_range(params).await)
     }
 
-    async fn inlay_hint(
-        &self,
-        _params: tower_lsp::lsp_types::InlayHintParams,
-    ) -> Result<Option<Vec<InlayHint>>> {
-        let mut hints = Vec::new();
-        let test = InlayHint {
-            position: Position {
-                line: 1,
-                character: 2,
-            },
-            label: InlayHintLabel::String("01".to_owned()),
-            kind: Some(InlayHintKind::TYPE),
-            padding_left: Some(true),
-            padding_right: Some(true),
-            data: None,
-            text_edits: None,
-            tooltip: None,
-        };
-        hints.push(test);
-        // Ok(Some(hints))
-        Ok(None)
+    async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
+        Ok(self.provide_inlay_hints(params).await)
     }
 
-    async fn completion(&self, _params: CompletionParams) -> Result<Option<CompletionResponse>> {
-        Ok(None)
+    async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        Ok(self.provide_completion(params))
+    }
+
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<PrepareRenameResponse>> {
+        Ok(self.provide_prepare_rename(&params.text_document.uri, params.position))
     }
 
     async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
-        Ok(self.provide_rename(params))
+        self.provide_rename(params)
     }
 
-    async fn did_change_configuration(&self, _: DidChangeConfigurationParams) {
-        eprintln!("configuration changed!");
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        let settings = match self.pull_settings().await {
+            Some(settings) => settings,
+            None => Settings::from_value(params.settings),
+        };
+        if self.update_settings(settings).await {
+            self.prewarm_dictionary().await;
+        }
     }
 
-    async fn did_change_workspace_folders(&self, _: DidChangeWorkspaceFoldersParams) {
-        eprintln!("workspace folders changed!");
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        self.on_workspace_folders_changed(params);
+        // their word lists came or went with them
+        self.refresh_diagnostics().await;
     }
 
-    async fn did_change_watched_files(&self, _: DidChangeWatchedFilesParams) {
-        eprintln!("watched files have changed!");
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        self.on_watched_files_changed(params).await;
+        // links to the files may have broken or resolved
+        self.refresh_diagnostics().await;
     }
 
-    async fn execute_command(&self, _: ExecuteCommandParams) -> Result<Option<Value>> {
-        eprintln!("command executed!");
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> Result<Option<DocumentSymbolResponse>> {
+        Ok(self.provide_document_symbols(params))
+    }
 
-        match self.client.apply_edit(WorkspaceEdit::default()).await {
-            Ok(res) if res.applied => self.client.log_message(MessageType::INFO, "applied").await,
-            Ok(_) => self.client.log_message(MessageType::INFO, "rejected").await,
-            Err(err) => self.client.log_message(MessageType::ERROR, err).await,
-        }
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        Ok(self.provide_folding_ranges(params))
+    }
 
-        Ok(None)
+    async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<Value>> {
+        self.run_command(params).await
     }
 
     /// Handle hover requests
@@ -353,53 +529,148 @@ impl Backend {
         // 03. update Ropey from vec of changes in params
         let key = params.text_document.uri.to_string();
 
-        if let Some(mut doc) = self.document_map.get_mut(&key) {
+        // lines `first..=last` now stand for lines `first..=last - delta` of
+        // the previous text, `None` once the whole text was replaced
+        let mut changed: Option<(usize, usize, isize)> = None;
+        let mut replaced = false;
+        let encoding = self.index.encoding();
+        let uri = params.text_document.uri;
+        {
+            let Some(mut doc) = self.document_map.get_mut(&key) else {
+                return;
+            };
+            doc.version = params.text_document.version;
             for change in params.content_changes {
                 // Get the document content
-                let rope = doc.value_mut();
+                let rope = &mut doc.rope;
 
                 // Get the range of the change
                 if let Some(range) = change.range {
                     // Get the start and end positions of the range
-                    // Convert the position to an offset
-                    let start_idx = position_to_offset(range.start, rope).unwrap_or(0);
-                    let end_idx =
-                        position_to_offset(range.end, rope).unwrap_or(rope.len_utf16_cu());
+                    // Convert the position to a char index
+                    let start_idx = encoding
+                        .offset(rope, range.start)
+                        .unwrap_or(rope.len_chars());
+                    let end_idx = encoding
+                        .offset(rope, range.end)
+                        .unwrap_or(rope.len_chars())
+                        .max(start_idx);
                     // Replace the text in the range with the new text
                     rope.remove(start_idx..end_idx);
                     rope.insert(start_idx, &change.text);
+                    // a `\r` and a `\n` brought together break a single line
+                    let joins = |i: usize| {
+                        i > 0
+                            && rope.get_char(i - 1) == Some('\r')
+                            && rope.get_char(i) == Some('\n')
+                    };
+                    if joins(start_idx) || joins(start_idx + change.text.chars().count()) {
+                        replaced = true;
+                    }
+
+                    let (start, end) = (range.start.line as usize, range.end.line as usize);
+                    let inserted = span::lines(&change.text).len() - 1;
+                    let delta = inserted as isize - (end - start) as isize;
+                    changed = Some(match changed {
+                        None => (start, start + inserted, delta),
+                        Some((first, last, total)) => (
+                            first.min(start),
+                            last.max(end).saturating_add_signed(delta),
+                            total + delta,
+                        ),
+                    });
                 } else {
                     // If range is None, replace the whole text
                     rope.remove(0..rope.len_chars());
                     rope.insert(0, &change.text);
+                    replaced = true;
                 }
             }
-        }
 
-        // == diagnostics ==
-        let diagnostics = self.get_diagnostics(&key);
-        self.client
-            .publish_diagnostics(params.text_document.uri.clone(), diagnostics, None)
-            .await;
+            // the index rescans and the CST drops the trees of the sections
+            // around the edit, reading only their lines from the rope
+            match changed {
+                Some((first, last, delta)) if !replaced => {
+                    let old_last = last.saturating_add_signed(-delta);
+                    let splice = self
+                        .index
+                        .edit(uri.clone(), &doc.rope, first, old_last, last);
+                    doc.syntax.splice(splice);
+                }
+                _ => {
+                    self.index.update(uri.clone(), &doc.rope.to_string());
+                    doc.syntax = Syntax::new(self.index.section_lens(&uri));
+                }
+            }
+        }
 
-        if let Some(source) = self.document_map.get(&key) {
-            let source = source.to_string();
-            let mut p = neorg_syntax::Parser::new(&source);
-            let parsed = neorg_syntax::document(&mut p);
-            self.cst_map.insert(key.to_owned(), parsed);
+        // parsing the dropped sections waits for typing to pause, and is
+        // left to the last change; requests needing the CST earlier reparse
+        // on their own
+        let version = params.text_document.version;
+        let backend = self.clone();
+        let task = tokio::spawn(async move { backend.settle(uri.to_string(), version).await });
+        if let Some(previous) = self.pending_reparse.insert(key, task.abort_handle()) {
+            previous.abort();
         }
     }
-}
 
-pub fn offset_to_position(offset: usize, rope: &Rope) -> Option<Position> {
-    let line = rope.try_char_to_line(offset).ok()?;
-    let first_char_of_line = rope.try_line_to_char(line).ok()?;
-    let column = offset - first_char_of_line;
-    Some(Position::new(line as u32, column as u32))
+    /// Reparses `uri` and publishes diagnostics once it stayed at `version`
+    /// for [`REPARSE_DELAY`].
+    async fn settle(&self, uri: String, version: i32) {
+        tokio::time::sleep(REPARSE_DELAY).await;
+        if self.version(&uri) != Some(version) {
+            return;
+        }
+        self.pending_reparse.remove(&uri);
+        self.reparse(&uri);
+
+        // == diagnostics ==
+        if let Ok(uri) = Url::parse(&uri) {
+            self.publish_edit_diagnostics(&uri).await;
+        }
+    }
 }
 
-pub fn position_to_offset(position: Position, rope: &Rope) -> Option<usize> {
-    let line_char_offset = rope.try_line_to_char(position.line as usize).ok()?;
-    let slice = rope.slice(0..line_char_offset + position.character as usize);
-    Some(slice.len_utf16_cu())
+#[cfg(test)]
+mod tests {
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::testing::Server;
+
+    /// Runs on a paused clock, which jumps ahead whenever every task waits
+    /// for a timer.
+    #[tokio::test(start_paused = true)]
+    async fn changes_reparse_once_typing_pauses() {
+        let server = Server::new();
+        let uri = server.open("a.norg", "* A\n").await;
+        let backend = server.backend();
+
+        let started = Instant::now();
+        for version in 2..=4 {
+            let change = TextDocumentContentChangeEvent {
+                range: Some(Range::new(Position::new(0, 3), Position::new(0, 3))),
+                range_length: None,
+                text: "b".to_owned(),
+            };
+            let params = DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier::new(uri.clone(), version),
+                content_changes: vec![change],
+            };
+            backend.did_change(params).await;
+        }
+        // the handler does not wait for the pause
+        assert!(started.elapsed() < REPARSE_DELAY);
+        let parsed = || backend.document_map.get(uri.as_str()).map(|doc| doc.parsed);
+        assert_eq!(parsed(), Some(1));
+        assert_eq!(backend.pending_reparse.len(), 1);
+        // the index follows every change right away
+        let title = backend.index.get(&uri).map(|s| s.headings[0].title.clone());
+        assert_eq!(title.as_deref(), Some("Abbb"));
+
+        tokio::time::sleep(REPARSE_DELAY * 3).await;
+        assert_eq!(parsed(), Some(4));
+        assert!(backend.pending_reparse.is_empty());
+    }
 }
@@ -0,0 +1,250 @@
+//! Server settings, read from `initializationOptions` and refreshed from
+//! `workspace/didChangeConfiguration` and `workspace/configuration`.
+//!
+//! Every field has a default, so clients only send what they want to change:
+//!
+//! ```json
+//! { "neorg": { "dictionary": { "provider": "local", "path": "~/dict.jsonl" } } }
+//! ```
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Section name used for `workspace/configuration` pulls.
+pub(crate) const SECTION: &str = "neorg";
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct Settings {
+    pub(crate) dictionary: DictionarySettings,
+    pub(crate) formatter: FormatterSettings,
+    pub(crate) diagnostics: DiagnosticSettings,
+    pub(crate) spelling: SpellingSettings,
+    pub(crate) semantic_tokens: SemanticTokenSettings,
+    pub(crate) inlay_hints: InlayHintSettings,
+}
+
+impl Settings {
+    /// Parses either the settings object itself or one nested under `"neorg"`.
+    /// Unknown or malformed values fall back to the defaults.
+    pub(crate) fn from_value(value: Value) -> Self {
+        let value = match value {
+            Value::Object(mut map) if map.contains_key(SECTION) => {
+                map.remove(SECTION).unwrap_or_default()
+            }
+            value => value,
+        };
+        match serde_json::from_value(value) {
+            Ok(settings) => settings,
+            Err(err) => {
+                eprintln!("invalid neorg settings, using defaults: {err}");
+                Self::default()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum ProviderKind {
+    /// Local dump when one is found, online api otherwise.
+    #[default]
+    Auto,
+    Remote,
+    Local,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct DictionarySettings {
+    pub(crate) provider: ProviderKind,
+    pub(crate) language: String,
+    /// Dump used by the local provider.
+    pub(crate) path: Option<PathBuf>,
+    pub(crate) cache: CacheSettings,
+    /// Show meanings on hover.
+    pub(crate) hover: bool,
+    /// Offer synonym replacements as code actions.
+    pub(crate) synonyms: bool,
+}
+
+impl DictionarySettings {
+    /// Whether `other` builds the same dictionary, whatever it is used for.
+    pub(crate) fn same_source(&self, other: &Self) -> bool {
+        self.provider == other.provider
+            && self.language == other.language
+            && self.path == other.path
+            && self.cache == other.cache
+    }
+}
+
+impl Default for DictionarySettings {
+    fn default() -> Self {
+        Self {
+            provider: ProviderKind::default(),
+            language: "en".to_owned(),
+            path: None,
+            cache: CacheSettings::default(),
+            hover: true,
+            synonyms: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct CacheSettings {
+    /// Defaults to `<data dir>/neorg/dict`.
+    pub(crate) directory: Option<PathBuf>,
+    pub(crate) ttl_days: u64,
+    pub(crate) max_size_mb: u64,
+    /// Word list looked up in the background on startup.
+    pub(crate) prewarm: Option<PathBuf>,
+}
+
+impl CacheSettings {
+    pub(crate) fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl_days * 24 * 60 * 60)
+    }
+
+    pub(crate) fn max_size(&self) -> u64 {
+        self.max_size_mb * 1024 * 1024
+    }
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            ttl_days: 30,
+            max_size_mb: 50,
+            prewarm: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum HeadingIndent {
+    /// Leave indentation as it is.
+    #[default]
+    Keep,
+    /// Start every line at the first column.
+    None,
+    /// Start content below a heading where its title starts.
+    Title,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct FormatterSettings {
+    pub(crate) enable: bool,
+    /// Continue lists and indent below headings on a line break.
+    pub(crate) on_type: bool,
+    pub(crate) heading_indent: HeadingIndent,
+    /// Blank lines left before headings, `null` to keep them as they are.
+    pub(crate) blank_lines_before_headings: Option<usize>,
+    /// A single space after list and quote markers.
+    pub(crate) normalize_list_markers: bool,
+    /// Pad the cells of `@table`s to line up.
+    pub(crate) align_tables: bool,
+    /// Unless the client's formatting options say otherwise.
+    pub(crate) trim_trailing_whitespace: bool,
+    /// Column past which paragraphs and list items are wrapped, `null` for
+    /// no wrapping.
+    pub(crate) line_width: Option<usize>,
+}
+
+impl Default for FormatterSettings {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            on_type: true,
+            heading_indent: HeadingIndent::default(),
+            blank_lines_before_headings: None,
+            normalize_list_markers: true,
+            align_tables: true,
+            trim_trailing_whitespace: true,
+            line_width: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct DiagnosticSettings {
+    /// Syntax errors reported by the parser.
+    pub(crate) syntax: bool,
+    /// Links to headings, anchors and files that do not exist.
+    pub(crate) links: bool,
+}
+
+impl Default for DiagnosticSettings {
+    fn default() -> Self {
+        Self {
+            syntax: true,
+            links: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct SpellingSettings {
+    pub(crate) enable: bool,
+    /// Name of the Hunspell dictionary, as in `en_US.dic`.
+    pub(crate) language: String,
+    /// A `.dic` file with its `.aff` next to it, or a directory holding
+    /// `<language>.dic`. Defaults to searching `$DICPATH` and the usual
+    /// Hunspell directories.
+    pub(crate) path: Option<PathBuf>,
+}
+
+impl Default for SpellingSettings {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            language: "en_US".to_owned(),
+            path: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct SemanticTokenSettings {
+    pub(crate) enable: bool,
+}
+
+impl Default for SemanticTokenSettings {
+    fn default() -> Self {
+        Self { enable: true }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct InlayHintSettings {
+    /// `1.2.3` before heading titles.
+    pub(crate) section_numbers: bool,
+    /// `1.`, `2.`, ... after the markers of ordered list items.
+    pub(crate) list_ordinals: bool,
+    /// `3/7 done` after headings with todo items below them.
+    pub(crate) todo_progress: bool,
+    /// The first heading of the file a `{:file:}` link points at.
+    pub(crate) link_titles: bool,
+}
+
+impl Default for InlayHintSettings {
+    fn default() -> Self {
+        Self {
+            section_numbers: true,
+            list_ordinals: true,
+            todo_progress: true,
+            link_titles: true,
+        }
+    }
+}
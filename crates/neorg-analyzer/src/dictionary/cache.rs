@@ -0,0 +1,184 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::Mutex;
+
+use super::DictionaryError;
+use crate::types::DictionaryEntry;
+
+/// On-disk cache of dictionary lookups.
+///
+/// Each word is stored as the raw `DictionaryEntry` json under
+/// `<dir>/<language>/<word>.json`; the file's modification time is its age.
+/// The directory is swept on the first write, and again whenever writes grow
+/// it past `max_size`.
+#[derive(Debug)]
+pub(crate) struct DictionaryCache {
+    dir: PathBuf,
+    ttl: Duration,
+    max_size: u64,
+    /// Bytes taken by the cache, `None` until the first sweep measured it.
+    size: Mutex<Option<u64>>,
+}
+
+impl DictionaryCache {
+    pub(crate) fn new(dir: PathBuf, ttl: Duration, max_size: u64) -> Self {
+        Self {
+            dir,
+            ttl,
+            max_size,
+            size: Mutex::new(None),
+        }
+    }
+
+    /// `<data dir>/neorg/dict`, falling back to the working directory.
+    pub(crate) fn default_dir() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_default()
+            .join("neorg")
+            .join("dict")
+    }
+
+    /// Path of `word` with the given extension, or `None` when `word` cannot be
+    /// used as a file name.
+    pub(crate) fn path(&self, word: &str, language: &str, extension: &str) -> Option<PathBuf> {
+        let key = word.to_lowercase();
+        let valid = !key.is_empty()
+            && key
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '-' || c == '\'');
+        valid.then(|| self.dir.join(language).join(format!("{key}.{extension}")))
+    }
+
+    pub(crate) async fn get(&self, word: &str, language: &str) -> Option<Vec<DictionaryEntry>> {
+        let path = self.path(word, language, "json")?;
+        let metadata = tokio::fs::metadata(&path).await.ok()?;
+        if is_expired(metadata.modified().ok()?, self.ttl) {
+            let mut size = self.size.lock().await;
+            if tokio::fs::remove_file(&path).await.is_ok() {
+                *size = size.map(|size| size.saturating_sub(metadata.len()));
+            }
+            return None;
+        }
+        let ctx = tokio::fs::read_to_string(&path).await.ok()?;
+        serde_json::from_str(&ctx).ok()
+    }
+
+    pub(crate) async fn put(
+        &self,
+        word: &str,
+        language: &str,
+        entries: &[DictionaryEntry],
+    ) -> Result<(), DictionaryError> {
+        let Some(path) = self.path(word, language, "json") else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let json = serde_json::to_vec(entries)?;
+        let mut size = self.size.lock().await;
+        let replaced = tokio::fs::metadata(&path).await.map_or(0, |old| old.len());
+        tokio::fs::write(&path, &json).await?;
+        let grown = size.map(|size| size.saturating_sub(replaced) + json.len() as u64);
+        *size = Some(match grown {
+            Some(grown) if grown <= self.max_size => grown,
+            _ => self.evict().await?,
+        });
+        Ok(())
+    }
+
+    /// Removes expired files, then the oldest ones until the cache fits in
+    /// `max_size`. Returns the size left.
+    async fn evict(&self) -> Result<u64, DictionaryError> {
+        let mut files = Vec::new();
+        let mut dirs = vec![self.dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            let mut read_dir = tokio::fs::read_dir(&dir).await?;
+            while let Some(entry) = read_dir.next_entry().await? {
+                let metadata = entry.metadata().await?;
+                if metadata.is_dir() {
+                    dirs.push(entry.path());
+                } else {
+                    let modified = metadata.modified()?;
+                    files.push((modified, metadata.len(), entry.path()));
+                }
+            }
+        }
+
+        files.sort_by_key(|(modified, ..)| *modified);
+        let mut size: u64 = files.iter().map(|(_, len, _)| len).sum();
+        for (modified, len, path) in files {
+            if size <= self.max_size && !is_expired(modified, self.ttl) {
+                continue;
+            }
+            tokio::fs::remove_file(&path).await?;
+            size = size.saturating_sub(len);
+        }
+        Ok(size)
+    }
+}
+
+fn is_expired(modified: SystemTime, ttl: Duration) -> bool {
+    modified.elapsed().is_ok_and(|age| age > ttl)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn entries(word: &str) -> Vec<DictionaryEntry> {
+        let json = serde_json::json!([{ "word": word, "meanings": [] }]);
+        serde_json::from_value(json).unwrap_or_default()
+    }
+
+    /// Makes the entry of `word` look `age` old.
+    fn age(cache: &DictionaryCache, word: &str, age: Duration) {
+        let path = cache.path(word, "en", "json").unwrap_or_default();
+        let file = File::options().write(true).open(path);
+        assert!(file.is_ok_and(|file| file.set_modified(SystemTime::now() - age).is_ok()));
+    }
+
+    #[tokio::test]
+    async fn expired_entries_are_dropped() {
+        let dir = TempDir::new();
+        let cache = DictionaryCache::new(dir.path().to_owned(), Duration::from_secs(60), u64::MAX);
+        assert!(cache.put("fresh", "en", &entries("fresh")).await.is_ok());
+        assert!(cache.put("stale", "en", &entries("stale")).await.is_ok());
+        age(&cache, "stale", Duration::from_secs(120));
+
+        let fresh = cache.get("fresh", "en").await;
+        assert_eq!(
+            fresh.map(|entries| entries[0].word.clone()).as_deref(),
+            Some("fresh")
+        );
+        assert!(cache.get("stale", "en").await.is_none());
+        assert!(!dir.path().join("en").join("stale.json").exists());
+        // the size left is that of the fresh entry alone
+        let len = serde_json::to_vec(&entries("fresh")).map_or(0, |json| json.len() as u64);
+        assert_eq!(*cache.size.lock().await, Some(len));
+    }
+
+    #[tokio::test]
+    async fn oldest_entries_make_room() {
+        let dir = TempDir::new();
+        let len = serde_json::to_vec(&entries("alpha")).map_or(0, |json| json.len() as u64);
+        let cache = DictionaryCache::new(dir.path().to_owned(), Duration::MAX, 2 * len);
+        for (word, minutes) in [("alpha", 3), ("bravo", 2)] {
+            assert!(cache.put(word, "en", &entries(word)).await.is_ok());
+            age(&cache, word, Duration::from_secs(minutes * 60));
+        }
+        // rewriting an entry does not count it twice
+        assert!(cache.put("bravo", "en", &entries("bravo")).await.is_ok());
+        assert!(cache.get("alpha", "en").await.is_some());
+
+        assert!(cache.put("delta", "en", &entries("delta")).await.is_ok());
+        assert!(cache.get("alpha", "en").await.is_none());
+        assert!(cache.get("bravo", "en").await.is_some());
+        assert!(cache.get("delta", "en").await.is_some());
+        assert_eq!(*cache.size.lock().await, Some(2 * len));
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::{DictionaryError, DictionaryProvider};
+use crate::types::DictionaryEntry;
+
+/// Dictionary loaded from a dump on disk, so lookups never touch the network.
+///
+/// `path` may be a `.json` file holding an array of entries, a `.jsonl` file
+/// with one entry per line (the usual shape of Wiktionary extracts), or a
+/// directory containing any number of those.
+#[derive(Debug, Default)]
+pub(crate) struct LocalDictionary {
+    entries: HashMap<String, Vec<DictionaryEntry>>,
+}
+
+impl LocalDictionary {
+    pub(crate) fn load(path: &Path) -> Result<Self, DictionaryError> {
+        let mut dictionary = Self::default();
+        dictionary.load_path(path)?;
+        Ok(dictionary)
+    }
+
+    fn load_path(&mut self, path: &Path) -> Result<(), DictionaryError> {
+        if path.is_dir() {
+            for entry in std::fs::read_dir(path)? {
+                let path = entry?.path();
+                if path.is_dir() || is_dump(&path) {
+                    self.load_path(&path)?;
+                }
+            }
+            return Ok(());
+        }
+
+        let ctx = std::fs::read_to_string(path)?;
+        if path.extension().is_some_and(|ext| ext == "jsonl") {
+            for line in ctx.lines().filter(|line| !line.trim().is_empty()) {
+                self.insert(serde_json::from_str(line)?);
+            }
+        } else {
+            for entry in serde_json::from_str::<Vec<DictionaryEntry>>(&ctx)? {
+                self.insert(entry);
+            }
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, entry: DictionaryEntry) {
+        self.entries
+            .entry(entry.word.to_lowercase())
+            .or_default()
+            .push(entry);
+    }
+}
+
+fn is_dump(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == "json" || ext == "jsonl")
+}
+
+impl DictionaryProvider for LocalDictionary {
+    async fn lookup(&self, word: &str) -> Result<Vec<DictionaryEntry>, DictionaryError> {
+        self.entries
+            .get(&word.to_lowercase())
+            .cloned()
+            .ok_or_else(|| DictionaryError::NotFound(word.to_owned()))
+    }
+}
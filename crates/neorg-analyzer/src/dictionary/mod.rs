@@ -0,0 +1,264 @@
+//! Word lookups used by hover, go-to-definition and the synonym code actions.
+//!
+//! Every dictionary feature goes through [`DictionaryProvider`], so the
+//! server works the same whether entries come from `api.dictionaryapi.dev`
+//! or from a dump on disk.
+
+mod cache;
+mod local;
+mod remote;
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+pub(crate) use cache::DictionaryCache;
+pub(crate) use local::LocalDictionary;
+pub(crate) use remote::RemoteDictionary;
+
+use crate::config::{DictionarySettings, ProviderKind};
+use crate::types::{DictionaryEntry, MarkDown};
+
+/// Environment variable pointing at a local dictionary dump.
+const DICTIONARY_ENV: &str = "NEORG_DICTIONARY";
+/// Environment variable pointing at a word list used to pre-warm the cache.
+const PREWARM_ENV: &str = "NEORG_DICTIONARY_WORDS";
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum DictionaryError {
+    #[error("dictionary request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("dictionary i/o failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("malformed dictionary dump: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("no dictionary entry for `{0}`")]
+    NotFound(String),
+}
+
+pub(crate) trait DictionaryProvider {
+    /// Returns every entry known for `word`.
+    async fn lookup(&self, word: &str) -> Result<Vec<DictionaryEntry>, DictionaryError>;
+}
+
+#[derive(Debug)]
+pub(crate) enum Provider {
+    Remote(RemoteDictionary),
+    Local(LocalDictionary),
+}
+
+impl DictionaryProvider for Provider {
+    async fn lookup(&self, word: &str) -> Result<Vec<DictionaryEntry>, DictionaryError> {
+        match self {
+            Self::Remote(remote) => remote.lookup(word).await,
+            Self::Local(local) => local.lookup(word).await,
+        }
+    }
+}
+
+/// A [`Provider`] fronted by the on-disk [`DictionaryCache`].
+#[derive(Debug)]
+pub(crate) struct Dictionary {
+    provider: Provider,
+    cache: DictionaryCache,
+    language: String,
+}
+
+impl Dictionary {
+    pub(crate) fn new(provider: Provider, cache: DictionaryCache, language: &str) -> Self {
+        Self {
+            provider,
+            cache,
+            language: language.to_owned(),
+        }
+    }
+
+    /// A dictionary knowing no words, until the settings are read.
+    pub(crate) fn empty(settings: &DictionarySettings) -> Self {
+        let cache = DictionaryCache::new(
+            DictionaryCache::default_dir(),
+            settings.cache.ttl(),
+            settings.cache.max_size(),
+        );
+        Self::new(
+            Provider::Local(LocalDictionary::default()),
+            cache,
+            &settings.language,
+        )
+    }
+
+    /// Builds the dictionary described by `settings`.
+    ///
+    /// The `auto` provider uses the dump named by `path`, `$NEORG_DICTIONARY` or
+    /// `<data dir>/neorg/dictionary.json`, whichever exists first, and falls back
+    /// to the online api otherwise.
+    pub(crate) fn from_settings(settings: &DictionarySettings) -> Self {
+        let remote = || Provider::Remote(RemoteDictionary::new(&settings.language));
+        let path = settings
+            .path
+            .clone()
+            .or_else(|| std::env::var_os(DICTIONARY_ENV).map(PathBuf::from))
+            .or_else(|| dirs::data_dir().map(|dir| dir.join("neorg").join("dictionary.json")));
+
+        let provider = match (settings.provider, path) {
+            (ProviderKind::Remote, _) => remote(),
+            (ProviderKind::Auto, Some(path)) if !path.exists() => remote(),
+            (ProviderKind::Auto | ProviderKind::Local, Some(path)) => {
+                match LocalDictionary::load(&path) {
+                    Ok(local) => Provider::Local(local),
+                    Err(err) => {
+                        eprintln!("failed to load dictionary `{}`: {err}", path.display());
+                        Provider::Local(LocalDictionary::default())
+                    }
+                }
+            }
+            (ProviderKind::Auto, None) => remote(),
+            (ProviderKind::Local, None) => Provider::Local(LocalDictionary::default()),
+        };
+
+        let cache = DictionaryCache::new(
+            settings
+                .cache
+                .directory
+                .clone()
+                .unwrap_or_else(DictionaryCache::default_dir),
+            settings.cache.ttl(),
+            settings.cache.max_size(),
+        );
+        Self::new(provider, cache, &settings.language)
+    }
+
+    /// Word list from the settings or `$NEORG_DICTIONARY_WORDS`, if any.
+    pub(crate) fn prewarm_list(settings: &DictionarySettings) -> Option<PathBuf> {
+        settings
+            .cache
+            .prewarm
+            .clone()
+            .or_else(|| std::env::var_os(PREWARM_ENV).map(PathBuf::from))
+    }
+
+    /// Looks up every word of `list` (one per line) that is not cached yet.
+    pub(crate) async fn prewarm(&self, list: &Path) -> Result<(), DictionaryError> {
+        let ctx = tokio::fs::read_to_string(list).await?;
+        for word in ctx.lines().map(str::trim).filter(|word| !word.is_empty()) {
+            if self.cache.get(word, &self.language).await.is_none()
+                && let Err(err) = self.lookup(word).await
+            {
+                eprintln!("failed to pre-warm `{word}`: {err}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Markdown rendering of the first entry for `word`.
+    pub(crate) async fn meaning(&self, word: &str) -> Result<String, DictionaryError> {
+        let entries = self.lookup(word).await?;
+        let entry = entries
+            .first()
+            .ok_or_else(|| DictionaryError::NotFound(word.to_owned()))?;
+        let MarkDown(content) = entry.into();
+        Ok(content)
+    }
+
+    /// Writes the markdown rendering of `word` next to its cache entry and
+    /// returns the path, so editors can open it as a regular file.
+    pub(crate) async fn meaning_file(&self, word: &str) -> Result<PathBuf, DictionaryError> {
+        let path = self
+            .cache
+            .path(word, &self.language, "md")
+            .ok_or_else(|| DictionaryError::NotFound(word.to_owned()))?;
+        let meaning = self.meaning(word).await?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&path, meaning).await?;
+        Ok(path)
+    }
+
+    /// Every synonym listed for `word`, across all meanings and definitions.
+    pub(crate) async fn synonyms(&self, word: &str) -> Result<HashSet<String>, DictionaryError> {
+        let entries = self.lookup(word).await?;
+
+        let mut set = HashSet::new();
+        for entry in entries {
+            for meaning in &entry.meanings {
+                set.extend(meaning.synonyms.iter().cloned());
+                for def in &meaning.definitions {
+                    set.extend(def.synonyms.iter().cloned());
+                }
+            }
+        }
+        Ok(set)
+    }
+}
+
+impl DictionaryProvider for Dictionary {
+    async fn lookup(&self, word: &str) -> Result<Vec<DictionaryEntry>, DictionaryError> {
+        if let Some(entries) = self.cache.get(word, &self.language).await {
+            return Ok(entries);
+        }
+        let entries = self.provider.lookup(word).await?;
+        // local dumps are already on disk, only remote results are worth keeping
+        if let Provider::Remote(_) = self.provider
+            && let Err(err) = self.cache.put(word, &self.language, &entries).await
+        {
+            eprintln!("failed to cache `{word}`: {err}");
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn entry(word: &str, synonyms: &[&str]) -> String {
+        serde_json::json!({
+            "word": word,
+            "meanings": [{
+                "partOfSpeech": "noun",
+                "definitions": [{ "definition": format!("what {word} means") }],
+                "synonyms": synonyms,
+            }],
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn offline_dump_answers_lookups() {
+        let dir = TempDir::new();
+        let luck = entry("Serendipity", &["luck"]);
+        let chance = entry("serendipity", &["chance"]);
+        dir.write("dump/a.json", &format!("[{luck}]"));
+        dir.write(
+            "dump/more/b.jsonl",
+            &format!("{chance}\n\n{}\n", entry("norg", &[])),
+        );
+        dir.write("dump/notes.txt", "not a dump");
+
+        let local = LocalDictionary::load(&dir.path().join("dump"));
+        let Ok(local) = local else {
+            panic!("dump failed to load: {local:?}");
+        };
+        let provider = Provider::Local(local);
+        let entries = provider.lookup("SERENDIPITY").await;
+        assert_eq!(entries.map(|entries| entries.len()).ok(), Some(2));
+        assert!(matches!(
+            provider.lookup("missing").await,
+            Err(DictionaryError::NotFound(word)) if word == "missing"
+        ));
+
+        let cache = DictionaryCache::new(dir.path().join("cache"), Duration::MAX, u64::MAX);
+        let dictionary = Dictionary::new(provider, cache, "en");
+        let synonyms = dictionary.synonyms("serendipity").await.ok();
+        let expected = HashSet::from(["luck".to_owned(), "chance".to_owned()]);
+        assert_eq!(synonyms, Some(expected));
+        let meaning = dictionary.meaning("norg").await.ok();
+        assert!(meaning.is_some_and(|meaning| meaning.contains("what norg means")));
+        assert!(dictionary.meaning("missing").await.is_err());
+        // dumps are never copied into the cache
+        assert!(!dir.path().join("cache").exists());
+    }
+}
@@ -0,0 +1,35 @@
+use super::{DictionaryError, DictionaryProvider};
+use crate::types::DictionaryEntry;
+
+/// Looks words up on `api.dictionaryapi.dev`.
+#[derive(Debug)]
+pub(crate) struct RemoteDictionary {
+    client: reqwest::Client,
+    language: String,
+}
+
+impl RemoteDictionary {
+    pub(crate) fn new(language: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            language: language.to_owned(),
+        }
+    }
+}
+
+impl Default for RemoteDictionary {
+    fn default() -> Self {
+        Self::new("en")
+    }
+}
+
+impl DictionaryProvider for RemoteDictionary {
+    async fn lookup(&self, word: &str) -> Result<Vec<DictionaryEntry>, DictionaryError> {
+        let url = format!(
+            "https://api.dictionaryapi.dev/api/v2/entries/{}/{word}",
+            self.language
+        );
+        let response = self.client.get(&url).send().await?;
+        Ok(response.json::<Vec<DictionaryEntry>>().await?)
+    }
+}
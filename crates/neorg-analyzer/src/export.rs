@@ -0,0 +1,288 @@
+//! Converting Neorg documents to Markdown, for the `neorg.exportFile` command.
+//!
+//! Headings, lists, quotes, todos, definitions, links, bold, italic,
+//! strikethrough and verbatim map to their CommonMark counterparts. Markup
+//! Markdown lacks, like underline or spoilers, is kept as plain text, and
+//! `@document.meta` is dropped.
+
+use crate::span::lines;
+
+/// Renders `text` as CommonMark.
+pub(crate) fn markdown(text: &str) -> String {
+    let mut out: Vec<String> = Vec::new();
+    // inside a ranged verbatim tag, and whether it is rendered
+    let mut verbatim: Option<bool> = None;
+
+    for line in lines(text) {
+        let trimmed = line.trim();
+        if let Some(rendered) = verbatim {
+            if trimmed == "@end" {
+                if rendered {
+                    out.push("```".to_owned());
+                }
+                verbatim = None;
+            } else if rendered {
+                out.push(line.to_owned());
+            }
+            continue;
+        }
+
+        if let Some(tag) = trimmed.strip_prefix('@') {
+            let mut words = tag.split_whitespace();
+            let name = words.next().unwrap_or_default();
+            if name == "document.meta" {
+                verbatim = Some(false);
+            } else if name != "end" {
+                let info = if name == "code" {
+                    words.next().unwrap_or_default()
+                } else {
+                    name
+                };
+                out.push(format!("```{info}"));
+                verbatim = Some(true);
+            }
+            continue;
+        }
+        // other tags only carry metadata
+        if trimmed.starts_with(['|', '=', '#', '+'])
+            && trimmed[1..].starts_with(|c: char| c.is_alphabetic())
+        {
+            continue;
+        }
+        if trimmed.len() >= 3 && trimmed.chars().all(|c| c == '_') {
+            out.push("---".to_owned());
+            continue;
+        }
+
+        out.push(detached(trimmed).unwrap_or_else(|| inline(trimmed)));
+    }
+
+    let mut markdown = out.join("\n");
+    markdown.truncate(markdown.trim_end().len());
+    markdown.push('\n');
+    markdown
+}
+
+/// A heading, list item, quote or definition line.
+fn detached(line: &str) -> Option<String> {
+    let marker = line.chars().next()?;
+    if !matches!(marker, '*' | '-' | '~' | '>' | '$' | '^') {
+        return None;
+    }
+    let level = line.chars().take_while(|c| *c == marker).count();
+    let rest = &line[level..];
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let content = inline(&todo(rest.trim()));
+    let indent = level - 1;
+    Some(match marker {
+        '*' => format!("{} {content}", "#".repeat(level.min(6))),
+        '-' => format!("{}- {content}", "  ".repeat(indent)),
+        '~' => format!("{}1. {content}", "   ".repeat(indent)),
+        '>' => format!("{} {content}", ">".repeat(level)),
+        '$' => format!("**{content}**"),
+        _ => content,
+    })
+}
+
+/// Replaces a leading todo extension like `(x)` with a task list box.
+fn todo(text: &str) -> String {
+    let Some(inner) = text.strip_prefix('(') else {
+        return text.to_owned();
+    };
+    let Some(close) = inner.find(')') else {
+        return text.to_owned();
+    };
+    let rest = inner[close + 1..].trim_start();
+    if inner[..close].split('|').any(|item| item == "x") {
+        format!("[x] {rest}")
+    } else {
+        format!("[ ] {rest}")
+    }
+}
+
+/// Markdown markup opening and closing what an attached modifier marks up.
+fn modifier(c: char) -> Option<(&'static str, &'static str)> {
+    Some(match c {
+        '*' => ("**", "**"),
+        '/' => ("*", "*"),
+        '-' => ("~~", "~~"),
+        '_' | '!' => ("", ""),
+        '^' => ("<sup>", "</sup>"),
+        ',' => ("<sub>", "</sub>"),
+        _ => return None,
+    })
+}
+
+/// The closing position of an attached modifier opening at `i`.
+pub(crate) fn closing(chars: &[char], i: usize) -> Option<usize> {
+    let c = chars[i];
+    let opens = (i == 0 || !chars[i - 1].is_alphanumeric())
+        && chars
+            .get(i + 1)
+            .is_some_and(|next| !next.is_whitespace() && *next != c);
+    if !opens {
+        return None;
+    }
+    (i + 2..chars.len()).find(|j| {
+        chars[*j] == c
+            && !chars[j - 1].is_whitespace()
+            && chars.get(j + 1).is_none_or(|next| !next.is_alphanumeric())
+    })
+}
+
+fn inline(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let find = |from: usize, c: char| (from..chars.len()).find(|j| chars[*j] == c);
+    let slice = |from: usize, to: usize| chars[from..to].iter().collect::<String>();
+
+    let mut out = String::new();
+    let mut i = 0;
+    while let Some(&c) = chars.get(i) {
+        if c == '\\' && i + 1 < chars.len() {
+            out.push(c);
+            out.push(chars[i + 1]);
+            i += 2;
+            continue;
+        }
+        // verbatim and math are copied as they are
+        let copied = match c {
+            '`' => find(i + 1, c),
+            '$' => closing(&chars, i),
+            _ => None,
+        };
+        if let Some(close) = copied {
+            out.push_str(&slice(i, close + 1));
+            i = close + 1;
+            continue;
+        }
+        if c == '{'
+            && let Some(close) = find(i + 1, '}')
+        {
+            let target = slice(i + 1, close);
+            let mut end = close;
+            let mut description = None;
+            if chars.get(close + 1) == Some(&'[')
+                && let Some(desc_close) = find(close + 2, ']')
+            {
+                description = Some(slice(close + 2, desc_close));
+                end = desc_close;
+            }
+            out.push_str(&link(&target, description.as_deref()));
+            i = end + 1;
+            continue;
+        }
+        if c == '['
+            && let Some(close) = find(i + 1, ']')
+            && chars.get(close + 1) == Some(&'{')
+            && let Some(target_close) = find(close + 2, '}')
+        {
+            let description = slice(i + 1, close);
+            out.push_str(&link(&slice(close + 2, target_close), Some(&description)));
+            i = target_close + 1;
+            continue;
+        }
+        if let Some((open, close)) = modifier(c)
+            && let Some(end) = closing(&chars, i)
+        {
+            out.push_str(open);
+            out.push_str(&inline(&slice(i + 1, end)));
+            out.push_str(close);
+            i = end + 1;
+            continue;
+        }
+        out.push(c);
+        i += 1;
+    }
+    out
+}
+
+/// A Markdown link for the Neorg link `{target}[description]`.
+fn link(target: &str, description: Option<&str>) -> String {
+    let target = target.trim();
+    let (file, rest) = match target
+        .strip_prefix(':')
+        .and_then(|after| after.split_once(':'))
+    {
+        Some((file, rest)) => (Some(file.trim()), rest.trim()),
+        None => (None, target),
+    };
+    let title = rest.trim_start_matches(['*', '#', '^', '$', '/']).trim();
+    let anchor = rest.starts_with(['*', '#', '^', '$']).then(|| slug(title));
+
+    let href = match (file, anchor) {
+        (Some(file), Some(anchor)) => format!("{file}.md#{anchor}"),
+        (Some(file), None) => format!("{file}.md"),
+        (None, Some(anchor)) => format!("#{anchor}"),
+        (None, None) if rest.starts_with('/') => title.to_owned(),
+        (None, None) => rest.to_owned(),
+    };
+    let text = match description {
+        Some(description) => inline(description),
+        None if title.is_empty() => file.unwrap_or(rest).to_owned(),
+        None => title.to_owned(),
+    };
+    format!("[{text}]({href})")
+}
+
+/// The anchor Markdown renderers give a heading titled `title`.
+fn slug(title: &str) -> String {
+    title
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' | '-' => Some('-'),
+            c if c.is_alphanumeric() || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_markdown() {
+        let norg = "\
+@document.meta
+title: notes
+@end
+* Notes
+  Some *bold*, /italic/ and -struck- text, `a * b` and a/b/c.
+** ( ) Open task
+   - ( ) first
+   -- (x) second
+   ~ one
+   > quoted
+   $ Term
+   Its definition.
+   See {* Open task}[the task], {:other:* Intro}, {https://neorg.org} and [the site]{https://neorg.org}.
+   {/ ./image.png}
+@code rust
+let a = *b;
+@end
+#comment
+___
+";
+        let expected = "\
+# Notes
+Some **bold**, *italic* and ~~struck~~ text, `a * b` and a/b/c.
+## [ ] Open task
+- [ ] first
+  - [x] second
+1. one
+> quoted
+**Term**
+Its definition.
+See [the task](#open-task), [Intro](other.md#intro), [https://neorg.org](https://neorg.org) and [the site](https://neorg.org).
+[./image.png](./image.png)
+```rust
+let a = *b;
+```
+---
+";
+        assert_eq!(markdown(norg), expected);
+    }
+}
@@ -0,0 +1,670 @@
+//! The Neorg formatter, and minimal edits turning a text into its formatted
+//! version.
+//!
+//! Formatting works line by line. The content of verbatim ranged tags is
+//! left alone, except for aligning the cells of `@table`s. Edits replace
+//! whole lines, only those that differ, so that the cursor and the undo
+//! history of the rest of the document survive.
+
+use std::ops::Range as Span;
+
+use tower_lsp::lsp_types::{FormattingOptions, Position, Range, TextEdit};
+
+use crate::config::{FormatterSettings, HeadingIndent};
+use crate::span::{PositionEncoding, lines, lines_with_breaks};
+use crate::structure::{TagKind, detached_prefix, tag_line};
+
+/// Above this many line pairs, differing lines are replaced in one edit
+/// rather than diffed.
+const MAX_DIFF: usize = 4_000_000;
+
+struct Formatter<'a> {
+    settings: &'a FormatterSettings,
+    trim: bool,
+    out: Vec<String>,
+    /// Where content starts below the current heading.
+    indent: usize,
+    /// Where the continuation lines of the current list item start.
+    hanging: Option<usize>,
+}
+
+impl Formatter<'_> {
+    fn trimmed<'l>(&self, line: &'l str) -> &'l str {
+        if self.trim { line.trim_end() } else { line }
+    }
+
+    /// `line` starting at `indent`, unless indentation is kept as it is.
+    fn indented(&self, line: &str, indent: usize) -> String {
+        let line = self.trimmed(line);
+        match self.settings.heading_indent {
+            HeadingIndent::Keep => line.to_owned(),
+            _ => format!("{}{}", " ".repeat(indent), line.trim_start()),
+        }
+    }
+
+    fn set_level(&mut self, level: usize) {
+        self.indent = match self.settings.heading_indent {
+            HeadingIndent::Title if level > 0 => level + 1,
+            _ => 0,
+        };
+    }
+
+    /// Pushes `head` followed by the words of `body`, wrapped at the line
+    /// width with continuation lines starting at `continuation`. A word that
+    /// would open a detached modifier or a tag at the start of a line takes
+    /// the word before it along, or stays on its line past the width.
+    fn push_wrapped(&mut self, head: String, body: &str, continuation: usize) {
+        let Some(width) = self.settings.line_width else {
+            self.out.push(format!("{head}{body}"));
+            return;
+        };
+        let mut line = head;
+        let mut empty = true;
+        // start of the last word of `line`, unless it is the first
+        let mut last = None;
+        for word in words(body) {
+            let len = line.chars().count();
+            if !empty && len + 1 + word.chars().count() > width {
+                let indent = " ".repeat(continuation);
+                if !opens_line(word) {
+                    self.out.push(std::mem::replace(&mut line, indent));
+                    empty = true;
+                    last = None;
+                } else if let Some(start) = last.filter(|start| !opens_line(&line[*start..])) {
+                    let carried = line.split_off(start);
+                    line.pop();
+                    self.out
+                        .push(std::mem::replace(&mut line, format!("{indent}{carried}")));
+                    last = None;
+                }
+            }
+            if !empty {
+                line.push(' ');
+                last = Some(line.len());
+            }
+            line.push_str(word);
+            empty = false;
+        }
+        self.out.push(line);
+    }
+
+    fn push_heading(&mut self, line: &str, level: usize) {
+        if let Some(blank) = self.settings.blank_lines_before_headings {
+            while self.out.last().is_some_and(String::is_empty) {
+                self.out.pop();
+            }
+            if !self.out.is_empty() {
+                self.out.extend(std::iter::repeat_n(String::new(), blank));
+            }
+        }
+        let line = self.indented(line, 0);
+        self.out.push(line);
+        self.set_level(level);
+        self.hanging = None;
+    }
+
+    fn push_item(&mut self, line: &str, level: usize, marker: char) {
+        let line = self.indented(line, self.indent);
+        let indent = line.len() - line.trim_start().len();
+        let rest = line[indent + level..].trim_start();
+        let gap = if self.settings.normalize_list_markers {
+            " "
+        } else {
+            &line[indent + level..line.len() - rest.len()]
+        };
+        let mut head = format!(
+            "{}{}{gap}",
+            &line[..indent],
+            marker.to_string().repeat(level)
+        );
+        let continuation = indent + level + 1;
+        // a todo extension is never split from its marker
+        let mut body = rest;
+        if let Some(todo) = rest.strip_prefix('(')
+            && let Some(close) = todo.find(')')
+        {
+            head.push_str(&rest[..close + 2]);
+            body = rest[close + 2..].trim_start();
+            if !body.is_empty() {
+                head.push(' ');
+            }
+        }
+        self.push_wrapped(head, body, continuation);
+        self.hanging = Some(continuation);
+    }
+
+    fn push_paragraph(&mut self, line: &str) {
+        let indent = self.hanging.unwrap_or(self.indent);
+        let line = self.indented(line, indent);
+        let lead = line.len() - line.trim_start().len();
+        let continuation = match self.settings.heading_indent {
+            HeadingIndent::Keep => lead,
+            _ => indent,
+        };
+        self.push_wrapped(line[..lead].to_owned(), &line[lead..], continuation);
+    }
+
+    fn push_table(&mut self, rows: &[&str]) {
+        if !self.settings.align_tables {
+            let rows = rows.iter().map(|row| self.trimmed(row).to_owned());
+            self.out.extend(rows.collect::<Vec<_>>());
+            return;
+        }
+        let cells: Vec<Option<Vec<&str>>> = rows
+            .iter()
+            .map(|row| {
+                row.contains('|')
+                    .then(|| row.trim().split('|').map(str::trim).collect())
+            })
+            .collect();
+        let mut widths: Vec<usize> = Vec::new();
+        for row in cells.iter().flatten() {
+            for (i, cell) in row.iter().enumerate() {
+                let separator = !cell.is_empty() && cell.chars().all(|c| c == '-');
+                let width = if separator { 1 } else { cell.chars().count() };
+                if i < widths.len() {
+                    widths[i] = widths[i].max(width);
+                } else {
+                    widths.push(width);
+                }
+            }
+        }
+        for (row, cells) in rows.iter().zip(cells) {
+            let Some(cells) = cells else {
+                self.out.push(self.trimmed(row).to_owned());
+                continue;
+            };
+            let lead = &row[..row.len() - row.trim_start().len()];
+            let last = cells.len() - 1;
+            let cells: Vec<String> = cells
+                .iter()
+                .enumerate()
+                .map(|(i, cell)| {
+                    if !cell.is_empty() && cell.chars().all(|c| c == '-') {
+                        "-".repeat(widths[i])
+                    } else if i == last {
+                        (*cell).to_owned()
+                    } else {
+                        format!("{cell:<0$}", widths[i])
+                    }
+                })
+                .collect();
+            self.out
+                .push(format!("{lead}{}", cells.join(" | ").trim_end()));
+        }
+    }
+}
+
+/// Words of `text`, keeping links and inline verbatim together.
+fn words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut closing: Option<char> = None;
+    for (i, c) in text.char_indices() {
+        if let Some(close) = closing {
+            if c == close {
+                closing = None;
+            }
+            continue;
+        }
+        if c.is_whitespace() {
+            if let Some(start) = start.take() {
+                words.push(&text[start..i]);
+            }
+            continue;
+        }
+        start.get_or_insert(i);
+        closing = match c {
+            '{' => Some('}'),
+            '[' => Some(']'),
+            '`' => Some('`'),
+            _ => None,
+        };
+    }
+    if let Some(start) = start {
+        words.push(&text[start..]);
+    }
+    words
+}
+
+/// `text` formatted after `settings`, and the client's `options` on
+/// whitespace at line and file ends.
+pub(crate) fn format(
+    text: &str,
+    settings: &FormatterSettings,
+    options: &FormattingOptions,
+) -> String {
+    let eol = if text.contains("\r\n") {
+        "\r\n"
+    } else if text.contains('\r') {
+        "\r"
+    } else {
+        "\n"
+    };
+    let mut formatter = Formatter {
+        settings,
+        trim: options
+            .trim_trailing_whitespace
+            .unwrap_or(settings.trim_trailing_whitespace),
+        out: Vec::new(),
+        indent: 0,
+        hanging: None,
+    };
+    let mut level: usize = 0;
+
+    let mut lines = lines(text);
+    // what follows a final line break is no line of its own
+    if lines.len() > 1 && lines.last() == Some(&"") {
+        lines.pop();
+    }
+    let mut n = 0;
+    while n < lines.len() {
+        let line = lines[n];
+        n += 1;
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            formatter.out.push(formatter.trimmed(line).to_owned());
+            formatter.hanging = None;
+            continue;
+        }
+
+        if let Some((kind, _, rest)) = tag_line(line) {
+            let line = formatter.indented(line, formatter.indent);
+            formatter.out.push(line);
+            formatter.hanging = None;
+            let name = rest.split_whitespace().next().unwrap_or_default();
+            if !matches!(kind, TagKind::Ranged | TagKind::Macro) || name == "end" {
+                continue;
+            }
+            // verbatim up to the closing tag
+            let end = format!("{}end", kind.prefix());
+            let body_end = (n..lines.len())
+                .find(|i| lines[*i].trim() == end)
+                .unwrap_or(lines.len());
+            if name == "table" {
+                formatter.push_table(&lines[n..body_end]);
+            } else {
+                formatter
+                    .out
+                    .extend(lines[n..body_end].iter().map(|line| (*line).to_owned()));
+            }
+            if let Some(close) = lines.get(body_end) {
+                let close = formatter.indented(close, formatter.indent);
+                formatter.out.push(close);
+            }
+            n = body_end + 1;
+            continue;
+        }
+
+        if trimmed.len() >= 3 && trimmed.chars().all(|c| matches!(c, '-' | '=' | '_')) {
+            // `---` closes the current heading, `===` all of them
+            level = match trimmed.chars().next() {
+                Some('-') => level.saturating_sub(1),
+                Some('=') => 0,
+                _ => level,
+            };
+            formatter.set_level(level);
+            formatter.hanging = None;
+            let line = formatter.indented(line, formatter.indent);
+            formatter.out.push(line);
+            continue;
+        }
+
+        match detached_prefix(line) {
+            Some(prefix) if prefix.char == '*' => {
+                level = prefix.level;
+                formatter.push_heading(line, level);
+            }
+            Some(prefix) if matches!(prefix.char, '-' | '~' | '>') => {
+                formatter.push_item(line, prefix.level, prefix.char);
+            }
+            Some(_) => {
+                let line = formatter.indented(line, formatter.indent);
+                formatter.out.push(line);
+                formatter.hanging = None;
+            }
+            None => formatter.push_paragraph(line),
+        }
+    }
+
+    let mut out = formatter.out;
+    if options.trim_final_newlines == Some(true) {
+        while out.last().is_some_and(|line| line.trim().is_empty()) {
+            out.pop();
+        }
+    }
+    let mut formatted = out.join(eol);
+    let final_newline = text.ends_with(['\n', '\r']) || options.insert_final_newline == Some(true);
+    if final_newline && !formatted.is_empty() {
+        formatted.push_str(eol);
+    }
+    formatted
+}
+
+/// Pairs of differing line spans of `old` and `new`, in order.
+fn hunks(old: &[&str], new: &[&str]) -> Vec<(Span<usize>, Span<usize>)> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_end, new_end) = (old.len() - suffix, new.len() - suffix);
+    let (a, b) = (&old[prefix..old_end], &new[prefix..new_end]);
+    if a.is_empty() && b.is_empty() {
+        return vec![];
+    }
+    if a.len() * b.len() > MAX_DIFF {
+        return vec![(prefix..old_end, prefix..new_end)];
+    }
+
+    // longest common subsequence of the lines in between
+    let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut hunks = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let (mut start_i, mut start_j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            if (start_i, start_j) != (i, j) {
+                hunks.push((prefix + start_i..prefix + i, prefix + start_j..prefix + j));
+            }
+            i += 1;
+            j += 1;
+            (start_i, start_j) = (i, j);
+        } else if j < b.len() && (i == a.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            j += 1;
+        } else {
+            i += 1;
+        }
+    }
+    if (start_i, start_j) != (i, j) {
+        hunks.push((prefix + start_i..prefix + i, prefix + start_j..prefix + j));
+    }
+    hunks
+}
+
+/// Edits turning `old` into `new`, one per run of differing lines. With
+/// `lines`, only the parts of the runs within those lines of `old`, the
+/// lines around them paired with as many lines of `new` as there are.
+pub(crate) fn edits(
+    old: &str,
+    new: &str,
+    encoding: PositionEncoding,
+    lines: Option<Span<u32>>,
+) -> Vec<TextEdit> {
+    // lines keep their line break, so that a missing final one differs
+    let split = |text| {
+        let mut lines = lines_with_breaks(text);
+        lines.retain(|line| !line.is_empty());
+        lines
+    };
+    let (old, new) = (split(old), split(new));
+    let position = |line: usize| match old.last() {
+        Some(last) if line == old.len() && !last.ends_with(['\n', '\r']) => {
+            Position::new(line as u32 - 1, encoding.len(last) as u32)
+        }
+        _ => Position::new(line as u32, 0),
+    };
+    hunks(&old, &new)
+        .into_iter()
+        .filter_map(|(changed, replacement)| {
+            let Some(lines) = &lines else {
+                return Some((changed, replacement));
+            };
+            let (start, end) = (lines.start as usize, lines.end as usize);
+            let touched = changed.start < end
+                && (changed.end > start || changed.is_empty() && changed.start >= start);
+            if !touched {
+                return None;
+            }
+            let first = changed.start.max(start);
+            let clipped = first..changed.end.min(end).max(first);
+            let from = (replacement.start + first - changed.start).min(replacement.end);
+            let to = replacement
+                .end
+                .saturating_sub(changed.end - clipped.end)
+                .max(from);
+            Some((clipped, from..to))
+        })
+        .map(|(changed, replacement)| {
+            let range = Range::new(position(changed.start), position(changed.end));
+            TextEdit::new(range, new[replacement].concat())
+        })
+        .collect()
+}
+
+/// Whether a line starting with `word` would open a detached modifier or a
+/// tag rather than continue a paragraph.
+fn opens_line(word: &str) -> bool {
+    detached_prefix(&format!("{word} ")).is_some() || tag_line(word).is_some()
+}
+
+/// The todo box opening `rest`, as in `( ) item`.
+fn todo_box(rest: &str) -> Option<&str> {
+    let mut chars = rest.char_indices();
+    let open = chars.next().is_some_and(|(_, c)| c == '(');
+    let (_, status) = chars.next()?;
+    let (close, c) = chars.next()?;
+    (open && c == ')' && status != ')').then(|| &rest[..close + 1])
+}
+
+/// Edits after a line break typed at the end of line `line - 1` of `text`.
+///
+/// Below a `-` or `~` list item, the new line starts another item at the
+/// same depth, undone when the item above has a todo box. Below an empty
+/// item, the list ends: the empty item loses its marker. Below a heading,
+/// the new line is indented to the heading's title.
+pub(crate) fn on_enter(
+    text: &str,
+    line: u32,
+    settings: &FormatterSettings,
+    encoding: PositionEncoding,
+) -> Vec<TextEdit> {
+    let Some(above) = line.checked_sub(1) else {
+        return vec![];
+    };
+    let mut lines = lines(text).into_iter().skip(above as usize);
+    let Some(previous) = lines.next() else {
+        return vec![];
+    };
+    let current = lines.next().unwrap_or_default();
+    let Some(prefix) = detached_prefix(previous) else {
+        return vec![];
+    };
+
+    let mut edits = Vec::new();
+    let indent = match prefix.char {
+        '-' | '~' if detached_prefix(current).is_none() => {
+            let rest = previous[prefix.len..].trim();
+            let todo = todo_box(rest);
+            if rest.is_empty() || todo == Some(rest) {
+                let end = Position::new(above, encoding.len(previous) as u32);
+                let item = Range::new(Position::new(above, 0), end);
+                edits.push(TextEdit::new(item, String::new()));
+                previous[..prefix.indent].to_owned()
+            } else {
+                let marker = prefix.char.to_string().repeat(prefix.level);
+                let todo = if todo.is_some() { "( ) " } else { "" };
+                format!("{}{marker} {todo}", &previous[..prefix.indent])
+            }
+        }
+        '*' if settings.heading_indent != HeadingIndent::None => " ".repeat(prefix.level + 1),
+        _ => return vec![],
+    };
+    let leading = current.len() - current.trim_start().len();
+    if current[..leading] != indent {
+        let end = Position::new(line, leading as u32);
+        edits.push(TextEdit::new(
+            Range::new(Position::new(line, 0), end),
+            indent,
+        ));
+    }
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_after_the_settings() {
+        let settings = FormatterSettings {
+            heading_indent: HeadingIndent::Title,
+            blank_lines_before_headings: Some(1),
+            line_width: Some(24),
+            ..Default::default()
+        };
+        let text = "\
+* Notes
+text below {* a heading}
+-   ( ) a long item that wraps around
+  continued
+** Sub
+
+
+@code
+   keep
+@end
+@table
+a | bb
+- | -
+ccc | d
+@end
+---
+after
+";
+        let expected = "\
+* Notes
+  text below
+  {* a heading}
+  - ( ) a long item that
+    wraps around
+    continued
+
+** Sub
+
+
+   @code
+   keep
+   @end
+   @table
+a   | bb
+--- | --
+ccc | d
+   @end
+  ---
+  after
+";
+        assert_eq!(
+            format(text, &settings, &FormattingOptions::default()),
+            expected
+        );
+    }
+
+    #[test]
+    fn wrapping_never_opens_a_modifier() {
+        let settings = FormatterSettings {
+            line_width: Some(10),
+            ..Default::default()
+        };
+        let format = |text| format(text, &settings, &FormattingOptions::default());
+        assert_eq!(format("aaaa bbbb - cc dd\n"), "aaaa\nbbbb - cc\ndd\n");
+        assert_eq!(format("aaaa bbbb #tag cc\n"), "aaaa\nbbbb #tag\ncc\n");
+        // with no word to take along, the line runs past the width
+        assert_eq!(format("aaaaaaaaa * b\n"), "aaaaaaaaa *\nb\n");
+        for text in [
+            "a b c d e - f ~ g > h @i #j +k\n",
+            "- x yy zz - ( ) a -- b\n",
+        ] {
+            let formatted = format(text);
+            for line in formatted.lines().skip(1) {
+                assert!(!opens_line(line.trim_start()), "{formatted:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn edits_replace_differing_lines() {
+        let old = "a\nb\nc\nd\ne";
+        let new = "a\nB\nc\nd\nx\ne\nf\n";
+        let edits = edits(old, new, PositionEncoding::Utf16, None);
+        let summary: Vec<_> = edits
+            .iter()
+            .map(|edit| {
+                (
+                    edit.range.start.line,
+                    edit.range.end.line,
+                    edit.new_text.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(summary, [(1, 2, "B\n"), (4, 4, "x\ne\nf\n")]);
+        assert_eq!(edits[1].range.end, Position::new(4, 1));
+
+        let edits = super::edits(old, new, PositionEncoding::Utf16, Some(0..2));
+        assert_eq!(edits.len(), 1);
+    }
+
+    #[test]
+    fn edits_stay_within_the_lines() {
+        let old = "a  \nb  \nc  \n";
+        let new = "a\nb\nc\n";
+        let summary = |lines| {
+            edits(old, new, PositionEncoding::Utf16, lines)
+                .into_iter()
+                .map(|edit| {
+                    let Range { start, end } = edit.range;
+                    (start.line, end.line, edit.new_text)
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(summary(Some(1..2)), [(1, 2, "b\n".to_owned())]);
+        assert_eq!(summary(Some(0..2)), [(0, 2, "a\nb\n".to_owned())]);
+        assert_eq!(summary(None), [(0, 3, new.to_owned())]);
+
+        // a wrapped line is replaced by all of its parts
+        let old = "a\nb c\nd\n";
+        let edits = edits(old, "a\nb\nc\nd\n", PositionEncoding::Utf16, Some(1..2));
+        let summary: Vec<_> = edits
+            .into_iter()
+            .map(|edit| (edit.range.start.line, edit.range.end.line, edit.new_text))
+            .collect();
+        assert_eq!(summary, [(1, 2, "b\nc\n".to_owned())]);
+    }
+
+    #[test]
+    fn enter_continues_lists_and_indents_headings() {
+        let settings = FormatterSettings::default();
+        let enter = |text: &str, line| {
+            on_enter(text, line, &settings, PositionEncoding::Utf16)
+                .into_iter()
+                .map(|edit| {
+                    let Range { start, end } = edit.range;
+                    (start.line, start.character, end.character, edit.new_text)
+                })
+                .collect::<Vec<_>>()
+        };
+        let edit = |line, start, end, text: &str| (line, start, end, text.to_owned());
+
+        assert_eq!(enter("  -- (x) done\n  ", 1), [edit(1, 0, 2, "  -- ( ) ")]);
+        assert_eq!(enter("~ one\n", 1), [edit(1, 0, 0, "~ ")]);
+        assert_eq!(enter("- a\n- \n", 2), [edit(1, 0, 2, "")]);
+        assert_eq!(
+            enter(" - ( )\n", 1),
+            [edit(0, 0, 6, ""), edit(1, 0, 0, " ")]
+        );
+        assert_eq!(enter("** Title\ntext", 1), [edit(1, 0, 0, "   ")]);
+        assert_eq!(enter("- a\n- b", 1), []);
+        assert_eq!(enter("text\n", 1), []);
+    }
+}
@@ -1,15 +1,41 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
 use tower_lsp::{
     jsonrpc::Error,
     lsp_types::{
-        CodeActionKind, CodeActionOrCommand, CodeActionParams, CodeActionResponse, Position, Range,
-        TextEdit,
+        CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams, CodeActionResponse,
+        NumberOrString, Position, Range, TextEdit, WorkspaceEdit,
     },
 };
 
-use crate::types::DictionaryEntry;
-use crate::{backend::Backend, range};
+use crate::{backend::Backend, handle::diagnostics::BROKEN_LINK, range};
+
+/// Quick-fixes of the broken link diagnostics in `params`, rewriting each
+/// link to the closest existing target.
+fn link_fixes(params: &CodeActionParams) -> Vec<CodeActionOrCommand> {
+    let broken = NumberOrString::String(BROKEN_LINK.to_owned());
+    params
+        .context
+        .diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.code.as_ref() == Some(&broken))
+        .filter_map(|diagnostic| {
+            let edit: TextEdit = serde_json::from_value(diagnostic.data.clone()?).ok()?;
+            let changes = HashMap::from([(params.text_document.uri.clone(), vec![edit.clone()])]);
+            Some(CodeActionOrCommand::CodeAction(CodeAction {
+                title: format!("Change to `{}`", edit.new_text),
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![diagnostic.clone()]),
+                edit: Some(WorkspaceEdit {
+                    changes: Some(changes),
+                    ..Default::default()
+                }),
+                is_preferred: Some(true),
+                ..Default::default()
+            }))
+        })
+        .collect()
+}
 
 pub(crate) trait HandleCodeAction {
     async fn provide_code_action(
@@ -23,37 +49,41 @@ impl HandleCodeAction for Backend {
         &self,
         params: CodeActionParams,
     ) -> Result<Option<CodeActionResponse>, Error> {
-        let mut result = Vec::new();
         let uri = params.text_document.uri.to_string(); // uri str for getting CST
+        // the version the request is about
+        let version = self.version(&uri);
+        let mut result = link_fixes(&params);
+        result.extend(self.spelling_actions(&params).await);
+        result.extend(self.todo_actions(&params));
+        let settings = self.settings().await;
+        let dictionary = self.dictionary().await;
+        self.reparse(&uri);
 
-        let test_range = &params.range; // is the range of char under the cursor.
-        assert_eq!(test_range.start.line, test_range.end.line); // so the start's and end's line and char position must be equal
-        assert_eq!(test_range.start.character, test_range.end.character);
-
+        // synonyms are offered for the word at the start of the range
         let Position { line, character } = params.range.start;
 
         #[allow(unused_assignments)] // false positive
         let mut word_range = range!(); // Range default
 
-        let err = format!("{:#?}", &params.range);
-        eprintln!("{err}");
-
-        if let Some(s) = self.cst_map.get(uri.as_str()) {
-            let node = s.to_owned();
-            let words = neorg_syntax::get_kinds(neorg_syntax::SyntaxKind::Word, node);
+        // collected, so that no lock is held while looking synonyms up
+        let encoding = self.index.encoding();
+        let words = self
+            .document_map
+            .get(&uri)
+            .map(|doc| doc.syntax.words(&doc.rope, encoding));
+        if let Some(words) = words {
             for word in words {
-                let Range { start, end } = word.range();
-                let err = format!("words: start: {:#?}, end: {:#?}", &start, &end);
-                eprintln!("{err}");
-
+                let Range { start, end } = word.range;
                 if (line == start.line)
                     && (line == end.line)
                     && (character >= start.character)
                     && (character <= end.character)
                 {
-                    word_range = word.range();
-                    let word_text = word.text();
-                    if let Ok(meaning) = get_synonyms(word.text()).await {
+                    word_range = word.range;
+                    let word_text = &word.text;
+                    if settings.dictionary.synonyms
+                        && let Ok(meaning) = dictionary.synonyms(word_text).await
+                    {
                         meaning.iter().for_each(|st| {
                             let mut store = HashMap::new();
                             store.insert(
@@ -80,27 +110,48 @@ impl HandleCodeAction for Backend {
                 }
             }
 
+            // looking synonyms up may take long enough for the text to change
+            self.ensure_version(&uri, version)?;
             return Ok(Some(CodeActionResponse::from(result)));
         }
-        Ok(None)
+        Ok((!result.is_empty()).then_some(result))
     }
 }
 
-async fn get_synonyms(word: &str) -> Result<HashSet<String>, reqwest::Error> {
-    let url = format!("https://api.dictionaryapi.dev/api/v2/entries/en/{word}");
-    let response = reqwest::get(&url).await?;
-    let entries = response.json::<Vec<DictionaryEntry>>().await?;
+#[cfg(test)]
+mod tests {
+    use tower_lsp::jsonrpc::ErrorCode;
+    use tower_lsp::lsp_types::{CodeActionContext, TextDocumentIdentifier};
 
-    let mut set = std::collections::HashSet::new();
+    use super::*;
+    use crate::testing::Server;
 
-    for entry in entries {
-        for meaning in &entry.meanings {
-            set.extend(meaning.synonyms.clone());
-            for def in &meaning.definitions {
-                set.extend(def.synonyms.clone());
-            }
-        }
-    }
+    #[tokio::test]
+    async fn stale_code_actions_are_dropped() {
+        let server = Server::new();
+        let uri = server.open("a.norg", "- ( ) task\n").await;
+        let params = CodeActionParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+            range: Range::new(Position::new(0, 3), Position::new(0, 3)),
+            context: CodeActionContext::default(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let actions = |backend: Backend, params| {
+            tokio::spawn(async move { backend.provide_code_action(params).await })
+        };
 
-    Ok(set)
+        let up_to_date = actions(server.backend().clone(), params.clone()).await;
+        let up_to_date = up_to_date.ok().and_then(Result::ok).flatten();
+        assert!(up_to_date.is_some_and(|actions| !actions.is_empty()));
+
+        // the text changes while the request waits
+        let settings = server.backend().settings.write().await;
+        let request = actions(server.backend().clone(), params);
+        tokio::task::yield_now().await;
+        server.change(&uri, 2, "text\n").await;
+        drop(settings);
+        let stale = request.await.ok().and_then(Result::err);
+        assert_eq!(stale.map(|err| err.code), Some(ErrorCode::ContentModified));
+    }
 }
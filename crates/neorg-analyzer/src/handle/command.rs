@@ -0,0 +1,587 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tower_lsp::Client;
+use tower_lsp::jsonrpc::{Error, ErrorCode, Result};
+use tower_lsp::lsp_types::notification::Progress as ProgressNotification;
+use tower_lsp::lsp_types::request::WorkDoneProgressCreate;
+use tower_lsp::lsp_types::{
+    CreateFile, CreateFileOptions, DocumentChangeOperation, DocumentChanges, ExecuteCommandParams,
+    OneOf, OptionalVersionedTextDocumentIdentifier, Position, ProgressParams, ProgressParamsValue,
+    ProgressToken, Range, ResourceOp, TextDocumentEdit, TextDocumentIdentifier,
+    TextDocumentPositionParams, TextEdit, Url, WorkDoneProgress, WorkDoneProgressBegin,
+    WorkDoneProgressCreateParams, WorkDoneProgressEnd, WorkDoneProgressReport, WorkspaceEdit,
+};
+
+use crate::backend::Backend;
+use crate::export;
+use crate::link::contains;
+use crate::span;
+use crate::structure::{Heading, TodoStatus};
+
+/// Deepest level demoting a heading goes to.
+const MAX_HEADING_LEVEL: usize = 8;
+
+/// Every command the server runs through `workspace/executeCommand`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ServerCommand {
+    /// Marks the todo item on a line done, or undone again. Takes a
+    /// `TextDocumentPositionParams`.
+    ToggleTodo,
+    /// Raises the heading around a position, with its subheadings, one
+    /// level. Takes a `TextDocumentPositionParams`.
+    PromoteHeading,
+    /// Lowers the heading around a position, with its subheadings, one
+    /// level, as long as none goes past [`MAX_HEADING_LEVEL`]. Takes a
+    /// `TextDocumentPositionParams`.
+    DemoteHeading,
+    /// Inserts a list of links to every heading at the start of a line.
+    /// Takes a `TextDocumentPositionParams`.
+    InsertToc,
+    /// Lists the todo items of the workspace, like the `neorg/todos`
+    /// request. Takes an optional filter.
+    ListTodos,
+    /// Writes the meaning of the word at a position to a file and opens it.
+    /// Takes a `TextDocumentPositionParams`.
+    LookupWord,
+    /// Scans every workspace folder again.
+    ReindexWorkspace,
+    /// Writes a document as Markdown next to it. Takes a
+    /// `TextDocumentIdentifier`.
+    ExportFile,
+    /// Adds a word to a `.neorg-words` list. Takes the word, and a document
+    /// uri to pick the list of its workspace folder over the user's.
+    AddWord,
+    /// Stops reporting a word in one document. Takes the word and the
+    /// document uri.
+    IgnoreWord,
+}
+
+impl ServerCommand {
+    pub(crate) const ALL: [Self; 10] = [
+        Self::ToggleTodo,
+        Self::PromoteHeading,
+        Self::DemoteHeading,
+        Self::InsertToc,
+        Self::ListTodos,
+        Self::LookupWord,
+        Self::ReindexWorkspace,
+        Self::ExportFile,
+        Self::AddWord,
+        Self::IgnoreWord,
+    ];
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::ToggleTodo => "neorg.toggleTodo",
+            Self::PromoteHeading => "neorg.promoteHeading",
+            Self::DemoteHeading => "neorg.demoteHeading",
+            Self::InsertToc => "neorg.insertToc",
+            Self::ListTodos => "neorg.listTodos",
+            Self::LookupWord => "neorg.lookupWord",
+            Self::ReindexWorkspace => "neorg.reindexWorkspace",
+            Self::ExportFile => "neorg.exportFile",
+            Self::AddWord => "neorg.addWord",
+            Self::IgnoreWord => "neorg.ignoreWord",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|command| command.name() == name)
+    }
+
+    /// Shown while the command runs.
+    fn title(self) -> &'static str {
+        match self {
+            Self::ToggleTodo => "Toggling todo",
+            Self::PromoteHeading => "Promoting heading",
+            Self::DemoteHeading => "Demoting heading",
+            Self::InsertToc => "Inserting table of contents",
+            Self::ListTodos => "Listing todos",
+            Self::LookupWord => "Looking word up",
+            Self::ReindexWorkspace => "Indexing workspace",
+            Self::ExportFile => "Exporting to Markdown",
+            Self::AddWord => "Adding word",
+            Self::IgnoreWord => "Ignoring word",
+        }
+    }
+}
+
+/// Source of progress tokens the server creates itself.
+static PROGRESS_TOKENS: AtomicU32 = AtomicU32::new(0);
+
+/// Reports the progress of a command through `$/progress`, with the token
+/// the client sent or one the server created. Reports nothing when there is
+/// neither.
+struct Progress<'a> {
+    client: &'a Client,
+    token: Option<ProgressToken>,
+}
+
+impl<'a> Progress<'a> {
+    async fn begin(backend: &'a Backend, token: Option<ProgressToken>, title: &str) -> Self {
+        let client = &backend.client;
+        let token = match token {
+            Some(token) => Some(token),
+            None if backend.work_done_progress.load(Ordering::Relaxed) => {
+                let id = PROGRESS_TOKENS.fetch_add(1, Ordering::Relaxed);
+                let token = ProgressToken::String(format!("neorg-command-{id}"));
+                let params = WorkDoneProgressCreateParams {
+                    token: token.clone(),
+                };
+                let created = client.send_request::<WorkDoneProgressCreate>(params).await;
+                created.ok().map(|()| token)
+            }
+            None => None,
+        };
+        let progress = Self { client, token };
+        progress
+            .send(WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: title.to_owned(),
+                ..Default::default()
+            }))
+            .await;
+        progress
+    }
+
+    async fn report(&self, message: String) {
+        self.send(WorkDoneProgress::Report(WorkDoneProgressReport {
+            message: Some(message),
+            ..Default::default()
+        }))
+        .await;
+    }
+
+    async fn end(self, message: Option<String>) {
+        self.send(WorkDoneProgress::End(WorkDoneProgressEnd { message }))
+            .await;
+    }
+
+    async fn send(&self, progress: WorkDoneProgress) {
+        if let Some(token) = &self.token {
+            self.client
+                .send_notification::<ProgressNotification>(ProgressParams {
+                    token: token.clone(),
+                    value: ProgressParamsValue::WorkDone(progress),
+                })
+                .await;
+        }
+    }
+}
+
+/// The first argument of a command, expected to be `what`.
+fn argument<T: DeserializeOwned>(arguments: Vec<Value>, what: &str) -> Result<T> {
+    arguments
+        .into_iter()
+        .next()
+        .and_then(|argument| serde_json::from_value(argument).ok())
+        .ok_or_else(|| Error::invalid_params(format!("expected {what}")))
+}
+
+/// The innermost heading whose section holds `position`.
+fn heading_around(headings: &[Heading], position: Position) -> Option<&Heading> {
+    headings
+        .iter()
+        .filter(|heading| contains(heading.section, position))
+        .max_by_key(|heading| heading.level)
+}
+
+impl Backend {
+    pub(crate) async fn run_command(&self, params: ExecuteCommandParams) -> Result<Option<Value>> {
+        let Some(command) = ServerCommand::from_name(&params.command) else {
+            return Err(Error::invalid_params(format!(
+                "unknown command `{}`",
+                params.command
+            )));
+        };
+        let token = params.work_done_progress_params.work_done_token;
+        let progress = Progress::begin(self, token, command.title()).await;
+        let result = self.dispatch(command, params.arguments, &progress).await;
+        progress
+            .end(result.as_ref().err().map(|err| err.message.to_string()))
+            .await;
+        result
+    }
+
+    async fn dispatch(
+        &self,
+        command: ServerCommand,
+        arguments: Vec<Value>,
+        progress: &Progress<'_>,
+    ) -> Result<Option<Value>> {
+        match command {
+            ServerCommand::ToggleTodo => {
+                let position = argument(arguments, "a text document position")?;
+                self.apply(self.toggle_todo(position)?).await.map(|()| None)
+            }
+            ServerCommand::PromoteHeading | ServerCommand::DemoteHeading => {
+                let position = argument(arguments, "a text document position")?;
+                let promote = command == ServerCommand::PromoteHeading;
+                let edit = self.shift_heading(position, promote)?;
+                self.apply(edit).await.map(|()| None)
+            }
+            ServerCommand::InsertToc => {
+                let position = argument(arguments, "a text document position")?;
+                self.apply(self.insert_toc(position)?).await.map(|()| None)
+            }
+            ServerCommand::ListTodos => self.list_todos(arguments.into_iter().next()).map(Some),
+            ServerCommand::LookupWord => {
+                let position = argument(arguments, "a text document position")?;
+                let uri = self.lookup_word(position).await;
+                Ok(uri.map(|uri| Value::String(uri.to_string())))
+            }
+            ServerCommand::ReindexWorkspace => {
+                let count = self.reindex_workspace(progress).await;
+                Ok(Some(Value::from(count)))
+            }
+            ServerCommand::ExportFile => {
+                let document: TextDocumentIdentifier = argument(arguments, "a text document")?;
+                let (uri, edit) = self.export_file(&document.uri)?;
+                self.apply(edit)
+                    .await
+                    .map(|()| Some(Value::String(uri.to_string())))
+            }
+            ServerCommand::AddWord => self.add_word(arguments).await.map(|()| None),
+            ServerCommand::IgnoreWord => self.ignore_word(arguments).await.map(|()| None),
+        }
+    }
+
+    /// Has the client apply `edit`.
+    async fn apply(&self, edit: WorkspaceEdit) -> Result<()> {
+        let response = self.client.apply_edit(edit).await?;
+        if response.applied {
+            return Ok(());
+        }
+        Err(Error {
+            code: ErrorCode::InternalError,
+            message: response
+                .failure_reason
+                .unwrap_or_else(|| "the edit was not applied".to_owned())
+                .into(),
+            data: None,
+        })
+    }
+
+    /// `edits` to the document `uri`, at the version they were made for.
+    pub(crate) fn document_edit(&self, uri: Url, edits: Vec<TextEdit>) -> WorkspaceEdit {
+        let edit = TextDocumentEdit {
+            text_document: OptionalVersionedTextDocumentIdentifier {
+                version: self.version(uri.as_str()),
+                uri,
+            },
+            edits: edits.into_iter().map(OneOf::Left).collect(),
+        };
+        WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Edits(vec![edit])),
+            ..Default::default()
+        }
+    }
+
+    fn toggle_todo(&self, params: TextDocumentPositionParams) -> Result<WorkspaceEdit> {
+        let uri = params.text_document.uri;
+        let structure = self.index.get(&uri);
+        let Some(todo) = structure
+            .iter()
+            .flat_map(|structure| &structure.todos)
+            .find(|todo| todo.range.start.line == params.position.line)
+        else {
+            return Err(Error::invalid_params("no todo item on this line"));
+        };
+        let status = match todo.status {
+            TodoStatus::Done => TodoStatus::Undone,
+            _ => TodoStatus::Done,
+        };
+        let edit = TextEdit::new(todo.status_range, status.as_char().to_string());
+        Ok(self.document_edit(uri, vec![edit]))
+    }
+
+    fn shift_heading(
+        &self,
+        params: TextDocumentPositionParams,
+        promote: bool,
+    ) -> Result<WorkspaceEdit> {
+        let uri = params.text_document.uri;
+        let Some(structure) = self.index.get(&uri) else {
+            return Err(Error::invalid_params("no heading here"));
+        };
+        let Some(heading) = heading_around(&structure.headings, params.position) else {
+            return Err(Error::invalid_params("no heading here"));
+        };
+        if promote && heading.level == 1 {
+            return Err(Error::invalid_params(
+                "the heading is already at the top level",
+            ));
+        }
+
+        // levels are capped at `u8::MAX` in the index, so the `*`s are counted
+        // on the lines themselves
+        let text = match self.document_map.get(uri.as_str()) {
+            Some(doc) => doc.rope.to_string(),
+            None => uri
+                .to_file_path()
+                .ok()
+                .and_then(|path| std::fs::read_to_string(path).ok())
+                .ok_or_else(|| Error::invalid_params(format!("can not read {uri}")))?,
+        };
+        let lines = span::lines(&text);
+        let subheadings: Vec<(Position, usize)> = structure
+            .headings
+            .iter()
+            .filter(|sub| contains(heading.section, sub.range.start))
+            .map(|sub| {
+                let line = lines.get(sub.range.start.line as usize).copied();
+                let stars = line.map_or(0, |line| {
+                    line.trim_start().chars().take_while(|c| *c == '*').count()
+                });
+                (sub.range.start, stars)
+            })
+            .collect();
+        if !promote
+            && subheadings
+                .iter()
+                .any(|(_, stars)| *stars >= MAX_HEADING_LEVEL)
+        {
+            return Err(Error::invalid_params(format!(
+                "headings go {MAX_HEADING_LEVEL} levels deep at most"
+            )));
+        }
+
+        let edits = subheadings
+            .into_iter()
+            .map(|(start, stars)| {
+                let level = if promote {
+                    stars.saturating_sub(1)
+                } else {
+                    stars + 1
+                };
+                // the `*`s are ascii, so as many columns as there are of them
+                let end = Position::new(start.line, start.character + stars as u32);
+                TextEdit::new(Range::new(start, end), "*".repeat(level))
+            })
+            .collect();
+        Ok(self.document_edit(uri, edits))
+    }
+
+    fn insert_toc(&self, params: TextDocumentPositionParams) -> Result<WorkspaceEdit> {
+        let uri = params.text_document.uri;
+        let Some(structure) = self.index.get(&uri) else {
+            return Err(Error::invalid_params("no headings to list"));
+        };
+        let toc: String = structure
+            .headings
+            .iter()
+            // titles with a `}` would end the link early
+            .filter(|heading| !heading.title.is_empty() && !heading.title.contains('}'))
+            .map(|heading| {
+                let level = usize::from(heading.level);
+                format!(
+                    "{} {{{} {}}}\n",
+                    "-".repeat(level),
+                    "*".repeat(level),
+                    heading.title
+                )
+            })
+            .collect();
+        if toc.is_empty() {
+            return Err(Error::invalid_params("no headings to list"));
+        }
+        let start = Position::new(params.position.line, 0);
+        let edit = TextEdit::new(Range::new(start, start), toc);
+        Ok(self.document_edit(uri, vec![edit]))
+    }
+
+    /// Indexes every workspace folder again and forgets deleted files.
+    /// Returns the number of files indexed.
+    async fn reindex_workspace(&self, progress: &Progress<'_>) -> usize {
+        let mut count = 0;
+        for root in self.index.roots() {
+            progress.report(root.display().to_string()).await;
+            let index = Arc::clone(&self.index);
+            let indexed = tokio::task::spawn_blocking(move || index.index_root(&root)).await;
+            count += indexed.unwrap_or(0);
+        }
+        self.index.prune();
+        self.refresh_diagnostics().await;
+        count
+    }
+
+    /// Creates `<name>.md` next to `uri`, overwriting it, with the document
+    /// rendered as Markdown. Returns its uri with the edit.
+    fn export_file(&self, uri: &Url) -> Result<(Url, WorkspaceEdit)> {
+        let text = match self.document_map.get(uri.as_str()) {
+            Some(doc) => doc.rope.to_string(),
+            None => uri
+                .to_file_path()
+                .ok()
+                .and_then(|path| std::fs::read_to_string(path).ok())
+                .ok_or_else(|| Error::invalid_params(format!("can not read {uri}")))?,
+        };
+        let Some(target) = uri
+            .to_file_path()
+            .ok()
+            .and_then(|path| Url::from_file_path(path.with_extension("md")).ok())
+        else {
+            return Err(Error::invalid_params(format!("{uri} is not a local file")));
+        };
+
+        let create = ResourceOp::Create(CreateFile {
+            uri: target.clone(),
+            options: Some(CreateFileOptions {
+                overwrite: Some(true),
+                ignore_if_exists: None,
+            }),
+            annotation_id: None,
+        });
+        let write = TextDocumentEdit {
+            text_document: OptionalVersionedTextDocumentIdentifier {
+                uri: target.clone(),
+                version: None,
+            },
+            edits: vec![OneOf::Left(TextEdit::new(
+                Range::default(),
+                export::markdown(&text),
+            ))],
+        };
+        let edit = WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Operations(vec![
+                DocumentChangeOperation::Op(create),
+                DocumentChangeOperation::Edit(write),
+            ])),
+            ..Default::default()
+        };
+        Ok((target, edit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Server;
+
+    fn at(uri: &Url, line: u32, character: u32) -> TextDocumentPositionParams {
+        TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            position: Position::new(line, character),
+        }
+    }
+
+    /// The edits to `uri`, checking they are made for its open version.
+    fn edits(backend: &Backend, uri: &Url, edit: WorkspaceEdit) -> Vec<(Range, String)> {
+        let Some(DocumentChanges::Edits(documents)) = edit.document_changes else {
+            panic!("no document edits");
+        };
+        let [document] = documents.as_slice() else {
+            panic!("edits to {} documents", documents.len());
+        };
+        assert_eq!(&document.text_document.uri, uri);
+        assert_eq!(
+            document.text_document.version,
+            backend.version(uri.as_str())
+        );
+        document
+            .edits
+            .iter()
+            .map(|edit| match edit {
+                OneOf::Left(edit) => (edit.range, edit.new_text.clone()),
+                OneOf::Right(edit) => (edit.text_edit.range, edit.text_edit.new_text.clone()),
+            })
+            .collect()
+    }
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+        Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+    }
+
+    #[tokio::test]
+    async fn todos_toggle_both_ways() {
+        let server = Server::new();
+        let uri = server
+            .open("a.norg", "- ( ) open\n- (x) done\ntext\n")
+            .await;
+        let backend = server.backend();
+
+        let done = backend.toggle_todo(at(&uri, 0, 0)).ok();
+        let done = done.map(|edit| edits(backend, &uri, edit));
+        assert_eq!(done, Some(vec![(range((0, 3), (0, 4)), "x".to_owned())]));
+        let undone = backend.toggle_todo(at(&uri, 1, 5)).ok();
+        let undone = undone.map(|edit| edits(backend, &uri, edit));
+        assert_eq!(undone, Some(vec![(range((1, 3), (1, 4)), " ".to_owned())]));
+        assert!(backend.toggle_todo(at(&uri, 2, 0)).is_err());
+    }
+
+    #[tokio::test]
+    async fn headings_shift_with_their_subheadings() {
+        let server = Server::new();
+        let text = "* Top\n** Middle\n*** Bottom\n** Sibling\n* Next\n";
+        let uri = server.open("a.norg", text).await;
+        let backend = server.backend();
+
+        let Ok(demoted) = backend.shift_heading(at(&uri, 1, 3), false) else {
+            panic!("the heading was not demoted");
+        };
+        assert_eq!(
+            edits(backend, &uri, demoted),
+            vec![
+                (range((1, 0), (1, 2)), "***".to_owned()),
+                (range((2, 0), (2, 3)), "****".to_owned()),
+            ]
+        );
+        let Ok(promoted) = backend.shift_heading(at(&uri, 2, 0), true) else {
+            panic!("the heading was not promoted");
+        };
+        assert_eq!(
+            edits(backend, &uri, promoted),
+            vec![(range((2, 0), (2, 3)), "**".to_owned())]
+        );
+        // a top level heading can only go down
+        assert!(backend.shift_heading(at(&uri, 0, 0), true).is_err());
+        assert!(backend.shift_heading(at(&uri, 4, 0), true).is_err());
+        assert!(backend.shift_heading(at(&uri, 4, 0), false).is_ok());
+    }
+
+    #[tokio::test]
+    async fn headings_shift_by_their_own_stars() {
+        let server = Server::new();
+        let deep = "*".repeat(300);
+        let text = format!("* Top\n******** Eighth\n{deep} Deep\n");
+        let uri = server.open("a.norg", &text).await;
+        let backend = server.backend();
+
+        let err = backend.shift_heading(at(&uri, 1, 0), false).err();
+        assert_eq!(err.map(|err| err.code), Some(ErrorCode::InvalidParams));
+        assert!(backend.shift_heading(at(&uri, 0, 0), false).is_err());
+        assert!(backend.shift_heading(at(&uri, 2, 0), false).is_err());
+
+        let Ok(promoted) = backend.shift_heading(at(&uri, 2, 0), true) else {
+            panic!("the heading was not promoted");
+        };
+        assert_eq!(
+            edits(backend, &uri, promoted),
+            vec![(range((2, 0), (2, 300)), "*".repeat(299))]
+        );
+    }
+
+    #[tokio::test]
+    async fn tables_of_contents_skip_titles_that_end_links() {
+        let server = Server::new();
+        let text = "* One\n** Two {x}\n** Three\n";
+        let uri = server.open("a.norg", text).await;
+        let backend = server.backend();
+
+        let Ok(toc) = backend.insert_toc(at(&uri, 0, 3)) else {
+            panic!("no table of contents");
+        };
+        assert_eq!(
+            edits(backend, &uri, toc),
+            vec![(
+                range((0, 0), (0, 0)),
+                "- {* One}\n-- {** Three}\n".to_owned()
+            )]
+        );
+
+        let empty = server.open("b.norg", "* {a}\ntext\n").await;
+        assert!(backend.insert_toc(at(&empty, 0, 0)).is_err());
+    }
+}
@@ -0,0 +1,413 @@
+use std::collections::BTreeSet;
+
+use tower_lsp::lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionParams, CompletionResponse, CompletionTextEdit,
+    Position, Range, TextEdit,
+};
+
+use crate::backend::Backend;
+use crate::structure::{BlockKind, Structure, TagKind, TodoStatus};
+
+/// Prefixes following `{`, with what they link to.
+const LINK_KINDS: &[(&str, &str)] = &[
+    ("* ", "heading"),
+    ("# ", "any titled element"),
+    (":", "file"),
+    ("^ ", "footnote"),
+    ("$ ", "definition"),
+    ("/ ", "path"),
+    ("? ", "wiki page"),
+    ("@ ", "timestamp"),
+];
+
+const RANGED_TAGS: &[(&str, &str)] = &[
+    ("code", "verbatim code block"),
+    ("table", "table"),
+    ("document.meta", "document metadata"),
+    ("math", "math block"),
+    ("image", "embedded image"),
+    ("embed", "embedded content"),
+    ("comment", "comment"),
+];
+
+const CARRYOVER_TAGS: &[(&str, &str)] = &[
+    ("comment", "comment on the next element"),
+    ("name", "name the next element"),
+    ("ordered", "number the next list"),
+    ("contexts", "contexts of the next task"),
+    ("time.due", "due date of the next task"),
+    ("time.start", "start date of the next task"),
+    ("waiting.for", "who the next task waits for"),
+];
+
+/// What is being typed, from the text of the line before the cursor. Offsets
+/// are bytes into that text, where the replaced text starts.
+#[derive(Debug, PartialEq, Eq)]
+enum Context<'a> {
+    /// Right after `{`.
+    LinkKind,
+    /// `{:path`
+    LinkFile { start: usize },
+    /// `{* title`, `{:file:# name`, ...
+    LinkTarget {
+        file: Option<&'a str>,
+        prefix: char,
+        level: usize,
+        start: usize,
+    },
+    /// `{:file:` with nothing after it yet.
+    FileHeadings { file: &'a str },
+    /// `@name`, `#name` or `+name` at the start of a line.
+    Tag { kind: TagKind, start: usize },
+    /// `(` of a todo extension.
+    Todo { start: usize },
+}
+
+fn context(line: &str) -> Option<Context<'_>> {
+    if let Some(open) = line.rfind('{')
+        && !line[open..].contains('}')
+    {
+        return link_context(line, open + 1);
+    }
+
+    let trimmed = line.trim_start();
+    let start = line.len() - trimmed.len() + 1;
+    let kind = match trimmed.chars().next() {
+        Some('@') => Some(TagKind::Ranged),
+        Some('#') => Some(TagKind::Weak),
+        Some('+') => Some(TagKind::Strong),
+        _ => None,
+    };
+    if let Some(kind) = kind
+        && !trimmed[1..].contains(char::is_whitespace)
+    {
+        return Some(Context::Tag { kind, start });
+    }
+
+    // `- (`, `* ( ) | (`
+    let paren = line.rfind('(')?;
+    let before = line[..paren].trim();
+    let detached = before
+        .chars()
+        .next()
+        .is_some_and(|c| matches!(c, '*' | '-' | '~') && before.chars().all(|other| other == c));
+    let joined = before.ends_with('|') && before.contains('(');
+    (line[paren + 1..].chars().count() <= 1 && (detached || joined))
+        .then_some(Context::Todo { start: paren })
+}
+
+/// `start` is the byte offset right after `{`.
+fn link_context(line: &str, start: usize) -> Option<Context<'_>> {
+    let content = &line[start..];
+    if content.is_empty() {
+        return Some(Context::LinkKind);
+    }
+
+    let (file, rest, offset) = match content.strip_prefix(':') {
+        Some(after) => match after.find(':') {
+            Some(end) => (
+                Some(after[..end].trim()),
+                &after[end + 1..],
+                start + end + 2,
+            ),
+            None => return Some(Context::LinkFile { start: start + 1 }),
+        },
+        None => (None, content, start),
+    };
+    let prefix = match (rest.chars().next(), file) {
+        (None, Some(file)) => return Some(Context::FileHeadings { file }),
+        (Some(c @ ('*' | '#' | '^' | '$')), _) => c,
+        _ => return None,
+    };
+    let level = rest.chars().take_while(|c| *c == prefix).count();
+    let name = &rest[level..];
+    Some(Context::LinkTarget {
+        file,
+        prefix,
+        level,
+        start: offset + level + (name.len() - name.trim_start().len()),
+    })
+}
+
+fn item(
+    label: String,
+    kind: CompletionItemKind,
+    detail: Option<String>,
+    range: Range,
+    new_text: String,
+) -> CompletionItem {
+    CompletionItem {
+        label,
+        kind: Some(kind),
+        detail,
+        text_edit: Some(CompletionTextEdit::Edit(TextEdit { range, new_text })),
+        ..Default::default()
+    }
+}
+
+/// Titles `{<prefix> ...}` can point at in `structure`.
+fn targets(structure: &Structure, prefix: char, level: usize) -> Vec<(String, String)> {
+    match prefix {
+        '*' => structure
+            .headings
+            .iter()
+            .filter(|heading| usize::from(heading.level) == level)
+            .map(|heading| (heading.title.clone(), "*".repeat(level)))
+            .collect(),
+        '#' => structure
+            .anchors
+            .iter()
+            .map(|anchor| (anchor.name.clone(), "anchor".to_owned()))
+            .collect(),
+        '^' | '$' => {
+            let (kind, detail) = match prefix {
+                '^' => (BlockKind::Footnote, "footnote"),
+                _ => (BlockKind::Definition, "definition"),
+            };
+            structure
+                .blocks
+                .iter()
+                .filter(|block| block.kind == kind)
+                .map(|block| (block.title.clone(), detail.to_owned()))
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+impl Backend {
+    /// Link kinds and targets, tag names and todo states, depending on what
+    /// precedes the cursor.
+    pub(crate) fn provide_completion(
+        &self,
+        params: CompletionParams,
+    ) -> Option<CompletionResponse> {
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+        let line = {
+            let doc = self.document_map.get(uri.as_str())?;
+            let line = doc.rope.get_line(position.line as usize)?.to_string();
+            line.trim_end_matches(['\n', '\r']).to_owned()
+        };
+        let encoding = self.index.encoding();
+        let (before, after) = line.split_at(encoding.byte(&line, position.character));
+
+        // replaces from the byte offset `start` of `before` up to the cursor
+        let range = |start: usize| {
+            let character = encoding.column(before, start);
+            Range::new(Position::new(position.line, character), position)
+        };
+        let close = if after.starts_with('}') { "" } else { "}" };
+
+        let items = match context(before)? {
+            Context::LinkKind => LINK_KINDS
+                .iter()
+                .map(|(prefix, detail)| {
+                    let label = prefix.trim_end().to_owned();
+                    let kind = CompletionItemKind::KEYWORD;
+                    item(
+                        label,
+                        kind,
+                        Some((*detail).to_owned()),
+                        range(before.len()),
+                        (*prefix).to_owned(),
+                    )
+                })
+                .collect(),
+            Context::LinkFile { start } => {
+                let mut paths: Vec<String> = self
+                    .index
+                    .files()
+                    .into_iter()
+                    .filter(|(other, _)| *other != uri)
+                    .filter_map(|(other, _)| self.index.link_path(&uri, &other))
+                    .collect();
+                paths.sort();
+                paths
+                    .into_iter()
+                    .map(|path| {
+                        let new_text = format!("{path}:");
+                        item(path, CompletionItemKind::FILE, None, range(start), new_text)
+                    })
+                    .collect()
+            }
+            Context::FileHeadings { file } => {
+                let structure = self.index.get(&self.index.resolve_file(&uri, file)?)?;
+                structure
+                    .headings
+                    .iter()
+                    .map(|heading| {
+                        let stars = "*".repeat(heading.level.into());
+                        let new_text = format!("{stars} {}{close}", heading.title);
+                        let kind = CompletionItemKind::REFERENCE;
+                        item(
+                            heading.title.clone(),
+                            kind,
+                            Some(stars),
+                            range(before.len()),
+                            new_text,
+                        )
+                    })
+                    .collect()
+            }
+            Context::LinkTarget {
+                file,
+                prefix,
+                level,
+                start,
+            } => {
+                let target = match file {
+                    Some(file) => self.index.resolve_file(&uri, file)?,
+                    None => uri.clone(),
+                };
+                let structure = self.index.get(&target)?;
+                targets(&structure, prefix, level)
+                    .into_iter()
+                    .map(|(title, detail)| {
+                        let new_text = format!("{title}{close}");
+                        let kind = CompletionItemKind::REFERENCE;
+                        item(title, kind, Some(detail), range(start), new_text)
+                    })
+                    .collect()
+            }
+            Context::Tag { kind, start } => {
+                let builtin = match kind {
+                    TagKind::Ranged => RANGED_TAGS,
+                    _ => CARRYOVER_TAGS,
+                };
+                let carryover = |other: TagKind| matches!(other, TagKind::Weak | TagKind::Strong);
+                let used: BTreeSet<String> = self
+                    .index
+                    .files()
+                    .iter()
+                    .flat_map(|(_, structure)| structure.tags.clone())
+                    .filter(|tag| tag.kind == kind || (carryover(kind) && carryover(tag.kind)))
+                    .map(|tag| tag.name)
+                    .filter(|name| !builtin.iter().any(|(builtin, _)| builtin == name))
+                    .collect();
+                builtin
+                    .iter()
+                    .map(|(name, detail)| ((*name).to_owned(), Some((*detail).to_owned())))
+                    .chain(used.into_iter().map(|name| (name, None)))
+                    .map(|(name, detail)| {
+                        let new_text = name.clone();
+                        item(
+                            name,
+                            CompletionItemKind::PROPERTY,
+                            detail,
+                            range(start),
+                            new_text,
+                        )
+                    })
+                    .collect()
+            }
+            Context::Todo { start } => TodoStatus::ALL
+                .iter()
+                .map(|status| {
+                    let label = format!("({})", status.as_char());
+                    let mut range = range(start);
+                    // overwrite the `)` the editor may have inserted
+                    if after.starts_with(')') {
+                        range.end.character += 1;
+                    }
+                    let detail = Some(status.name().to_owned());
+                    item(
+                        label.clone(),
+                        CompletionItemKind::ENUM_MEMBER,
+                        detail,
+                        range,
+                        label,
+                    )
+                })
+                .collect(),
+        };
+        Some(CompletionResponse::Array(items))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::{TextDocumentIdentifier, TextDocumentPositionParams};
+
+    use super::*;
+    use crate::testing::Server;
+
+    const TEXT: &str = "\
+* Plan
+** Sub
+see {* 
+{:
+{:notes:
++custom x
+#
+- ()
+@co
+";
+
+    /// Label, inserted text and replaced columns of every item offered at
+    /// `line` and `character`.
+    async fn complete(line: u32, character: u32) -> Vec<(String, String, u32, u32)> {
+        let server = Server::new();
+        server.open("notes.norg", "* Goal\n").await;
+        let uri = server.open("index.norg", TEXT).await;
+        let params = CompletionParams {
+            text_document_position: TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(uri),
+                Position::new(line, character),
+            ),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: None,
+        };
+        let Some(CompletionResponse::Array(items)) = server.backend().provide_completion(params)
+        else {
+            return Vec::new();
+        };
+        items
+            .into_iter()
+            .filter_map(|item| match item.text_edit? {
+                CompletionTextEdit::Edit(edit) => Some((
+                    item.label,
+                    edit.new_text,
+                    edit.range.start.character,
+                    edit.range.end.character,
+                )),
+                CompletionTextEdit::InsertAndReplace(_) => None,
+            })
+            .collect()
+    }
+
+    fn item(label: &str, new_text: &str, start: u32, end: u32) -> (String, String, u32, u32) {
+        (label.to_owned(), new_text.to_owned(), start, end)
+    }
+
+    #[tokio::test]
+    async fn headings_of_the_level_typed() {
+        assert_eq!(complete(2, 7).await, [item("Plan", "Plan}", 7, 7)]);
+    }
+
+    #[tokio::test]
+    async fn files_and_their_headings() {
+        assert_eq!(complete(3, 2).await, [item("notes", "notes:", 2, 2)]);
+        assert_eq!(complete(4, 8).await, [item("Goal", "* Goal}", 8, 8)]);
+    }
+
+    #[tokio::test]
+    async fn tags_builtin_and_used() {
+        let ranged = complete(8, 3).await;
+        assert!(ranged.contains(&item("code", "code", 1, 3)));
+        assert!(!ranged.iter().any(|(label, ..)| label == "custom"));
+        let carryover = complete(6, 1).await;
+        assert!(carryover.contains(&item("time.due", "time.due", 1, 1)));
+        assert!(carryover.contains(&item("custom", "custom", 1, 1)));
+    }
+
+    #[tokio::test]
+    async fn todo_states_replace_the_parentheses() {
+        let states = complete(7, 3).await;
+        assert_eq!(states.len(), TodoStatus::ALL.len());
+        assert!(states.contains(&item("(x)", "(x)", 2, 4)));
+        // nothing to complete in plain text
+        assert_eq!(complete(0, 6).await, []);
+    }
+}
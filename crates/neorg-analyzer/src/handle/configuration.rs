@@ -0,0 +1,110 @@
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use tower_lsp::lsp_types::ConfigurationItem;
+
+use crate::backend::Backend;
+use crate::config::{DictionarySettings, SECTION, Settings};
+use crate::dictionary::Dictionary;
+use crate::spelling;
+
+impl Backend {
+    /// Asks the client for the `neorg` section, when it supports `workspace/configuration`.
+    pub(crate) async fn pull_settings(&self) -> Option<Settings> {
+        if !self.pull_configuration.load(Ordering::Relaxed) {
+            return None;
+        }
+        let item = ConfigurationItem {
+            scope_uri: None,
+            section: Some(SECTION.to_owned()),
+        };
+        match self.client.configuration(vec![item]).await {
+            Ok(mut values) if !values.is_empty() => Some(Settings::from_value(values.remove(0))),
+            Ok(_) => None,
+            Err(err) => {
+                eprintln!("failed to pull configuration: {err}");
+                None
+            }
+        }
+    }
+
+    /// Stores `settings` and refreshes whatever depends on the parts that changed.
+    /// Returns whether the dictionary was rebuilt.
+    pub(crate) async fn update_settings(&self, settings: Settings) -> bool {
+        let old = self.settings().await;
+        if *old == settings {
+            return false;
+        }
+        *self.settings.write().await = Arc::new(settings.clone());
+
+        let rebuild = !old.dictionary.same_source(&settings.dictionary);
+        if rebuild {
+            self.load_dictionary(&settings.dictionary).await;
+        }
+
+        let respell = old.spelling != settings.spelling;
+        if respell {
+            *self.speller.write().await = spelling::load(&settings.spelling).map(Arc::new);
+        }
+
+        if respell || old.diagnostics != settings.diagnostics {
+            self.refresh_diagnostics().await;
+        }
+
+        if old.semantic_tokens != settings.semantic_tokens {
+            self.semantic_tokens.clear();
+            _ = self.client.semantic_tokens_refresh().await;
+        }
+        if old.inlay_hints != settings.inlay_hints {
+            _ = self.client.inlay_hint_refresh().await;
+        }
+        rebuild
+    }
+
+    /// Builds the dictionary of `settings`, reading any dump off the async
+    /// runtime, and swaps it in.
+    pub(crate) async fn load_dictionary(&self, settings: &DictionarySettings) {
+        let settings = settings.clone();
+        let built = tokio::task::spawn_blocking(move || Dictionary::from_settings(&settings));
+        if let Ok(dictionary) = built.await {
+            *self.dictionary.write().await = Arc::new(dictionary);
+        }
+    }
+
+    /// Looks up the configured word list in the background.
+    pub(crate) async fn prewarm_dictionary(&self) {
+        let settings = self.settings().await;
+        let Some(list) = Dictionary::prewarm_list(&settings.dictionary) else {
+            return;
+        };
+        let dictionary = self.dictionary().await;
+        tokio::spawn(async move {
+            if let Err(err) = dictionary.prewarm(&list).await {
+                eprintln!("failed to pre-warm dictionary cache: {err}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Server;
+
+    #[tokio::test]
+    async fn only_the_source_rebuilds_the_dictionary() {
+        let server = Server::new();
+        let backend = server.backend();
+        let empty = backend.dictionary().await;
+        let mut settings = Settings::default();
+        settings.dictionary.hover = false;
+        settings.dictionary.synonyms = false;
+        assert!(!backend.update_settings(settings.clone()).await);
+        assert!(!backend.settings().await.dictionary.hover);
+        assert!(Arc::ptr_eq(&empty, &backend.dictionary().await));
+
+        settings.dictionary.language = "de".to_owned();
+        assert!(backend.update_settings(settings).await);
+        assert!(!Arc::ptr_eq(&empty, &backend.dictionary().await));
+    }
+}
@@ -0,0 +1,137 @@
+use tower_lsp::jsonrpc::Error;
+use tower_lsp::lsp_types::{
+    GotoDefinitionParams, GotoDefinitionResponse, Location, ShowDocumentParams, Url,
+};
+
+use crate::backend::Backend;
+use crate::link;
+use crate::range;
+use crate::structure::LinkKind;
+
+pub(crate) trait HandleDefinition {
+    async fn provide_def_ctx(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>, Error>;
+}
+
+impl HandleDefinition for Backend {
+    /// Follows the link under the cursor: headings, anchors, footnotes and
+    /// definitions to their declaration, `{:file:}` to the file, `{/ path}` to
+    /// the local file and urls to the browser.
+    async fn provide_def_ctx(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>, Error> {
+        let position = params.text_document_position_params;
+        let uri = position.text_document.uri;
+        let Some(structure) = self.index.get(&uri) else {
+            return Ok(None);
+        };
+        let Some(link) = structure
+            .links
+            .iter()
+            .find(|link| link::contains(link.range, position.position))
+        else {
+            return Ok(None);
+        };
+
+        match link.kind {
+            LinkKind::Path => Ok(self
+                .index
+                .resolve_path(&uri, &link.target)
+                .filter(|path| path.exists())
+                .and_then(|path| Url::from_file_path(path).ok())
+                .map(|uri| GotoDefinitionResponse::Scalar(Location::new(uri, range!())))),
+            LinkKind::Url => {
+                if let Ok(uri) = Url::parse(&link.target) {
+                    let params = ShowDocumentParams {
+                        uri,
+                        external: Some(true),
+                        take_focus: Some(true),
+                        selection: None,
+                    };
+                    _ = self.client.show_document(params).await;
+                }
+                Ok(None)
+            }
+            _ => {
+                let Some(target) = link::link_target(&self.index, &uri, link) else {
+                    return Ok(None);
+                };
+                let locations = link::resolve(&self.index, &target);
+                Ok((!locations.is_empty()).then_some(GotoDefinitionResponse::Array(locations)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::{
+        Position, Range, TextDocumentIdentifier, TextDocumentPositionParams,
+    };
+
+    use super::*;
+    use crate::testing::Server;
+
+    async fn definition(
+        server: &Server,
+        uri: &Url,
+        character: u32,
+    ) -> Option<GotoDefinitionResponse> {
+        let params = GotoDefinitionParams {
+            text_document_position_params: TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(uri.clone()),
+                Position::new(0, character),
+            ),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        server
+            .backend()
+            .provide_def_ctx(params)
+            .await
+            .ok()
+            .flatten()
+    }
+
+    #[tokio::test]
+    async fn file_and_path_links_lead_to_files() {
+        let server = Server::new();
+        let notes = server.open("notes.norg", "\n** Plan\n").await;
+        let data = server.root.write("data.txt", "");
+        let uri = server
+            .open(
+                "index.norg",
+                "{:notes:} {:notes:** Plan} {/ ./data.txt} {/ missing.txt}",
+            )
+            .await;
+
+        let title = Range::new(Position::new(1, 3), Position::new(1, 7));
+        assert_eq!(
+            definition(&server, &uri, 3).await,
+            Some(GotoDefinitionResponse::Array(vec![Location::new(
+                notes.clone(),
+                range!()
+            )]))
+        );
+        assert_eq!(
+            definition(&server, &uri, 15).await,
+            Some(GotoDefinitionResponse::Array(vec![Location::new(
+                notes, title
+            )]))
+        );
+        let data = Url::from_file_path(data)
+            .ok()
+            .map(|data| Location::new(data, range!()));
+        assert_eq!(
+            definition(&server, &uri, 30).await,
+            data.map(GotoDefinitionResponse::Scalar)
+        );
+        assert_eq!(definition(&server, &uri, 45).await, None);
+        // a file that does not exist
+        let uri = server.open("other.norg", "{:nowhere:}").await;
+        assert_eq!(definition(&server, &uri, 3).await, None);
+    }
+}
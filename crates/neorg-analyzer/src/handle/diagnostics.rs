@@ -1,12 +1,384 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
 use neorg_syntax::get_diagnostics;
-use tower_lsp::lsp_types::Diagnostic;
+use ropey::Rope;
+use tower_lsp::lsp_types::{
+    Diagnostic, DiagnosticSeverity, DocumentDiagnosticParams, DocumentDiagnosticReport,
+    DocumentDiagnosticReportResult, FullDocumentDiagnosticReport, NumberOrString,
+    RelatedFullDocumentDiagnosticReport, RelatedUnchangedDocumentDiagnosticReport,
+    UnchangedDocumentDiagnosticReport, Url, WorkspaceDiagnosticParams, WorkspaceDiagnosticReport,
+    WorkspaceDiagnosticReportResult, WorkspaceDocumentDiagnosticReport,
+    WorkspaceFullDocumentDiagnosticReport, WorkspaceUnchangedDocumentDiagnosticReport,
+};
 
 use crate::backend::Backend;
+use crate::link::{self, Target};
+use crate::syntax;
+
+/// Code of broken link diagnostics. Their `data` holds the `TextEdit` of the
+/// quick-fix, if there is one.
+pub(crate) const BROKEN_LINK: &str = "broken-link";
+
+/// Identifies a set of diagnostics, so that a client asking again gets
+/// `Unchanged` when nothing changed.
+fn result_id(diagnostics: &[Diagnostic]) -> String {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(diagnostics)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// The full report, or an unchanged one when the diagnostics still match the
+/// `previous` result id.
+fn report(
+    diagnostics: Vec<Diagnostic>,
+    previous: Option<&str>,
+) -> Result<FullDocumentDiagnosticReport, UnchangedDocumentDiagnosticReport> {
+    let result_id = result_id(&diagnostics);
+    if previous == Some(result_id.as_str()) {
+        return Err(UnchangedDocumentDiagnosticReport { result_id });
+    }
+    Ok(FullDocumentDiagnosticReport {
+        result_id: Some(result_id),
+        items: diagnostics,
+    })
+}
 
 impl Backend {
-    pub(crate) fn get_diagnostics(&self, uri: &str) -> Vec<Diagnostic> {
-        self.cst_map
-            .get(uri)
-            .map_or(vec![], |node| get_diagnostics(node.to_owned()))
+    pub(crate) async fn get_diagnostics(&self, uri: &str) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        if self.settings().await.diagnostics.syntax
+            && let Some(doc) = self.document_map.get(uri)
+        {
+            diagnostics = doc.syntax.diagnostics(&doc.rope, self.index.encoding());
+        }
+        if let Ok(uri) = Url::parse(uri) {
+            diagnostics.extend(self.link_diagnostics(&uri).await);
+            diagnostics.extend(self.spelling_diagnostics(&uri).await);
+        }
+        diagnostics
+    }
+
+    /// Links in `uri` pointing at targets that do not exist.
+    async fn link_diagnostics(&self, uri: &Url) -> Vec<Diagnostic> {
+        if !self.settings().await.diagnostics.links {
+            return vec![];
+        }
+        // looking for files on disk blocks
+        let index = Arc::clone(&self.index);
+        let from = uri.clone();
+        let broken = tokio::task::spawn_blocking(move || link::broken_links(&index, &from));
+        broken
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .map(|broken| Diagnostic {
+                range: broken.range,
+                severity: Some(DiagnosticSeverity::WARNING),
+                code: Some(NumberOrString::String(BROKEN_LINK.to_owned())),
+                source: Some("neorg".to_owned()),
+                message: broken.message,
+                data: broken.fix.and_then(|fix| serde_json::to_value(fix).ok()),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// Pushes the diagnostics of `uri`, unless the client pulls them.
+    pub(crate) async fn publish_diagnostics(&self, uri: Url, version: Option<i32>) {
+        if self.pull_diagnostics.load(Ordering::Relaxed) {
+            return;
+        }
+        let diagnostics = self.get_diagnostics(uri.as_str()).await;
+        self.client
+            .publish_diagnostics(uri, diagnostics, version)
+            .await;
+    }
+
+    /// Has the client pull every diagnostic again, or pushes those of every
+    /// open document.
+    pub(crate) async fn refresh_diagnostics(&self) {
+        if self.pull_diagnostics.load(Ordering::Relaxed) {
+            _ = self.client.workspace_diagnostic_refresh().await;
+            return;
+        }
+        self.publish_open_diagnostics().await;
+    }
+
+    /// Targets declared in `uri`.
+    pub(crate) fn declarations(&self, uri: &Url) -> Vec<Target> {
+        let Some(structure) = self.index.get(uri) else {
+            return vec![];
+        };
+        link::declarations(uri, &structure)
+            .into_iter()
+            .map(|(target, _)| target)
+            .collect()
+    }
+
+    /// The edited `uri`, and the open documents linking to what it started or
+    /// stopped declaring since the last call.
+    fn affected_by_edit(&self, uri: &Url) -> Vec<Url> {
+        let declared = self.declarations(uri);
+        let previous = self
+            .declared
+            .insert(uri.to_string(), declared.clone())
+            .unwrap_or_default();
+        let added = declared.iter().filter(|target| !previous.contains(target));
+        let removed = previous.iter().filter(|target| !declared.contains(target));
+        let changed: Vec<&Target> = added.chain(removed).collect();
+
+        let mut affected = vec![uri.clone()];
+        if changed.is_empty() {
+            return affected;
+        }
+        let open: Vec<Url> = self
+            .document_map
+            .iter()
+            .filter_map(|item| Url::parse(item.key()).ok())
+            .filter(|other| other != uri)
+            .collect();
+        for other in open {
+            let Some(structure) = self.index.get(&other) else {
+                continue;
+            };
+            let links = structure.links.iter();
+            let targets = links.filter_map(|link| link::link_target(&self.index, &other, link));
+            if targets
+                .into_iter()
+                .any(|target| changed.iter().any(|declared| target.matches(declared)))
+            {
+                affected.push(other);
+            }
+        }
+        affected
+    }
+
+    /// Pushes the diagnostics of the edited `uri` and of the open documents
+    /// whose links it may have broken or resolved, unless the client pulls
+    /// them.
+    pub(crate) async fn publish_edit_diagnostics(&self, uri: &Url) {
+        for uri in self.affected_by_edit(uri) {
+            let version = self.reparse(uri.as_str());
+            self.publish_diagnostics(uri, version).await;
+        }
+    }
+
+    /// Pushes the diagnostics of every open document, unless the client pulls
+    /// them. Links in one document may break or resolve on edits to another.
+    pub(crate) async fn publish_open_diagnostics(&self) {
+        let uris: Vec<String> = self
+            .document_map
+            .iter()
+            .map(|item| item.key().clone())
+            .collect();
+        for uri in uris {
+            let version = self.reparse(&uri);
+            if let Ok(uri) = Url::parse(&uri) {
+                self.publish_diagnostics(uri, version).await;
+            }
+        }
+    }
+
+    /// Diagnostics of an open document, or of a file as it is on disk.
+    async fn diagnostics_of(&self, uri: &Url) -> Vec<Diagnostic> {
+        if self.reparse(uri.as_str()).is_some() {
+            return self.get_diagnostics(uri.as_str()).await;
+        }
+        let mut diagnostics = self.disk_diagnostics_of(uri).await;
+        diagnostics.extend(self.link_diagnostics(uri).await);
+        diagnostics
+    }
+
+    /// Syntax errors of a file as it is on disk, cached until it is modified.
+    /// The disk is read and the file parsed off the async runtime.
+    async fn disk_diagnostics_of(&self, uri: &Url) -> Vec<Diagnostic> {
+        if !self.settings().await.diagnostics.syntax {
+            return vec![];
+        }
+        let Ok(path) = uri.to_file_path() else {
+            return vec![];
+        };
+        let cached = self.disk_diagnostics.get(uri).map(|cached| cached.clone());
+        let parsed_at = cached.as_ref().map(|(modified, _)| *modified);
+        let encoding = self.index.encoding();
+        let read = tokio::task::spawn_blocking(move || {
+            let modified = std::fs::metadata(&path).and_then(|metadata| metadata.modified());
+            let modified = modified.ok();
+            if modified.is_some() && modified == parsed_at {
+                return Some((modified, None));
+            }
+            let text = std::fs::read_to_string(&path).ok()?;
+            let rope = Rope::from_str(&text);
+            let diagnostics: Vec<_> = get_diagnostics(neorg_syntax::cst!(&text))
+                .into_iter()
+                .map(|diagnostic| Diagnostic {
+                    range: syntax::locate(&rope, 0, diagnostic.range, encoding),
+                    ..diagnostic
+                })
+                .collect();
+            Some((modified, Some(diagnostics)))
+        });
+        match read.await.ok().flatten() {
+            Some((_, None)) => cached
+                .map(|(_, diagnostics)| diagnostics)
+                .unwrap_or_default(),
+            Some((modified, Some(diagnostics))) => {
+                if let Some(modified) = modified {
+                    self.disk_diagnostics
+                        .insert(uri.clone(), (modified, diagnostics.clone()));
+                }
+                diagnostics
+            }
+            None => vec![],
+        }
+    }
+
+    pub(crate) async fn provide_document_diagnostics(
+        &self,
+        params: DocumentDiagnosticParams,
+    ) -> DocumentDiagnosticReportResult {
+        let diagnostics = self.diagnostics_of(&params.text_document.uri).await;
+        let report = match report(diagnostics, params.previous_result_id.as_deref()) {
+            Ok(full) => DocumentDiagnosticReport::Full(RelatedFullDocumentDiagnosticReport {
+                related_documents: None,
+                full_document_diagnostic_report: full,
+            }),
+            Err(unchanged) => {
+                DocumentDiagnosticReport::Unchanged(RelatedUnchangedDocumentDiagnosticReport {
+                    related_documents: None,
+                    unchanged_document_diagnostic_report: unchanged,
+                })
+            }
+        };
+        DocumentDiagnosticReportResult::Report(report)
+    }
+
+    /// Diagnostics of every indexed file, open or not.
+    pub(crate) async fn provide_workspace_diagnostics(
+        &self,
+        params: WorkspaceDiagnosticParams,
+    ) -> WorkspaceDiagnosticReportResult {
+        let mut items = Vec::new();
+        for (uri, _) in self.index.files() {
+            let previous = params
+                .previous_result_ids
+                .iter()
+                .find(|previous| previous.uri == uri)
+                .map(|previous| previous.value.as_str());
+            let diagnostics = self.diagnostics_of(&uri).await;
+            let version = self.version(uri.as_str()).map(i64::from);
+            items.push(match report(diagnostics, previous) {
+                Ok(full) => {
+                    WorkspaceDocumentDiagnosticReport::Full(WorkspaceFullDocumentDiagnosticReport {
+                        uri,
+                        version,
+                        full_document_diagnostic_report: full,
+                    })
+                }
+                Err(unchanged) => WorkspaceDocumentDiagnosticReport::Unchanged(
+                    WorkspaceUnchangedDocumentDiagnosticReport {
+                        uri,
+                        version,
+                        unchanged_document_diagnostic_report: unchanged,
+                    },
+                ),
+            });
+        }
+        WorkspaceDiagnosticReportResult::Report(WorkspaceDiagnosticReport { items })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::PreviousResultId;
+
+    use super::*;
+    use crate::testing::Server;
+
+    /// Result id of every full report, `None` for unchanged ones.
+    async fn pull(server: &Server, previous: &[(Url, String)]) -> Vec<(Url, Option<String>)> {
+        let params = WorkspaceDiagnosticParams {
+            identifier: None,
+            previous_result_ids: previous
+                .iter()
+                .map(|(uri, value)| PreviousResultId {
+                    uri: uri.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let WorkspaceDiagnosticReportResult::Report(report) =
+            server.backend().provide_workspace_diagnostics(params).await
+        else {
+            panic!("partial report");
+        };
+        let mut items: Vec<_> = report
+            .items
+            .into_iter()
+            .map(|item| match item {
+                WorkspaceDocumentDiagnosticReport::Full(full) => {
+                    (full.uri, full.full_document_diagnostic_report.result_id)
+                }
+                WorkspaceDocumentDiagnosticReport::Unchanged(unchanged) => (unchanged.uri, None),
+            })
+            .collect();
+        items.sort();
+        items
+    }
+
+    #[tokio::test]
+    async fn unchanged_diagnostics_are_not_sent_again() {
+        let server = Server::new();
+        let open = server.open("a.norg", "{* missing}\n").await;
+        let path = server.root.write("b.norg", "{:a:* missing}\n");
+        server.backend().index.index_file(&path);
+
+        let first = pull(&server, &[]).await;
+        let previous: Vec<(Url, String)> = first
+            .iter()
+            .filter_map(|(uri, id)| Some((uri.clone(), id.clone()?)))
+            .collect();
+        assert_eq!(previous.len(), 2);
+        let unchanged: Vec<_> = previous
+            .iter()
+            .map(|(uri, _)| (uri.clone(), None))
+            .collect();
+        assert_eq!(pull(&server, &previous).await, unchanged);
+
+        // fixing the heading changes the diagnostics of both files
+        server.change(&open, 2, "* missing\n{* missing}\n").await;
+        let again = pull(&server, &previous).await;
+        assert!(again.iter().all(|(_, id)| id.is_some()), "{again:?}");
+        assert_ne!(again, first);
+    }
+
+    #[tokio::test]
+    async fn edits_refresh_the_documents_linking_into_them() {
+        let server = Server::new();
+        let a = server.open("a.norg", "* Heading\ntext\n").await;
+        let b = server.open("b.norg", "{:a:* Heading}\n").await;
+        server.open("c.norg", "* Other\n").await;
+        let backend = server.backend();
+
+        // the text changed, but not what it declares
+        backend.index.update(a.clone(), "* Heading\nmore text\n");
+        assert_eq!(backend.affected_by_edit(&a), vec![a.clone()]);
+
+        backend.index.update(a.clone(), "* Renamed\nmore text\n");
+        let mut affected = backend.affected_by_edit(&a);
+        affected.sort();
+        assert_eq!(affected, vec![a.clone(), b.clone()]);
+
+        // and back, which resolves the link again
+        backend.index.update(a.clone(), "* Heading\n");
+        let mut affected = backend.affected_by_edit(&a);
+        affected.sort();
+        assert_eq!(affected, vec![a, b]);
     }
 }
@@ -0,0 +1,118 @@
+use tower_lsp::lsp_types::{FoldingRange, FoldingRangeKind, FoldingRangeParams, Range};
+
+use crate::backend::Backend;
+use crate::structure::{BlockKind, Structure};
+
+fn fold(range: Range, kind: Option<FoldingRangeKind>) -> Option<FoldingRange> {
+    (range.end.line > range.start.line).then_some(FoldingRange {
+        start_line: range.start.line,
+        start_character: None,
+        end_line: range.end.line,
+        end_character: None,
+        kind,
+        collapsed_text: None,
+    })
+}
+
+/// Headings fold up to the next heading of equal or higher level, lists and
+/// quotes over their nested items, ranged tags and ranged detached modifiers
+/// up to their closing line.
+fn folding_ranges(structure: &Structure) -> Vec<FoldingRange> {
+    let headings = structure
+        .headings
+        .iter()
+        .filter_map(|heading| fold(heading.section, Some(FoldingRangeKind::Region)));
+
+    let blocks = structure.blocks.iter().filter_map(|block| {
+        let kind = match block.kind {
+            BlockKind::UnorderedList | BlockKind::OrderedList | BlockKind::Quote => None,
+            BlockKind::Definition | BlockKind::Footnote | BlockKind::Table => {
+                Some(FoldingRangeKind::Region)
+            }
+        };
+        fold(block.range, kind)
+    });
+
+    let tags = structure
+        .tags
+        .iter()
+        .filter(|tag| tag.kind.is_ranged())
+        .filter_map(|tag| {
+            let kind = match tag.name.as_str() {
+                "comment" => FoldingRangeKind::Comment,
+                _ => FoldingRangeKind::Region,
+            };
+            fold(tag.range, Some(kind))
+        });
+
+    headings.chain(blocks).chain(tags).collect()
+}
+
+impl Backend {
+    /// Folds come from the index rather than the CST, like the outline; see
+    /// [`crate::structure`].
+    pub(crate) fn provide_folding_ranges(
+        &self,
+        params: FoldingRangeParams,
+    ) -> Option<Vec<FoldingRange>> {
+        let structure = self.index.get(&params.text_document.uri)?;
+        Some(folding_ranges(&structure))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::span::PositionEncoding;
+    use crate::structure::parse;
+
+    fn folds(text: &str) -> Vec<(u32, u32, Option<FoldingRangeKind>)> {
+        let structure = parse(text, PositionEncoding::Utf16);
+        let mut folds: Vec<_> = folding_ranges(&structure)
+            .into_iter()
+            .map(|fold| (fold.start_line, fold.end_line, fold.kind))
+            .collect();
+        folds.sort_by_key(|(start, end, _)| (*start, *end));
+        folds
+    }
+
+    #[test]
+    fn headings_lists_quotes_and_tags_fold() {
+        let text = "\
+* Heading
+- item
+-- nested
+- single
+> quote
+  continued
+@comment
+hidden
+@end
+** Sub
+text
+";
+        let region = Some(FoldingRangeKind::Region);
+        assert_eq!(
+            folds(text),
+            [
+                (0, 11, region.clone()),
+                (1, 2, None),
+                (4, 5, None),
+                (6, 8, Some(FoldingRangeKind::Comment)),
+                (9, 11, region),
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_tags_fold_to_the_end() {
+        let region = Some(FoldingRangeKind::Region);
+        assert_eq!(
+            folds("text\n@code\nfn main() {}\n\n"),
+            [(1, 4, region.clone())]
+        );
+        // a single line has nothing to fold
+        assert_eq!(folds("@code"), []);
+        assert_eq!(folds("$$ term\nbody"), [(0, 1, region)]);
+    }
+}
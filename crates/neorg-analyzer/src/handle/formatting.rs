@@ -0,0 +1,101 @@
+use std::ops::Range as Span;
+
+use tower_lsp::lsp_types::{
+    DocumentFormattingParams, DocumentOnTypeFormattingParams, DocumentRangeFormattingParams,
+    FormattingOptions, Range, TextEdit, Url,
+};
+
+use crate::backend::Backend;
+use crate::format;
+
+/// Lines `range` covers. A range ending at the start of a line, as selecting
+/// whole lines does, leaves that line out.
+fn range_lines(range: Range) -> Span<u32> {
+    let end = if range.end.character == 0 && range.end.line > range.start.line {
+        range.end.line
+    } else {
+        range.end.line.saturating_add(1)
+    };
+    range.start.line..end
+}
+
+impl Backend {
+    /// Edits formatting the open document `uri`, limited to `lines` if any.
+    async fn format_lines(
+        &self,
+        uri: &Url,
+        options: &FormattingOptions,
+        lines: Option<Span<u32>>,
+    ) -> Option<Vec<TextEdit>> {
+        let settings = self.settings().await;
+        if !settings.formatter.enable {
+            return None;
+        }
+        let text = self.document_map.get(uri.as_str())?.rope.to_string();
+        let formatted = format::format(&text, &settings.formatter, options);
+        Some(format::edits(
+            &text,
+            &formatted,
+            self.index.encoding(),
+            lines,
+        ))
+    }
+
+    pub(crate) async fn provide_formatting(
+        &self,
+        params: DocumentFormattingParams,
+    ) -> Option<Vec<TextEdit>> {
+        self.format_lines(&params.text_document.uri, &params.options, None)
+            .await
+    }
+
+    pub(crate) async fn provide_range_formatting(
+        &self,
+        params: DocumentRangeFormattingParams,
+    ) -> Option<Vec<TextEdit>> {
+        let lines = range_lines(params.range);
+        self.format_lines(&params.text_document.uri, &params.options, Some(lines))
+            .await
+    }
+
+    /// Continues lists and indents below headings on a line break.
+    pub(crate) async fn provide_on_type_formatting(
+        &self,
+        params: DocumentOnTypeFormattingParams,
+    ) -> Option<Vec<TextEdit>> {
+        let settings = self.settings().await;
+        if !settings.formatter.on_type || params.ch != "\n" {
+            return None;
+        }
+        let position = params.text_document_position;
+        let uri = position.text_document.uri.as_str();
+        let text = self.document_map.get(uri)?.rope.to_string();
+        let line = position.position.line;
+        Some(format::on_enter(
+            &text,
+            line,
+            &settings.formatter,
+            self.index.encoding(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::Position;
+
+    use super::*;
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+        Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+    }
+
+    #[test]
+    fn ranges_cover_the_lines_they_select() {
+        assert_eq!(range_lines(range((1, 0), (3, 0))), 1..3);
+        assert_eq!(range_lines(range((1, 0), (3, 2))), 1..4);
+        assert_eq!(range_lines(range((1, 4), (1, 4))), 1..2);
+        assert_eq!(range_lines(range((1, 0), (1, 0))), 1..2);
+        assert_eq!(range_lines(range((1, 0), (u32::MAX, 5))), 1..u32::MAX);
+    }
+}
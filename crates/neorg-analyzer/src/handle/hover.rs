@@ -1,14 +1,11 @@
-use std::path::Path;
-
-use reqwest::Url;
 use tower_lsp::jsonrpc::Error;
 use tower_lsp::lsp_types::{
-    GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams, Location,
-    MarkupContent, MarkupKind, Position, Range,
+    Hover, HoverContents, HoverParams, MarkupContent, MarkupKind, Position, Range,
+    ShowDocumentParams, TextDocumentPositionParams, Url,
 };
 
 use crate::backend::Backend;
-use crate::types::{DictionaryEntry, MarkDown};
+use crate::range;
 
 pub(crate) fn contains_pos(r: Range, p: Position) -> bool {
     let Range { start, end } = r;
@@ -23,75 +20,38 @@ pub(crate) trait HandleHover {
     async fn provide_hover_ctx(&self, params: HoverParams) -> Result<Option<Hover>, Error>;
 }
 
-pub(crate) trait HandleDefinition {
-    async fn provide_def_ctx(
-        &self,
-        params: GotoDefinitionParams,
-    ) -> Result<Option<tower_lsp::lsp_types::GotoDefinitionResponse>, Error>;
-}
-
-impl HandleDefinition for Backend {
-    async fn provide_def_ctx(
-        &self,
-        params: GotoDefinitionParams,
-    ) -> Result<Option<tower_lsp::lsp_types::GotoDefinitionResponse>, Error> {
-        let mut path = "/home/abhi/.local/share/neorg/dict/".to_owned();
-        if !Path::new(&path).exists() {
-            let _ = tokio::fs::create_dir_all(&path).await;
-        }
-        let key = params
-            .text_document_position_params
-            .text_document
-            .uri
-            .as_str();
-
-        if let Some(sytaxnode) = self.cst_map.get(key) {
-            let node = sytaxnode.to_owned();
-            let words = neorg_syntax::get_kinds(neorg_syntax::SyntaxKind::Word, node);
-            for word in words {
-                if contains_pos(word.range(), params.text_document_position_params.position) {
-                    path += word.text();
-                    path += ".md";
-                    let _cached_result = tokio::fs::read_to_string(&path).await;
-                    let search_result = get_meaning(word.text()).await;
-                    match search_result {
-                        Ok(meaning) => {
-                            let _ = tokio::fs::write(&path, meaning).await;
-                        }
-                        _ => return Ok(None),
-                    }
-                }
-            }
-        }
-        match Url::from_file_path(&path) {
-            Ok(uri) => Ok(Some(GotoDefinitionResponse::Scalar(Location {
-                uri,
-                range: neorg_syntax::cst!(&path).range(),
-            }))),
-            Err(_) => Ok(None),
-        }
-    }
-}
-
 impl HandleHover for Backend {
     async fn provide_hover_ctx(&self, params: HoverParams) -> Result<Option<Hover>, Error> {
-        let mut hover_ctx = String::new();
         let uri = params
             .text_document_position_params
             .text_document
             .uri
             .to_string();
+        // the version the request is about
+        let version = self.version(&uri);
+        if !self.settings().await.dictionary.hover {
+            return Ok(None);
+        }
+        let dictionary = self.dictionary().await;
+        let mut hover_ctx = String::new();
+        self.reparse(&uri);
 
-        if let Some(s) = self.cst_map.get(uri.as_str()) {
-            let node = s.to_owned();
-            let words = neorg_syntax::get_kinds(neorg_syntax::SyntaxKind::Word, node);
+        // collected, so that no lock is held while looking words up
+        let encoding = self.index.encoding();
+        let words = self
+            .document_map
+            .get(&uri)
+            .map(|doc| doc.syntax.words(&doc.rope, encoding));
+        if let Some(words) = words {
             for word in words {
-                if contains_pos(word.range(), params.text_document_position_params.position)
-                    && let Ok(meaning) = get_meaning(word.text()).await
+                if contains_pos(word.range, params.text_document_position_params.position)
+                    && let Ok(meaning) = dictionary.meaning(&word.text).await
                 {
                     hover_ctx.push_str(&meaning);
                 }
             }
+            // looking words up may take long enough for the text to change
+            self.ensure_version(&uri, version)?;
 
             return Ok(Some(Hover {
                 contents: HoverContents::Markup(MarkupContent {
@@ -105,16 +65,66 @@ impl HandleHover for Backend {
     }
 }
 
-async fn get_meaning(word: &str) -> Result<String, reqwest::Error> {
-    let url = format!("https://api.dictionaryapi.dev/api/v2/entries/en/{word}");
-    let response = reqwest::get(&url).await?;
-    let entries = response.json::<Vec<DictionaryEntry>>().await?;
+impl Backend {
+    /// Writes the meaning of the word under the cursor to a file and opens it.
+    /// Backs the `neorg.lookupWord` command.
+    pub(crate) async fn lookup_word(&self, params: TextDocumentPositionParams) -> Option<Url> {
+        let dictionary = self.dictionary().await;
+        let uri = params.text_document.uri.as_str();
+        self.reparse(uri);
+        let doc = self.document_map.get(uri)?;
+        let words = doc.syntax.words(&doc.rope, self.index.encoding());
+        drop(doc);
+        let word = words
+            .into_iter()
+            .find(|word| contains_pos(word.range, params.position))?;
+
+        let path = dictionary.meaning_file(&word.text).await.ok()?;
+        let uri = Url::from_file_path(path).ok()?;
+        let params = ShowDocumentParams {
+            uri: uri.clone(),
+            external: None,
+            take_focus: Some(true),
+            selection: Some(range!()),
+        };
+        _ = self.client.show_document(params).await;
+        Some(uri)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::jsonrpc::ErrorCode;
+    use tower_lsp::lsp_types::TextDocumentIdentifier;
+
+    use super::*;
+    use crate::testing::Server;
+
+    #[tokio::test]
+    async fn stale_hovers_are_dropped() {
+        let server = Server::new();
+        let uri = server.open("a.norg", "word\n").await;
+        let params = HoverParams {
+            text_document_position_params: TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(uri.clone()),
+                Position::new(0, 1),
+            ),
+            work_done_progress_params: Default::default(),
+        };
+        let hover = |backend: Backend, params| {
+            tokio::spawn(async move { backend.provide_hover_ctx(params).await })
+        };
 
-    let mut result = String::new();
+        let up_to_date = hover(server.backend().clone(), params.clone()).await;
+        assert!(up_to_date.is_ok_and(|hover| hover.is_ok()));
 
-    if let Some(entry) = entries.first() {
-        let content = std::convert::Into::<MarkDown>::into(entry);
-        result.push_str(&content);
+        // the text changes while the request waits
+        let settings = server.backend().settings.write().await;
+        let request = hover(server.backend().clone(), params);
+        tokio::task::yield_now().await;
+        server.change(&uri, 2, "other\n").await;
+        drop(settings);
+        let stale = request.await.ok().and_then(Result::err);
+        assert_eq!(stale.map(|err| err.code), Some(ErrorCode::ContentModified));
     }
-    Ok(result)
 }
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+use tower_lsp::lsp_types::{InlayHint, InlayHintLabel, InlayHintParams, Position, Range};
+
+use crate::backend::Backend;
+use crate::config::InlayHintSettings;
+use crate::link::contains;
+use crate::structure::{BlockKind, Link, LinkKind, Structure, TodoStatus};
+
+fn hint(position: Position, label: String, left: bool) -> InlayHint {
+    InlayHint {
+        position,
+        label: InlayHintLabel::String(label),
+        kind: None,
+        text_edits: None,
+        tooltip: None,
+        padding_left: Some(left),
+        padding_right: Some(!left),
+        data: None,
+    }
+}
+
+/// `1.2.3` before every heading title, counting skipped levels as `0`.
+fn section_numbers(structure: &Structure) -> Vec<InlayHint> {
+    let mut counters: Vec<u32> = Vec::new();
+    structure
+        .headings
+        .iter()
+        .map(|heading| {
+            let level = usize::from(heading.level.max(1));
+            counters.resize(level, 0);
+            counters[level - 1] += 1;
+            let number = counters
+                .iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(".");
+            hint(heading.title_range.start, number, false)
+        })
+        .collect()
+}
+
+/// `1.`, `2.`, ... after the marker of every ordered list item, counting
+/// the items following each other at the same level.
+fn list_ordinals(structure: &Structure) -> Vec<InlayHint> {
+    // ordinal of the item expected at a level and line
+    let mut next: HashMap<(u8, u32), u32> = HashMap::new();
+    structure
+        .blocks
+        .iter()
+        .filter(|block| block.kind == BlockKind::OrderedList)
+        .map(|block| {
+            let line = block.range.start.line;
+            let ordinal = next.remove(&(block.level, line)).unwrap_or(1);
+            next.insert((block.level, block.range.end.line + 1), ordinal + 1);
+            hint(block.marker_range.end, format!("{ordinal}."), false)
+        })
+        .collect()
+}
+
+/// `done/total done` after every heading with todo items below it,
+/// cancelled items left out.
+fn todo_progress(structure: &Structure) -> Vec<InlayHint> {
+    structure
+        .headings
+        .iter()
+        .filter_map(|heading| {
+            let line = heading.range.start.line;
+            let (done, total) = structure
+                .todos
+                .iter()
+                .filter(|todo| todo.range.start.line > line)
+                .filter(|todo| contains(heading.section, todo.range.start))
+                .filter(|todo| todo.status != TodoStatus::Cancelled)
+                .fold((0, 0), |(done, total), todo| {
+                    (
+                        done + usize::from(todo.status == TodoStatus::Done),
+                        total + 1,
+                    )
+                });
+            (total > 0).then(|| hint(heading.range.end, format!("{done}/{total} done"), true))
+        })
+        .collect()
+}
+
+/// The `title:` of a file's metadata, or else its first heading as the
+/// closest thing to a title.
+fn file_title(structure: &Structure) -> Option<String> {
+    if let Some(title) = &structure.title {
+        return Some(title.clone());
+    }
+    let heading = &structure.headings.first()?.title;
+    (!heading.is_empty()).then(|| heading.clone())
+}
+
+/// Hints of `structure` enabled by `settings`, with `title` naming the file
+/// a `{:file:}` link points at.
+fn inlay_hints(
+    structure: &Structure,
+    settings: &InlayHintSettings,
+    title: impl Fn(&Link) -> Option<String>,
+) -> Vec<InlayHint> {
+    let mut hints = Vec::new();
+    if settings.section_numbers {
+        hints.extend(section_numbers(structure));
+    }
+    if settings.list_ordinals {
+        hints.extend(list_ordinals(structure));
+    }
+    if settings.todo_progress {
+        hints.extend(todo_progress(structure));
+    }
+    if settings.link_titles {
+        let links = structure
+            .links
+            .iter()
+            .filter(|link| link.kind == LinkKind::File);
+        hints.extend(links.filter_map(|link| Some(hint(link.range.end, title(link)?, true))));
+    }
+    hints
+}
+
+impl Backend {
+    pub(crate) async fn provide_inlay_hints(
+        &self,
+        params: InlayHintParams,
+    ) -> Option<Vec<InlayHint>> {
+        let settings = self.settings().await;
+        let uri = &params.text_document.uri;
+        let structure = self.index.get(uri)?;
+        let title = |link: &Link| {
+            let target = self.index.resolve_file(uri, link.file.as_deref()?)?;
+            let structure = self.index.get(&target)?;
+            file_title(&structure)
+        };
+
+        let Range { start, end } = params.range;
+        let mut hints = inlay_hints(&structure, &settings.inlay_hints, title);
+        hints.retain(|hint| start <= hint.position && hint.position <= end);
+        hints.sort_by_key(|hint| hint.position);
+        Some(hints)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::TextDocumentIdentifier;
+
+    use super::*;
+    use crate::span::PositionEncoding;
+    use crate::structure::parse;
+    use crate::testing::Server;
+
+    #[test]
+    fn hints_number_and_count() {
+        let text = "* Plan\n** ( ) Ship\n~ one\n~~ nested\n~ (x) two\n*** Deep\n* (_) Dropped\n- ( ) a\n- (_) b\n";
+        let structure = parse(text, PositionEncoding::Utf16);
+        let settings = InlayHintSettings {
+            link_titles: false,
+            ..Default::default()
+        };
+        let mut hints: Vec<(u32, u32, String)> = inlay_hints(&structure, &settings, |_| None)
+            .into_iter()
+            .map(|hint| {
+                let InlayHintLabel::String(label) = hint.label else {
+                    panic!("label parts");
+                };
+                (hint.position.line, hint.position.character, label)
+            })
+            .collect();
+        hints.sort();
+        let expected = [
+            (0, 2, "1"),
+            (0, 6, "1/2 done"),
+            (1, 7, "1.1"),
+            (1, 11, "1/1 done"),
+            (2, 2, "1."),
+            (3, 3, "1."),
+            (4, 2, "2."),
+            (5, 4, "1.1.1"),
+            (6, 6, "2"),
+            (6, 13, "0/1 done"),
+        ];
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(line, character, label)| (line, character, label.to_owned()))
+            .collect();
+        assert_eq!(hints, expected);
+    }
+
+    #[tokio::test]
+    async fn links_are_titled_by_the_metadata_first() {
+        let server = Server::new();
+        let meta = "@document.meta\nauthors: someone\ntitle: Meta Title\n@end\n* Heading\n";
+        for (name, text) in [("meta.norg", meta), ("plain.norg", "text\n* Heading\n")] {
+            let path = server.root.write(name, text);
+            server.backend().index.index_file(&path);
+        }
+        let text = "{:meta:}\n{:plain:}\n{:missing:}\n";
+        let uri = server.open("a.norg", text).await;
+        let params = InlayHintParams {
+            text_document: TextDocumentIdentifier::new(uri),
+            range: Range::new(Position::new(0, 0), Position::new(3, 0)),
+            work_done_progress_params: Default::default(),
+        };
+
+        let hints = server.backend().provide_inlay_hints(params).await;
+        let labels: Vec<(u32, String)> = hints
+            .into_iter()
+            .flatten()
+            .filter_map(|hint| match hint.label {
+                InlayHintLabel::String(label) => Some((hint.position.line, label)),
+                InlayHintLabel::LabelParts(_) => None,
+            })
+            .collect();
+        assert_eq!(
+            labels,
+            [(0, "Meta Title".to_owned()), (1, "Heading".to_owned())]
+        );
+    }
+}
@@ -1,8 +1,22 @@
 mod code_action;
+mod command;
+mod completion;
+mod configuration;
+mod definition;
 mod diagnostics;
+mod folding;
+mod formatting;
 mod hover;
+mod inlay_hint;
+mod references;
 pub(crate) mod rename;
+pub(crate) mod semantic_tokens;
+pub(crate) mod spelling;
+mod symbol;
+mod todo;
+mod workspace;
 
 pub(crate) use code_action::HandleCodeAction;
-pub(crate) use hover::HandleDefinition;
+pub(crate) use command::ServerCommand;
+pub(crate) use definition::HandleDefinition;
 pub(crate) use hover::HandleHover;
@@ -0,0 +1,100 @@
+use tower_lsp::lsp_types::{Location, ReferenceParams};
+
+use crate::backend::Backend;
+use crate::link;
+
+impl Backend {
+    /// Links pointing at the heading, anchor or link target under the cursor.
+    pub(crate) fn provide_references(&self, params: ReferenceParams) -> Option<Vec<Location>> {
+        let position = params.text_document_position;
+        let target = link::target_at(&self.index, &position.text_document.uri, position.position)?;
+
+        let mut locations = link::references(&self.index, &target);
+        if params.context.include_declaration {
+            locations.extend(link::resolve(&self.index, &target));
+        }
+        Some(locations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::{
+        Position, Range, ReferenceContext, TextDocumentIdentifier, TextDocumentPositionParams,
+    };
+
+    use super::*;
+    use crate::testing::Server;
+
+    fn params(position: TextDocumentPositionParams, include_declaration: bool) -> ReferenceParams {
+        ReferenceParams {
+            text_document_position: position,
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: ReferenceContext {
+                include_declaration,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn links_are_found_across_files() {
+        let server = Server::new();
+        let a = server
+            .open("a.norg", "* Target\n{* target}\n** Target\n")
+            .await;
+        let b = server
+            .open(
+                "dir/b.norg",
+                "{:../a:* Target} {:../a:# target}\n{:../a:** Target}\n",
+            )
+            .await;
+        let c = server.open("c.norg", "* Target\n").await;
+
+        let position = |uri: &tower_lsp::lsp_types::Url, line, character| {
+            TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(uri.clone()),
+                Position::new(line, character),
+            )
+        };
+        let locations = |position, include| {
+            let mut locations = server
+                .backend()
+                .provide_references(params(position, include))
+                .unwrap_or_default();
+            locations.sort_by_key(|location| (location.uri.to_string(), location.range.start));
+            locations
+                .into_iter()
+                .map(|location| (location.uri, location.range))
+                .collect::<Vec<_>>()
+        };
+        let range =
+            |line, start, end| Range::new(Position::new(line, start), Position::new(line, end));
+
+        // from the declaration
+        assert_eq!(
+            locations(position(&a, 0, 3), false),
+            [
+                (a.clone(), range(1, 0, 10)),
+                (b.clone(), range(0, 0, 16)),
+                (b.clone(), range(0, 17, 33)),
+            ]
+        );
+        // from a link, with the declaration
+        assert_eq!(
+            locations(position(&b, 0, 10), true),
+            [
+                (a.clone(), range(0, 2, 8)),
+                (a.clone(), range(1, 0, 10)),
+                (b.clone(), range(0, 0, 16)),
+                (b.clone(), range(0, 17, 33)),
+            ]
+        );
+        // `{# target}` matches whatever has that title
+        assert_eq!(
+            locations(position(&a, 2, 4), false),
+            [(b.clone(), range(0, 17, 33)), (b.clone(), range(1, 0, 17))]
+        );
+        assert_eq!(locations(position(&c, 0, 3), false), []);
+    }
+}
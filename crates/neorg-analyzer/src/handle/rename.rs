@@ -1,34 +1,168 @@
-use std::collections::HashMap;
-
-use tower_lsp::lsp_types::{RenameParams, TextEdit, WorkspaceEdit};
-
-impl crate::backend::Backend {
-    pub(crate) fn provide_rename(&self, params: RenameParams) -> Option<WorkspaceEdit> {
-        let mut results = HashMap::new();
-
-        let uri = params.text_document_position.text_document.uri.as_str();
-
-        if let Some(value) = self.cst_map.get_mut(uri) {
-            let node = value.to_owned();
-            let words = neorg_syntax::get_kinds(neorg_syntax::SyntaxKind::Word, node);
-            for word in words {
-                if super::hover::contains_pos(word.range(), params.text_document_position.position)
-                {
-                    results.insert(
-                        params.text_document_position.text_document.uri,
-                        vec![TextEdit {
-                            range: word.range(),
-                            new_text: params.new_name,
-                        }],
-                    );
-                    return Some(WorkspaceEdit {
-                        changes: Some(results),
-                        document_changes: None, // is a Workspace Edit
-                        change_annotations: None,
-                    });
-                }
+use std::collections::BTreeMap;
+
+use tower_lsp::jsonrpc::{Error, Result};
+use tower_lsp::lsp_types::{
+    DocumentChanges, OneOf, OptionalVersionedTextDocumentIdentifier, Position,
+    PrepareRenameResponse, Range, RenameParams, TextDocumentEdit, TextEdit, Url, WorkspaceEdit,
+};
+
+use crate::backend::Backend;
+use crate::link::{self, Target, TargetKind};
+
+impl Backend {
+    /// The heading, anchor, footnote or definition under `position`, either
+    /// at its declaration or at a link to it, with the range of its name.
+    fn rename_target(&self, uri: &Url, position: Position) -> Option<(Target, Range)> {
+        let (target, range) = link::name_at(&self.index, uri, position)?;
+        if target.kind == TargetKind::File || link::resolve(&self.index, &target).is_empty() {
+            return None;
+        }
+        Some((target, range))
+    }
+
+    pub(crate) fn provide_prepare_rename(
+        &self,
+        uri: &Url,
+        position: Position,
+    ) -> Option<PrepareRenameResponse> {
+        let (_, range) = self.rename_target(uri, position)?;
+        Some(PrepareRenameResponse::Range(range))
+    }
+
+    /// Renames the declaration and rewrites every link pointing at it, in
+    /// every indexed file.
+    pub(crate) fn provide_rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        let position = params.text_document_position;
+        let Some((target, _)) = self.rename_target(&position.text_document.uri, position.position)
+        else {
+            return Ok(None);
+        };
+
+        let new_name = params.new_name.trim();
+        if new_name.is_empty() || new_name.contains(['\n', '\r', '}', ']']) {
+            return Err(Error::invalid_params(format!(
+                "`{new_name}` is not a valid name"
+            )));
+        }
+
+        let mut edits: BTreeMap<Url, Vec<TextEdit>> = BTreeMap::new();
+        let declarations = link::resolve(&self.index, &target)
+            .into_iter()
+            .map(|location| (location.uri, location.range));
+        let links = link::referring_links(&self.index, &target)
+            .into_iter()
+            .map(|(uri, link)| (uri, link.target_range));
+        for (uri, range) in declarations.chain(links) {
+            let edits = edits.entry(uri).or_default();
+            // `{# name}` and `{* name}` may both resolve to the same title
+            if !edits.iter().any(|edit| edit.range == range) {
+                edits.push(TextEdit::new(range, new_name.to_owned()));
             }
         }
-        None
+
+        let changes = edits
+            .into_iter()
+            .map(|(uri, edits)| TextDocumentEdit {
+                text_document: OptionalVersionedTextDocumentIdentifier {
+                    // files that are not open are edited as they are on disk
+                    version: self.version(uri.as_str()),
+                    uri,
+                },
+                edits: edits.into_iter().map(OneOf::Left).collect(),
+            })
+            .collect();
+        Ok(Some(WorkspaceEdit {
+            changes: None,
+            document_changes: Some(DocumentChanges::Edits(changes)),
+            change_annotations: None,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::{TextDocumentIdentifier, TextDocumentPositionParams};
+
+    use super::*;
+    use crate::testing::Server;
+
+    fn range(line: u32, start: u32, end: u32) -> Range {
+        Range::new(Position::new(line, start), Position::new(line, end))
+    }
+
+    #[tokio::test]
+    async fn renaming_a_heading_rewrites_its_links() {
+        let server = Server::new();
+        let a = server
+            .open("a.norg", "* Old Name\nsee {* old name}\n")
+            .await;
+        let b = server
+            .open("b.norg", "{:a:* Old Name} {:a:} {* Old Name}\n")
+            .await;
+        // indexed from the disk, not open
+        let path = server.root.write("c.norg", "\n{:a:* Old  name}\n");
+        server.backend().index.index_file(&path);
+        let c = server.uri("c.norg");
+
+        let prepare = |uri: &Url, line, character| {
+            server
+                .backend()
+                .provide_prepare_rename(uri, Position::new(line, character))
+        };
+        assert_eq!(
+            prepare(&b, 0, 8),
+            Some(PrepareRenameResponse::Range(range(0, 6, 14)))
+        );
+        assert_eq!(
+            prepare(&a, 0, 4),
+            Some(PrepareRenameResponse::Range(range(0, 2, 10)))
+        );
+        // `{* Old Name}` in `b` is broken, and file links are not renamed
+        assert_eq!(prepare(&b, 0, 28), None);
+        assert_eq!(prepare(&b, 0, 18), None);
+
+        let params = RenameParams {
+            text_document_position: TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(b.clone()),
+                Position::new(0, 8),
+            ),
+            new_name: " New ".to_owned(),
+            work_done_progress_params: Default::default(),
+        };
+        let edit = server.backend().provide_rename(params).ok().flatten();
+        let Some(DocumentChanges::Edits(changes)) = edit.and_then(|edit| edit.document_changes)
+        else {
+            panic!("no versioned edits");
+        };
+        let changes: Vec<_> = changes
+            .into_iter()
+            .map(|change| {
+                let edits: Vec<_> = change
+                    .edits
+                    .into_iter()
+                    .map(|edit| match edit {
+                        OneOf::Left(edit) => (edit.range, edit.new_text),
+                        OneOf::Right(edit) => (edit.text_edit.range, edit.text_edit.new_text),
+                    })
+                    .collect();
+                (
+                    change.text_document.uri,
+                    change.text_document.version,
+                    edits,
+                )
+            })
+            .collect();
+        let new = || "New".to_owned();
+        let mut expected = vec![
+            (
+                a,
+                Some(1),
+                vec![(range(0, 2, 10), new()), (range(1, 7, 15), new())],
+            ),
+            (b, Some(1), vec![(range(0, 6, 14), new())]),
+            (c, None, vec![(range(1, 6, 15), new())]),
+        ];
+        expected.sort_by_key(|(uri, ..)| uri.clone());
+        assert_eq!(changes, expected);
     }
 }
@@ -0,0 +1,323 @@
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use ropey::Rope;
+use tower_lsp::lsp_types::{
+    Range, SemanticToken, SemanticTokens, SemanticTokensDelta, SemanticTokensDeltaParams,
+    SemanticTokensEdit, SemanticTokensFullDeltaResult, SemanticTokensParams,
+    SemanticTokensRangeParams, SemanticTokensRangeResult, SemanticTokensResult, Url,
+};
+
+use crate::backend::Backend;
+use crate::highlight;
+use crate::structure::Structure;
+
+/// Source of result ids, unique for the life of the server so that a
+/// reopened document never matches a result of its previous life.
+static RESULT_IDS: AtomicU64 = AtomicU64::new(0);
+
+/// Tokens of one document version, as last sent to the client.
+#[derive(Debug, Clone)]
+pub(crate) struct TokenCache {
+    version: i32,
+    tokens: SemanticTokens,
+}
+
+/// Every integer of the LSP encoding belongs to a token of five.
+const TOKEN_LEN: u32 = 5;
+
+/// One edit turning `old` into `new`, replacing what lies between their
+/// common start and common end. Empty when they are equal.
+fn delta(old: &[SemanticToken], new: &[SemanticToken]) -> Vec<SemanticTokensEdit> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let deleted = old.len() - prefix - suffix;
+    let inserted = &new[prefix..new.len() - suffix];
+    if deleted == 0 && inserted.is_empty() {
+        return vec![];
+    }
+    vec![SemanticTokensEdit {
+        start: prefix as u32 * TOKEN_LEN,
+        delete_count: deleted as u32 * TOKEN_LEN,
+        data: Some(inserted.to_vec()),
+    }]
+}
+
+/// The tokens of `tokens` overlapping `range`, encoded relative to each
+/// other again.
+fn in_range(tokens: &[SemanticToken], range: Range) -> Vec<SemanticToken> {
+    let mut result = Vec::new();
+    let (mut line, mut start) = (0, 0);
+    let (mut last_line, mut last_start) = (0, 0);
+    for token in tokens {
+        line += token.delta_line;
+        start = if token.delta_line == 0 {
+            start + token.delta_start
+        } else {
+            token.delta_start
+        };
+        if line > range.end.line {
+            break;
+        }
+        let before = line < range.start.line
+            || (line == range.start.line && start + token.length <= range.start.character);
+        let after = line == range.end.line && start >= range.end.character;
+        if before || after {
+            continue;
+        }
+        let delta_line = line - last_line;
+        result.push(SemanticToken {
+            delta_line,
+            delta_start: if delta_line == 0 {
+                start - last_start
+            } else {
+                start
+            },
+            ..*token
+        });
+        (last_line, last_start) = (line, start);
+    }
+    result
+}
+
+/// Line `n` of `rope`, without its line break.
+fn line(rope: &Rope, n: u32) -> Option<String> {
+    let line: String = rope.get_line(n as usize)?.into();
+    Some(line.trim_end_matches(['\n', '\r']).to_owned())
+}
+
+/// `lines` widened to the paragraphs they are in, whose markup may run on
+/// across line breaks.
+fn paragraphs(
+    rope: &Rope,
+    structure: &Structure,
+    lines: RangeInclusive<u32>,
+) -> RangeInclusive<u32> {
+    let continues =
+        |n: u32| line(rope, n).is_some_and(|text| highlight::continues(structure, n, &text));
+    let mut first = *lines.start();
+    while first > 0 && continues(first) {
+        first -= 1;
+    }
+    let mut last = *lines.end();
+    while last < u32::MAX && continues(last + 1) {
+        last += 1;
+    }
+    first..=last
+}
+
+impl Backend {
+    /// Tokens of the lines `lines` of the open document `uri`, looking at the
+    /// rest of their paragraphs but no further.
+    fn highlight(&self, uri: &Url, lines: RangeInclusive<u32>) -> Option<Vec<SemanticToken>> {
+        let structure = self.index.get(uri)?;
+        let text: Vec<(u32, String)> = {
+            let doc = self.document_map.get(uri.as_str())?;
+            paragraphs(&doc.rope, &structure, lines)
+                .map_while(|n| Some((n, line(&doc.rope, n)?)))
+                .collect()
+        };
+        let lines = text.iter().map(|(n, line)| (*n, line.as_str()));
+        Some(highlight::tokens(lines, &structure, self.index.encoding()))
+    }
+
+    /// Tokens of the open document `uri` at its current version, computed
+    /// once per version. Returns the tokens previously sent as well, when
+    /// they were of another version.
+    async fn semantic_tokens(&self, uri: &Url) -> Option<(SemanticTokens, Option<SemanticTokens>)> {
+        if !self.settings().await.semantic_tokens.enable {
+            return None;
+        }
+        let version = self.version(uri.as_str())?;
+        let previous = self
+            .semantic_tokens
+            .get(uri.as_str())
+            .map(|cache| cache.clone());
+        if let Some(cache) = &previous
+            && cache.version == version
+        {
+            return Some((cache.tokens.clone(), None));
+        }
+
+        let data = self.highlight(uri, 0..=u32::MAX)?;
+        let id = RESULT_IDS.fetch_add(1, Ordering::Relaxed);
+        let tokens = SemanticTokens {
+            result_id: Some(id.to_string()),
+            data,
+        };
+        let cache = TokenCache {
+            version,
+            tokens: tokens.clone(),
+        };
+        self.semantic_tokens.insert(uri.to_string(), cache);
+        Some((tokens, previous.map(|cache| cache.tokens)))
+    }
+
+    pub(crate) async fn provide_semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Option<SemanticTokensResult> {
+        let (tokens, _) = self.semantic_tokens(&params.text_document.uri).await?;
+        Some(SemanticTokensResult::Tokens(tokens))
+    }
+
+    /// Edits from the tokens the client holds to the current ones, or all of
+    /// them when the client holds others than those last sent.
+    pub(crate) async fn provide_semantic_tokens_delta(
+        &self,
+        params: SemanticTokensDeltaParams,
+    ) -> Option<SemanticTokensFullDeltaResult> {
+        let (tokens, previous) = self.semantic_tokens(&params.text_document.uri).await?;
+        let held = Some(params.previous_result_id);
+        let edits = match previous {
+            Some(previous) if previous.result_id == held => delta(&previous.data, &tokens.data),
+            // still at the version the client asked about
+            None if tokens.result_id == held => vec![],
+            _ => return Some(SemanticTokensFullDeltaResult::Tokens(tokens)),
+        };
+        Some(SemanticTokensFullDeltaResult::TokensDelta(
+            SemanticTokensDelta {
+                result_id: tokens.result_id,
+                edits,
+            },
+        ))
+    }
+
+    /// Only the paragraphs of the range are looked at, unless the tokens of
+    /// the whole document are at hand already.
+    pub(crate) async fn provide_semantic_tokens_range(
+        &self,
+        params: SemanticTokensRangeParams,
+    ) -> Option<SemanticTokensRangeResult> {
+        if !self.settings().await.semantic_tokens.enable {
+            return None;
+        }
+        let uri = &params.text_document.uri;
+        let version = self.version(uri.as_str())?;
+        let cached = self
+            .semantic_tokens
+            .get(uri.as_str())
+            .filter(|cache| cache.version == version)
+            .map(|cache| cache.tokens.data.clone());
+        let tokens = match cached {
+            Some(tokens) => tokens,
+            None => self.highlight(uri, params.range.start.line..=params.range.end.line)?,
+        };
+        Some(SemanticTokensRangeResult::Tokens(SemanticTokens {
+            result_id: None,
+            data: in_range(&tokens, params.range),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::{Position, TextDocumentIdentifier};
+
+    use super::*;
+    use crate::span::PositionEncoding;
+    use crate::structure::parse;
+    use crate::testing::Server;
+
+    fn token(delta_line: u32, delta_start: u32, length: u32) -> SemanticToken {
+        SemanticToken {
+            delta_line,
+            delta_start,
+            length,
+            token_type: 0,
+            token_modifiers_bitset: 0,
+        }
+    }
+
+    #[test]
+    fn delta_replaces_the_changed_middle() {
+        let old = [token(0, 0, 3), token(1, 2, 4), token(1, 0, 1)];
+        let new = [
+            token(0, 0, 3),
+            token(1, 2, 5),
+            token(0, 6, 2),
+            token(1, 0, 1),
+        ];
+        let edits = delta(&old, &new);
+        assert_eq!(
+            edits,
+            [SemanticTokensEdit {
+                start: 5,
+                delete_count: 5,
+                data: Some(vec![token(1, 2, 5), token(0, 6, 2)]),
+            }]
+        );
+        assert!(delta(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn range_keeps_overlapping_tokens() {
+        // (0, 0..3) (2, 2..6) (2, 8..10) (5, 0..1)
+        let tokens = [
+            token(0, 0, 3),
+            token(2, 2, 4),
+            token(0, 6, 2),
+            token(3, 0, 1),
+        ];
+        let range = Range::new(Position::new(2, 7), Position::new(5, 0));
+        assert_eq!(in_range(&tokens, range), [token(2, 8, 2)]);
+        let range = Range::new(Position::new(1, 0), Position::new(5, 1));
+        assert_eq!(
+            in_range(&tokens, range),
+            [token(2, 2, 4), token(0, 6, 2), token(3, 0, 1)]
+        );
+    }
+
+    #[test]
+    fn ranges_widen_to_their_paragraphs() {
+        let text = "* Heading\nfirst\n\n- item\n  more\n  and more\nafter\n\nlast\n";
+        let rope = Rope::from_str(text);
+        let structure = parse(text, PositionEncoding::Utf16);
+        assert_eq!(paragraphs(&rope, &structure, 4..=4), 3..=6);
+        assert_eq!(paragraphs(&rope, &structure, 3..=3), 3..=6);
+        assert_eq!(paragraphs(&rope, &structure, 1..=1), 0..=1);
+        // the line before a continued one is looked at, whatever it holds
+        assert_eq!(paragraphs(&rope, &structure, 8..=8), 7..=8);
+        assert_eq!(paragraphs(&rope, &structure, 0..=u32::MAX), 0..=u32::MAX);
+    }
+
+    #[tokio::test]
+    async fn ranges_see_markup_opened_before_them() {
+        let server = Server::new();
+        let mut text = "* Heading\n".repeat(50);
+        text.push_str("some *bold\ntext*\n\nend\n");
+        let uri = server.open("a.norg", &text).await;
+        let backend = server.backend();
+        let range = Range::new(Position::new(51, 0), Position::new(52, 0));
+        let params = SemanticTokensRangeParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+            range,
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+
+        let Some(SemanticTokensRangeResult::Tokens(tokens)) =
+            backend.provide_semantic_tokens_range(params).await
+        else {
+            panic!("no tokens");
+        };
+        // the document as a whole was not tokenized
+        assert!(backend.semantic_tokens.get(uri.as_str()).is_none());
+        let bold = SemanticToken {
+            delta_line: 51,
+            delta_start: 0,
+            length: 5,
+            token_type: tokens.data.first().map_or(0, |token| token.token_type),
+            token_modifiers_bitset: 0,
+        };
+        assert_eq!(tokens.data, [bold]);
+
+        let full = backend.highlight(&uri, 0..=u32::MAX).unwrap_or_default();
+        assert_eq!(in_range(&full, range), tokens.data);
+    }
+}
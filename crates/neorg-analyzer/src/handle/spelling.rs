@@ -0,0 +1,254 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde_json::Value;
+use tower_lsp::jsonrpc::{Error, ErrorCode, Result};
+use tower_lsp::lsp_types::{
+    CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams, Command, Diagnostic,
+    DiagnosticSeverity, NumberOrString, TextEdit, Url, WorkspaceEdit,
+};
+
+use crate::backend::Backend;
+use crate::handle::ServerCommand;
+use crate::spelling::{self, Hunspell, WORDS_FILE, WordList};
+
+/// Code of unknown word diagnostics. Their `data` holds the word.
+pub(crate) const SPELLING: &str = "spelling";
+/// Suggestions offered per unknown word.
+const SUGGESTIONS: usize = 5;
+
+impl Backend {
+    pub(crate) async fn speller(&self) -> Option<Arc<Hunspell>> {
+        self.speller.read().await.clone()
+    }
+
+    /// Reads the user's word list and those of every workspace folder.
+    pub(crate) fn load_word_lists(&self) {
+        let roots = self.index.roots().into_iter();
+        let lists = roots.map(|root| root.join(WORDS_FILE));
+        for path in lists.chain(spelling::user_words()) {
+            self.load_word_list(path);
+        }
+    }
+
+    pub(crate) fn load_word_list(&self, path: PathBuf) {
+        let list = WordList::read(&path);
+        self.word_lists.insert(path, list);
+    }
+
+    /// Whether `word` is spelled correctly, or accepted anyway, in `uri`.
+    fn is_known_word(&self, speller: &Hunspell, uri: &Url, word: &str) -> bool {
+        let path = uri.to_file_path().ok();
+        let user = spelling::user_words();
+        let applies = |list: &Path| {
+            Some(list) == user.as_deref()
+                || list
+                    .parent()
+                    .zip(path.as_deref())
+                    .is_some_and(|(dir, path)| path.starts_with(dir))
+        };
+        let lower = word.to_lowercase();
+        let ignored = |words: &HashSet<String>| words.contains(word) || words.contains(&lower);
+        speller.check(word)
+            || self
+                .word_lists
+                .iter()
+                .any(|list| applies(list.key()) && list.contains(word))
+            || self
+                .ignored_words
+                .get(uri)
+                .is_some_and(|words| ignored(&words))
+    }
+
+    /// Unknown words in the prose of the open document `uri`.
+    pub(crate) async fn spelling_diagnostics(&self, uri: &Url) -> Vec<Diagnostic> {
+        let Some(speller) = self.speller().await else {
+            return vec![];
+        };
+        let Some(text) = self
+            .document_map
+            .get(uri.as_str())
+            .map(|doc| doc.rope.to_string())
+        else {
+            return vec![];
+        };
+        let Some(structure) = self.index.get(uri) else {
+            return vec![];
+        };
+
+        let mut known: HashMap<String, bool> = HashMap::new();
+        spelling::prose_words(&text, &structure, self.index.encoding())
+            .into_iter()
+            .filter(|(_, word)| {
+                !*known
+                    .entry(word.clone())
+                    .or_insert_with(|| self.is_known_word(&speller, uri, word))
+            })
+            .map(|(range, word)| Diagnostic {
+                range,
+                severity: Some(DiagnosticSeverity::INFORMATION),
+                code: Some(NumberOrString::String(SPELLING.to_owned())),
+                source: Some("neorg".to_owned()),
+                message: format!("unknown word `{word}`"),
+                data: Some(Value::String(word)),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// Suggestions for the unknown words in `params`, and commands accepting
+    /// them in the workspace or in the document.
+    pub(crate) async fn spelling_actions(
+        &self,
+        params: &CodeActionParams,
+    ) -> Vec<CodeActionOrCommand> {
+        let Some(speller) = self.speller().await else {
+            return vec![];
+        };
+        let uri = &params.text_document.uri;
+        let code = NumberOrString::String(SPELLING.to_owned());
+
+        let mut actions = Vec::new();
+        for diagnostic in &params.context.diagnostics {
+            let Some(Value::String(word)) = &diagnostic.data else {
+                continue;
+            };
+            if diagnostic.code.as_ref() != Some(&code) {
+                continue;
+            }
+            let action = |title: String| CodeAction {
+                title,
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![diagnostic.clone()]),
+                ..Default::default()
+            };
+
+            for (i, suggestion) in speller.suggest(word, SUGGESTIONS).into_iter().enumerate() {
+                let edit = TextEdit::new(diagnostic.range, suggestion.clone());
+                actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                        ..Default::default()
+                    }),
+                    is_preferred: Some(i == 0),
+                    ..action(format!("Change to `{suggestion}`"))
+                }));
+            }
+
+            let mut commands = Vec::new();
+            if self.workspace_words(uri).is_some() {
+                commands.push(Command::new(
+                    format!("Add `{word}` to the workspace dictionary"),
+                    ServerCommand::AddWord.name().to_owned(),
+                    Some(vec![
+                        Value::String(word.clone()),
+                        Value::String(uri.to_string()),
+                    ]),
+                ));
+            }
+            commands.push(Command::new(
+                format!("Add `{word}` to the user dictionary"),
+                ServerCommand::AddWord.name().to_owned(),
+                Some(vec![Value::String(word.clone())]),
+            ));
+            commands.push(Command::new(
+                format!("Ignore `{word}` in this file"),
+                ServerCommand::IgnoreWord.name().to_owned(),
+                Some(vec![
+                    Value::String(word.clone()),
+                    Value::String(uri.to_string()),
+                ]),
+            ));
+            for command in commands {
+                actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    command: Some(command.clone()),
+                    ..action(command.title)
+                }));
+            }
+        }
+        actions
+    }
+
+    /// The word list of the workspace folder `uri` is in.
+    fn workspace_words(&self, uri: &Url) -> Option<PathBuf> {
+        let path = uri.to_file_path().ok()?;
+        Some(self.index.root_of(&path)?.join(WORDS_FILE))
+    }
+
+    /// Backs [`ServerCommand::AddWord`].
+    pub(crate) async fn add_word(&self, arguments: Vec<Value>) -> Result<()> {
+        let mut arguments = arguments.into_iter();
+        let Some(Value::String(word)) = arguments.next() else {
+            return Err(Error::invalid_params("expected a word"));
+        };
+        let uri = arguments
+            .next()
+            .and_then(|uri| serde_json::from_value::<Url>(uri).ok());
+        let Some(path) = uri
+            .and_then(|uri| self.workspace_words(&uri))
+            .or_else(spelling::user_words)
+        else {
+            return Err(Error::invalid_params("no word list to add to"));
+        };
+
+        let (list, added) = (path.clone(), word.clone());
+        let written = tokio::task::spawn_blocking(move || spelling::append(&list, &added))
+            .await
+            .unwrap_or_else(|err| Err(std::io::Error::other(err)));
+        if let Err(err) = written {
+            return Err(Error {
+                code: ErrorCode::InternalError,
+                message: format!("failed to write {}: {err}", path.display()).into(),
+                data: None,
+            });
+        }
+        self.word_lists.entry(path).or_default().insert(&word);
+        self.refresh_diagnostics().await;
+        Ok(())
+    }
+
+    /// Backs [`ServerCommand::IgnoreWord`].
+    pub(crate) async fn ignore_word(&self, arguments: Vec<Value>) -> Result<()> {
+        let mut arguments = arguments.into_iter();
+        let word = arguments.next();
+        let uri = arguments
+            .next()
+            .and_then(|uri| serde_json::from_value::<Url>(uri).ok());
+        let (Some(Value::String(word)), Some(uri)) = (word, uri) else {
+            return Err(Error::invalid_params("expected a word and a document uri"));
+        };
+        self.ignored_words.entry(uri).or_default().insert(word);
+        self.refresh_diagnostics().await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Server;
+
+    #[tokio::test]
+    async fn word_commands_take_the_word_first() {
+        let server = Server::new();
+        let uri = server.open("a.norg", "wurd\n").await;
+        let backend = server.backend();
+        let arguments = vec![
+            Value::String("wurd".to_owned()),
+            Value::String(uri.to_string()),
+        ];
+
+        assert!(backend.add_word(arguments.clone()).await.is_ok());
+        let list = std::fs::read_to_string(server.root.path().join(WORDS_FILE));
+        assert_eq!(list.ok().as_deref(), Some("wurd\n"));
+
+        assert!(backend.ignore_word(arguments.clone()).await.is_ok());
+        let ignored = backend.ignored_words.get(&uri).map(|words| words.clone());
+        assert_eq!(ignored, Some(HashSet::from(["wurd".to_owned()])));
+
+        let reversed = arguments.into_iter().rev().collect();
+        let err = backend.ignore_word(reversed).await.err();
+        assert_eq!(err.map(|err| err.code), Some(ErrorCode::InvalidParams));
+    }
+}
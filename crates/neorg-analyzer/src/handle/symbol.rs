@@ -0,0 +1,173 @@
+use tower_lsp::lsp_types::{
+    DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse, Range, SymbolKind,
+};
+
+use crate::backend::Backend;
+use crate::structure::{BlockKind, Structure, TagKind};
+
+#[allow(deprecated)] // `deprecated` has to be set, even if only to `None`
+fn document_symbol(
+    name: &str,
+    detail: Option<String>,
+    kind: SymbolKind,
+    range: Range,
+    selection_range: Range,
+) -> DocumentSymbol {
+    DocumentSymbol {
+        // clients reject symbols with an empty name
+        name: if name.is_empty() { "…" } else { name }.to_owned(),
+        detail,
+        kind,
+        tags: None,
+        deprecated: None,
+        range,
+        selection_range,
+        children: None,
+    }
+}
+
+/// Headings nest their sub-headings; definitions, footnotes, tables and ranged
+/// tags go under the heading whose section contains them.
+fn outline(structure: &Structure) -> Vec<DocumentSymbol> {
+    // (heading level, symbol), leaves use `None`
+    let mut items: Vec<(Option<u8>, DocumentSymbol)> = Vec::new();
+
+    for heading in &structure.headings {
+        let symbol = document_symbol(
+            &heading.title,
+            Some("*".repeat(heading.level.into())),
+            SymbolKind::NAMESPACE,
+            heading.section,
+            heading.title_range,
+        );
+        items.push((Some(heading.level), symbol));
+    }
+    for block in &structure.blocks {
+        let kind = match block.kind {
+            BlockKind::Definition => SymbolKind::CONSTANT,
+            BlockKind::Footnote => SymbolKind::STRING,
+            BlockKind::Table => SymbolKind::ARRAY,
+            _ => continue,
+        };
+        items.push((
+            None,
+            document_symbol(&block.title, None, kind, block.range, block.marker_range),
+        ));
+    }
+    for tag in structure.tags.iter().filter(|tag| tag.kind.is_ranged()) {
+        let name = format!("{}{}", tag.kind.prefix(), tag.name);
+        let detail = (!tag.parameters.is_empty()).then(|| tag.parameters.join(" "));
+        let kind = match tag.kind {
+            TagKind::Macro => SymbolKind::FUNCTION,
+            _ => SymbolKind::STRUCT,
+        };
+        items.push((
+            None,
+            document_symbol(&name, detail, kind, tag.range, tag.name_range),
+        ));
+    }
+    items.sort_by_key(|(level, symbol)| (symbol.range.start, level.is_none()));
+
+    let mut roots = Vec::new();
+    let mut stack: Vec<(u8, DocumentSymbol)> = Vec::new();
+    let close = |stack: &mut Vec<(u8, DocumentSymbol)>, roots: &mut Vec<DocumentSymbol>| {
+        if let Some((_, symbol)) = stack.pop() {
+            match stack.last_mut() {
+                Some((_, parent)) => parent.children.get_or_insert_default().push(symbol),
+                None => roots.push(symbol),
+            }
+        }
+    };
+
+    for (level, symbol) in items {
+        let line = symbol.range.start.line;
+        while stack.last().is_some_and(|(open, parent)| {
+            parent.range.end.line < line || level.is_some_and(|level| *open >= level)
+        }) {
+            close(&mut stack, &mut roots);
+        }
+        match (level, stack.last_mut()) {
+            (Some(level), _) => stack.push((level, symbol)),
+            (None, Some((_, parent))) => parent.children.get_or_insert_default().push(symbol),
+            (None, None) => roots.push(symbol),
+        }
+    }
+    while !stack.is_empty() {
+        close(&mut stack, &mut roots);
+    }
+    roots
+}
+
+impl Backend {
+    /// The outline comes from the index rather than the document's CST,
+    /// from which the analyzer reads no heading levels yet; see
+    /// [`crate::structure`].
+    pub(crate) fn provide_document_symbols(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> Option<DocumentSymbolResponse> {
+        let structure = self.index.get(&params.text_document.uri)?;
+        Some(DocumentSymbolResponse::Nested(outline(&structure)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::span::PositionEncoding;
+    use crate::structure::parse;
+
+    /// `name (start line..end line)` of every symbol, children indented.
+    fn tree(symbols: &[DocumentSymbol], depth: usize, out: &mut Vec<String>) {
+        for symbol in symbols {
+            let Range { start, end } = symbol.range;
+            let indent = "  ".repeat(depth);
+            out.push(format!(
+                "{indent}{} ({}..{})",
+                symbol.name, start.line, end.line
+            ));
+            tree(
+                symbol.children.as_deref().unwrap_or_default(),
+                depth + 1,
+                out,
+            );
+        }
+    }
+
+    #[test]
+    fn headings_nest_their_subheadings() {
+        let text = "\
+@code
+before
+@end
+* One
+** Two
+$ term
+*** Three
+** Four
+**** Deep
+* Five
+@math
+x
+@end
+";
+        let structure = parse(text, PositionEncoding::Utf16);
+        let mut out = Vec::new();
+        tree(&outline(&structure), 0, &mut out);
+        let expected = [
+            "@code (0..2)",
+            "One (3..8)",
+            "  Two (4..6)",
+            "    term (5..5)",
+            "    Three (6..6)",
+            "  Four (7..8)",
+            "    Deep (8..8)",
+            "Five (9..13)",
+            "  @math (10..12)",
+        ];
+        assert_eq!(out, expected);
+        let one = &outline(&structure)[1];
+        assert_eq!(one.selection_range, structure.headings[0].title_range);
+        assert_eq!(one.detail.as_deref(), Some("*"));
+    }
+}
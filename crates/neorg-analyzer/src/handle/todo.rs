@@ -0,0 +1,165 @@
+use serde_json::Value;
+use tower_lsp::jsonrpc::{Error, Result};
+use tower_lsp::lsp_types::{
+    CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams, TextEdit,
+};
+
+use crate::agenda::{self, TodoFilter};
+use crate::backend::Backend;
+use crate::structure::{Todo, TodoStatus};
+
+impl Backend {
+    /// Actions setting the status of the todo items on the lines of
+    /// `params`, and marking them done along with every item nested below.
+    pub(crate) fn todo_actions(&self, params: &CodeActionParams) -> Vec<CodeActionOrCommand> {
+        let uri = &params.text_document.uri;
+        let Some(structure) = self.index.get(uri) else {
+            return vec![];
+        };
+        let lines = params.range.start.line..=params.range.end.line;
+        let todos: Vec<&Todo> = structure
+            .todos
+            .iter()
+            .filter(|todo| lines.contains(&todo.range.start.line))
+            .collect();
+        if todos.is_empty() {
+            return vec![];
+        }
+
+        let action = |title: String, todos: &[&Todo], status: TodoStatus| {
+            let edits = todos
+                .iter()
+                .filter(|todo| todo.status != status)
+                .map(|todo| TextEdit::new(todo.status_range, status.as_char().to_string()))
+                .collect();
+            CodeActionOrCommand::CodeAction(CodeAction {
+                title,
+                kind: Some(CodeActionKind::REFACTOR_REWRITE),
+                edit: Some(self.document_edit(uri.clone(), edits)),
+                ..Default::default()
+            })
+        };
+
+        let mut actions: Vec<CodeActionOrCommand> = TodoStatus::ALL
+            .into_iter()
+            .filter(|status| todos.iter().any(|todo| todo.status != *status))
+            .map(|status| action(format!("Mark as {}", status.name()), &todos, status))
+            .collect();
+
+        let mut family = todos.clone();
+        for todo in &todos {
+            for sub in structure.subtodos(todo) {
+                if !family.contains(&sub) {
+                    family.push(sub);
+                }
+            }
+        }
+        let nested = family.len() > todos.len();
+        if nested && family.iter().any(|todo| todo.status != TodoStatus::Done) {
+            let title = "Mark as done with all children".to_owned();
+            actions.push(action(title, &family, TodoStatus::Done));
+        }
+        actions
+    }
+
+    /// Every todo of the workspace matching the `TodoFilter` in `filter`,
+    /// which matches all when left out.
+    pub(crate) fn list_todos(&self, filter: Option<Value>) -> Result<Value> {
+        let filter: TodoFilter = match filter {
+            Some(Value::Null) | None => TodoFilter::default(),
+            Some(filter) => serde_json::from_value(filter)
+                .map_err(|err| Error::invalid_params(format!("invalid todo filter: {err}")))?,
+        };
+        self.filter_todos(&filter)
+    }
+
+    /// Every todo of the workspace matching `filter`.
+    pub(crate) fn filter_todos(&self, filter: &TodoFilter) -> Result<Value> {
+        let todos = agenda::todos(&self.index, filter)
+            .map_err(|date| Error::invalid_params(format!("can not read the date `{date}`")))?;
+        serde_json::to_value(todos).map_err(|_| Error::internal_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::{
+        CodeActionContext, DocumentChanges, OneOf, Position, Range, TextDocumentIdentifier,
+    };
+
+    use super::*;
+    use crate::handle::code_action::HandleCodeAction;
+    use crate::testing::Server;
+
+    #[tokio::test]
+    async fn children_are_marked_done_together() {
+        let server = Server::new();
+        let text = "- ( ) parent\n-- ( ) child\n--- (x) done\n--- ( ) grandchild\n- ( ) other\n";
+        let uri = server.open("a.norg", text).await;
+        let params = CodeActionParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+            range: Range::new(Position::new(0, 0), Position::new(0, 0)),
+            context: CodeActionContext::default(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let actions = server.backend().provide_code_action(params).await;
+        let Some(edit) =
+            actions
+                .ok()
+                .flatten()
+                .into_iter()
+                .flatten()
+                .find_map(|action| match action {
+                    CodeActionOrCommand::CodeAction(action)
+                        if action.title == "Mark as done with all children" =>
+                    {
+                        action.edit
+                    }
+                    _ => None,
+                })
+        else {
+            panic!("no action marking the children done");
+        };
+
+        let Some(DocumentChanges::Edits(documents)) = edit.document_changes else {
+            panic!("no versioned edits");
+        };
+        let [document] = documents.as_slice() else {
+            panic!("edits to {} documents", documents.len());
+        };
+        assert_eq!(document.text_document.version, Some(1));
+        let lines: Vec<(u32, &str)> = document
+            .edits
+            .iter()
+            .filter_map(|edit| match edit {
+                OneOf::Left(edit) => Some((edit.range.start.line, edit.new_text.as_str())),
+                OneOf::Right(_) => None,
+            })
+            .collect();
+        // items already done are left alone, and siblings are not children
+        assert_eq!(lines, vec![(0, "x"), (1, "x"), (3, "x")]);
+    }
+
+    #[tokio::test]
+    async fn empty_filters_list_every_todo() {
+        let server = Server::new();
+        server.open("a.norg", "- ( ) open\n- (x) done\n").await;
+        server.open("b.norg", "* (-) pending\n").await;
+
+        // what tower-lsp hands `neorg/todos` for `"params": {}`
+        let Ok(filter) = serde_json::from_value::<TodoFilter>(serde_json::json!({})) else {
+            panic!("an empty filter was rejected");
+        };
+        let todos = server.backend().todos(filter).await;
+        let Ok(Value::Array(todos)) = todos else {
+            panic!("no todos: {todos:?}");
+        };
+        assert_eq!(todos.len(), 3);
+        // the command still takes no filter at all
+        assert_eq!(
+            server.backend().list_todos(None).ok(),
+            Some(Value::Array(todos))
+        );
+    }
+}
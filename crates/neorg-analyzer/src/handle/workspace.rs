@@ -0,0 +1,185 @@
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use tower_lsp::lsp_types::{
+    DidChangeWatchedFilesParams, DidChangeWatchedFilesRegistrationOptions,
+    DidChangeWorkspaceFoldersParams, FileChangeType, FileSystemWatcher, GlobPattern,
+    InitializeParams, MessageType, OneOf, Registration, RelativePattern, Url,
+};
+
+use crate::backend::Backend;
+use crate::index::EXTENSION;
+use crate::spelling::{self, WORDS_FILE};
+
+impl Backend {
+    /// Remembers the workspace folders to index once the client is initialized.
+    pub(crate) fn add_workspace_roots(&self, params: &InitializeParams) {
+        let folders = params.workspace_folders.iter().flatten().map(|f| &f.uri);
+        #[allow(deprecated)] // only used when the client sends no folders
+        let uris = folders.chain(
+            params
+                .root_uri
+                .iter()
+                .filter(|_| params.workspace_folders.is_none()),
+        );
+        for uri in uris {
+            if let Ok(path) = uri.to_file_path() {
+                self.index.add_root(path);
+            }
+        }
+    }
+
+    /// Indexes every workspace folder in the background.
+    pub(crate) fn index_workspace(&self) {
+        let index = Arc::clone(&self.index);
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            let indexed = tokio::task::spawn_blocking(move || index.index_roots()).await;
+            if let Ok(count) = indexed {
+                client
+                    .log_message(MessageType::INFO, format!("indexed {count} norg files"))
+                    .await;
+            }
+        });
+    }
+
+    /// Asks the client to report changes to `.norg` files and word lists.
+    pub(crate) async fn watch_files(&self) {
+        let mut patterns = vec![
+            GlobPattern::String(format!("**/*.{EXTENSION}")),
+            GlobPattern::String(format!("**/{WORDS_FILE}")),
+        ];
+        // the user's list is outside the workspace
+        if self.watch_relative.load(Ordering::Relaxed)
+            && let Some(dir) = spelling::user_words()
+                .as_deref()
+                .and_then(|path| path.parent())
+            && let Ok(base) = Url::from_directory_path(dir)
+        {
+            patterns.push(GlobPattern::Relative(RelativePattern {
+                base_uri: OneOf::Right(base),
+                pattern: WORDS_FILE.to_owned(),
+            }));
+        }
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: patterns
+                .into_iter()
+                .map(|glob_pattern| FileSystemWatcher {
+                    glob_pattern,
+                    kind: None,
+                })
+                .collect(),
+        };
+        let registration = Registration {
+            id: "neorg-watched-files".to_owned(),
+            method: "workspace/didChangeWatchedFiles".to_owned(),
+            register_options: serde_json::to_value(options).ok(),
+        };
+        if let Err(err) = self.client.register_capability(vec![registration]).await {
+            eprintln!("failed to watch files: {err}");
+        }
+    }
+
+    /// Reindexes the changed files and rereads the changed word lists off the
+    /// async runtime.
+    pub(crate) async fn on_watched_files_changed(&self, params: DidChangeWatchedFilesParams) {
+        let backend = self.clone();
+        let reread = tokio::task::spawn_blocking(move || {
+            for event in params.changes {
+                let Ok(path) = event.uri.to_file_path() else {
+                    continue;
+                };
+                if path.file_name().is_some_and(|name| name == WORDS_FILE) {
+                    if event.typ == FileChangeType::DELETED {
+                        backend.word_lists.remove(&path);
+                    } else {
+                        backend.load_word_list(path);
+                    }
+                    continue;
+                }
+                if event.typ == FileChangeType::DELETED {
+                    backend.index.remove(&event.uri);
+                } else if backend.index.is_indexed_path(&path) {
+                    backend.index.index_file(&path);
+                }
+            }
+        });
+        _ = reread.await;
+    }
+
+    pub(crate) fn on_workspace_folders_changed(&self, params: DidChangeWorkspaceFoldersParams) {
+        for folder in params.event.removed {
+            if let Ok(path) = folder.uri.to_file_path() {
+                self.word_lists.retain(|list, _| !list.starts_with(&path));
+                self.index.remove_root(&path);
+            }
+        }
+
+        let added: Vec<_> = params
+            .event
+            .added
+            .iter()
+            .filter_map(|folder| folder.uri.to_file_path().ok())
+            .collect();
+        for path in &added {
+            self.index.add_root(path.clone());
+            self.load_word_list(path.join(WORDS_FILE));
+        }
+
+        let index = Arc::clone(&self.index);
+        tokio::task::spawn_blocking(move || {
+            for path in added {
+                index.index_root(&path);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::FileEvent;
+
+    use super::*;
+    use crate::testing::Server;
+
+    fn changed(path: &std::path::Path, typ: FileChangeType) -> DidChangeWatchedFilesParams {
+        let Ok(uri) = Url::from_file_path(path) else {
+            panic!("not absolute: {}", path.display());
+        };
+        DidChangeWatchedFilesParams {
+            changes: vec![FileEvent { uri, typ }],
+        }
+    }
+
+    #[tokio::test]
+    async fn watched_files_are_reread() {
+        let server = Server::new();
+        let backend = server.backend();
+        let note = server.root.write("note.norg", "* Heading\n");
+        let words = server.root.write(WORDS_FILE, "neorg\n");
+
+        backend
+            .on_watched_files_changed(changed(&note, FileChangeType::CREATED))
+            .await;
+        backend
+            .on_watched_files_changed(changed(&words, FileChangeType::CREATED))
+            .await;
+        assert!(backend.index.get(&server.uri("note.norg")).is_some());
+        assert!(
+            backend
+                .word_lists
+                .get(&words)
+                .is_some_and(|list| list.contains("neorg"))
+        );
+
+        std::fs::remove_file(&note).ok();
+        backend
+            .on_watched_files_changed(changed(&note, FileChangeType::DELETED))
+            .await;
+        backend
+            .on_watched_files_changed(changed(&words, FileChangeType::DELETED))
+            .await;
+        assert!(backend.index.get(&server.uri("note.norg")).is_none());
+        assert!(backend.word_lists.get(&words).is_none());
+    }
+}
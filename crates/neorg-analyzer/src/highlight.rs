@@ -0,0 +1,594 @@
+//! Semantic tokens for Neorg documents, computed line by line, with inline
+//! markup running on across the lines of a paragraph.
+//!
+//! Every token type is prefixed with `neorg.`, so that Neovim highlights them
+//! as `@lsp.type.neorg.<type>`. Headings get a type per level, and headings,
+//! list items and quotes a `level<n>` modifier. Todo extensions carry their
+//! status as a modifier, cancelled ones `deprecated`. Tokens never overlap:
+//! markup nested in a heading title or in other markup splits the tokens
+//! around it.
+
+use tower_lsp::lsp_types::{
+    SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokensLegend,
+};
+
+use crate::export::closing;
+use crate::span::PositionEncoding;
+use crate::structure::{Structure, TagKind, TodoStatus, detached_prefix, tag_line};
+
+/// Token types, in legend order.
+const TYPES: [&str; 26] = [
+    "neorg.heading1",
+    "neorg.heading2",
+    "neorg.heading3",
+    "neorg.heading4",
+    "neorg.heading5",
+    "neorg.heading6",
+    "neorg.unorderedList",
+    "neorg.orderedList",
+    "neorg.quote",
+    "neorg.definition",
+    "neorg.footnote",
+    "neorg.bold",
+    "neorg.italic",
+    "neorg.underline",
+    "neorg.strikethrough",
+    "neorg.spoiler",
+    "neorg.superscript",
+    "neorg.subscript",
+    "neorg.inlineCode",
+    "neorg.inlineMath",
+    "neorg.verbatim",
+    "neorg.link",
+    "neorg.linkTarget",
+    "neorg.todo",
+    "neorg.tag",
+    "neorg.delimiter",
+];
+
+/// Token modifiers, in legend order.
+const MODIFIERS: [&str; 16] = [
+    "level1",
+    "level2",
+    "level3",
+    "level4",
+    "level5",
+    "level6",
+    "level7",
+    "level8",
+    "undone",
+    "done",
+    "pending",
+    "onHold",
+    "deprecated",
+    "urgent",
+    "uncertain",
+    "recurring",
+];
+
+pub(crate) fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TYPES.into_iter().map(SemanticTokenType::new).collect(),
+        token_modifiers: MODIFIERS
+            .into_iter()
+            .map(SemanticTokenModifier::new)
+            .collect(),
+    }
+}
+
+/// Index of `name` in [`TYPES`].
+fn token_type(name: &str) -> u32 {
+    TYPES
+        .iter()
+        .position(|ty| ty.strip_prefix("neorg.") == Some(name))
+        .unwrap_or_default() as u32
+}
+
+/// Bit of `name` in [`MODIFIERS`].
+fn modifier(name: &str) -> u32 {
+    MODIFIERS
+        .iter()
+        .position(|modifier| *modifier == name)
+        .map_or(0, |i| 1 << i)
+}
+
+fn level(level: usize) -> u32 {
+    modifier(&format!("level{}", level.clamp(1, 8)))
+}
+
+fn status(status: TodoStatus) -> u32 {
+    modifier(match status {
+        TodoStatus::Undone => "undone",
+        TodoStatus::Done => "done",
+        TodoStatus::Pending => "pending",
+        TodoStatus::OnHold => "onHold",
+        TodoStatus::Cancelled => "deprecated",
+        TodoStatus::Urgent => "urgent",
+        TodoStatus::Uncertain => "uncertain",
+        TodoStatus::Recurring => "recurring",
+    })
+}
+
+/// Type and modifiers of a token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Kind(u32, u32);
+
+impl Kind {
+    fn of(name: &str) -> Self {
+        Self(token_type(name), 0)
+    }
+}
+
+/// A token of one line, between two char indices.
+#[derive(Debug, Clone, Copy)]
+struct Span {
+    start: usize,
+    end: usize,
+    kind: Kind,
+}
+
+/// Tokens of one line.
+struct Line {
+    chars: Vec<char>,
+    spans: Vec<Span>,
+}
+
+impl Line {
+    fn push(&mut self, start: usize, end: usize, kind: Kind) {
+        if start >= end {
+            return;
+        }
+        match self.spans.last_mut() {
+            Some(last) if last.end == start && last.kind == kind => last.end = end,
+            _ => self.spans.push(Span { start, end, kind }),
+        }
+    }
+
+    fn find(&self, from: usize, c: char) -> Option<usize> {
+        (from..self.chars.len()).find(|i| self.chars[*i] == c)
+    }
+
+    /// Markup between `from` and `to`, with what is not marked up given
+    /// `base`, if any.
+    fn inline(&mut self, from: usize, to: usize, base: Option<Kind>) {
+        let mut plain = from;
+        let mut i = from;
+        while i < to {
+            if self.chars[i] == '\\' {
+                i += 2;
+                continue;
+            }
+            let Some(markup) = self.markup(i, to) else {
+                i += 1;
+                continue;
+            };
+            if let Some(base) = base {
+                self.push(plain, i, base);
+            }
+            let end = match markup {
+                Markup::Flat(pieces) => {
+                    let mut start = i;
+                    let mut end = i;
+                    for (piece_end, kind) in pieces {
+                        self.push(start, piece_end + 1, kind);
+                        start = piece_end + 1;
+                        end = piece_end;
+                    }
+                    end
+                }
+                Markup::Nested(end, kind) => {
+                    self.push(i, i + 1, kind);
+                    self.inline(i + 1, end, Some(kind));
+                    self.push(end, end + 1, kind);
+                    end
+                }
+            };
+            i = end + 1;
+            plain = i;
+        }
+        if let Some(base) = base {
+            self.push(plain, to, base);
+        }
+    }
+
+    /// The markup opening at `i` and closing before `to`.
+    fn markup(&self, i: usize, to: usize) -> Option<Markup> {
+        let c = self.chars[i];
+        let within = |end: usize| (end < to).then_some(end);
+        let flat = |pieces: &[(usize, &str)]| {
+            let pieces = pieces.iter().map(|(end, name)| (*end, Kind::of(name)));
+            Some(Markup::Flat(pieces.collect()))
+        };
+        let name = match c {
+            '`' => return flat(&[(within(self.find(i + 1, c)?)?, "inlineCode")]),
+            '$' => return flat(&[(within(closing(&self.chars, i)?)?, "inlineMath")]),
+            '{' => {
+                let close = within(self.find(i + 1, '}')?)?;
+                // `{target}[description]`
+                let description = (self.chars.get(close + 1) == Some(&'['))
+                    .then(|| self.find(close + 2, ']').and_then(within))
+                    .flatten();
+                return match description {
+                    Some(end) => flat(&[(close, "linkTarget"), (end, "link")]),
+                    None => flat(&[(close, "linkTarget")]),
+                };
+            }
+            '[' => {
+                // `[description]{target}`
+                let close = within(self.find(i + 1, ']')?)?;
+                if self.chars.get(close + 1) != Some(&'{') {
+                    return None;
+                }
+                let target = within(self.find(close + 2, '}')?)?;
+                return flat(&[(close, "link"), (target, "linkTarget")]);
+            }
+            '*' => "bold",
+            '/' => "italic",
+            '_' => "underline",
+            '-' => "strikethrough",
+            '!' => "spoiler",
+            '^' => "superscript",
+            ',' => "subscript",
+            _ => return None,
+        };
+        let end = within(closing(&self.chars, i)?)?;
+        Some(Markup::Nested(end, Kind::of(name)))
+    }
+}
+
+/// Markup found in a line.
+enum Markup {
+    /// Consecutive tokens, by the index of their last char.
+    Flat(Vec<(usize, Kind)>),
+    /// Markup holding other markup, up to the index of its closing char.
+    Nested(usize, Kind),
+}
+
+/// The todo extension starting at `i`, if any: its closing index and status.
+fn todo(chars: &[char], i: usize) -> Option<(usize, TodoStatus)> {
+    if chars.get(i) != Some(&'(') {
+        return None;
+    }
+    let close = (i + 1..chars.len()).find(|j| chars[*j] == ')')?;
+    let inner: String = chars[i + 1..close].iter().collect();
+    let status = inner.split('|').find_map(|item| {
+        let mut item = item.chars();
+        let c = item.next()?;
+        item.as_str()
+            .trim()
+            .is_empty()
+            .then(|| TodoStatus::from_char(c))
+            .flatten()
+    })?;
+    Some((close, status))
+}
+
+/// How a line takes part in the paragraph around it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flow {
+    /// A heading or definition title, whose markup ends with the line.
+    Alone,
+    /// The first line of a list item or quote, which the next plain lines
+    /// continue.
+    Opens,
+    /// Plain text, continuing the paragraph of the line before if any.
+    Continues,
+}
+
+/// The part of a line holding inline markup, still to be tokenized.
+#[derive(Debug, Clone, Copy)]
+struct Inline {
+    from: usize,
+    to: usize,
+    base: Option<Kind>,
+    flow: Flow,
+}
+
+/// Tokens of line `n`, whose text is `text`, as char index spans, but for
+/// its inline markup, which may run on to the next lines.
+fn line_spans(structure: &Structure, n: u32, text: &str) -> (Line, Option<Inline>) {
+    let mut line = Line {
+        chars: text.chars().collect(),
+        spans: Vec::new(),
+    };
+    let len = line.chars.len();
+    let indent = line.chars.iter().take_while(|c| c.is_whitespace()).count();
+    let trimmed_end = len
+        - line
+            .chars
+            .iter()
+            .rev()
+            .take_while(|c| c.is_whitespace())
+            .count();
+
+    // the body and closing line of verbatim ranged tags
+    let verbatim = structure.tags.iter().find(|tag| {
+        matches!(tag.kind, TagKind::Ranged | TagKind::Macro)
+            && tag.range.start.line < n
+            && n <= tag.range.end.line
+    });
+    if let Some(tag) = verbatim {
+        let end = n == tag.range.end.line && text.trim_start().starts_with(tag.kind.prefix());
+        let kind = if end { "tag" } else { "verbatim" };
+        line.push(indent, trimmed_end, Kind::of(kind));
+        return (line, None);
+    }
+
+    if let Some((_, _, rest)) = tag_line(text) {
+        let name = rest.split_whitespace().next().unwrap_or_default();
+        line.push(indent, indent + 1 + name.chars().count(), Kind::of("tag"));
+        return (line, None);
+    }
+
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return (line, None);
+    }
+    if trimmed.len() >= 3 && trimmed.chars().all(|c| matches!(c, '_' | '=' | '-')) {
+        line.push(indent, trimmed_end, Kind::of("delimiter"));
+        return (line, None);
+    }
+
+    let plain = Inline {
+        from: 0,
+        to: len,
+        base: None,
+        flow: Flow::Continues,
+    };
+    let Some(prefix) = detached_prefix(text) else {
+        return (line, Some(plain));
+    };
+    let marker_end = indent + prefix.level;
+    let kind = match prefix.char {
+        '*' => Kind(
+            token_type(&format!("heading{}", prefix.level.min(6))),
+            level(prefix.level),
+        ),
+        '-' => Kind(token_type("unorderedList"), level(prefix.level)),
+        '~' => Kind(token_type("orderedList"), level(prefix.level)),
+        '>' => Kind(token_type("quote"), level(prefix.level)),
+        '$' => Kind::of("definition"),
+        '^' => Kind::of("footnote"),
+        _ => return (line, Some(plain)),
+    };
+    line.push(indent, marker_end, kind);
+    let mut title = marker_end + 1;
+    if matches!(prefix.char, '*' | '-' | '~')
+        && let Some((close, todo_status)) = todo(&line.chars, title)
+    {
+        line.push(
+            title,
+            close + 1,
+            Kind(token_type("todo"), status(todo_status)),
+        );
+        title = close + 1;
+    }
+    // heading titles are marked up as headings as well
+    let base = (prefix.char == '*').then_some(kind);
+    let title_start = title
+        + line.chars[title.min(len)..]
+            .iter()
+            .take_while(|c| c.is_whitespace())
+            .count();
+    let inline = Inline {
+        from: title_start.min(trimmed_end),
+        to: trimmed_end,
+        base,
+        flow: match prefix.char {
+            '-' | '~' | '>' => Flow::Opens,
+            _ => Flow::Alone,
+        },
+    };
+    (line, Some(inline))
+}
+
+/// Whether line `n` continues the paragraph of the line before it, so that
+/// markup may run on to it.
+pub(crate) fn continues(structure: &Structure, n: u32, text: &str) -> bool {
+    let (_, inline) = line_spans(structure, n, text);
+    inline.is_some_and(|inline| inline.flow == Flow::Continues)
+}
+
+/// A line waiting for the end of its paragraph.
+struct Pending<'a> {
+    n: u32,
+    text: &'a str,
+    line: Line,
+    inline: Inline,
+}
+
+/// Tokenizes the markup of the lines of one paragraph, as if they were one
+/// line joined by line breaks.
+fn paragraph_spans(paragraph: &mut [Pending<'_>]) {
+    if let [pending] = paragraph {
+        let Inline { from, to, base, .. } = pending.inline;
+        pending.line.inline(from, to, base);
+        return;
+    }
+    let (Some(first), Some(last)) = (paragraph.first(), paragraph.last()) else {
+        return;
+    };
+    let mut joined = Line {
+        chars: Vec::new(),
+        spans: Vec::new(),
+    };
+    let mut offsets = Vec::new();
+    for pending in paragraph.iter() {
+        offsets.push(joined.chars.len());
+        joined.chars.extend(&pending.line.chars);
+        joined.chars.push('\n');
+    }
+    let (from, to) = (
+        first.inline.from,
+        offsets[offsets.len() - 1] + last.inline.to,
+    );
+    joined.inline(from, to, None);
+
+    for span in joined.spans {
+        for (pending, offset) in paragraph.iter_mut().zip(&offsets) {
+            let end = offset + pending.line.chars.len();
+            if span.start < end && *offset < span.end {
+                let start = span.start.max(*offset) - offset;
+                pending
+                    .line
+                    .push(start, span.end.min(end) - offset, span.kind);
+            }
+        }
+    }
+}
+
+/// Encodes the spans of lines relative to each other.
+struct Encoder {
+    encoding: PositionEncoding,
+    tokens: Vec<SemanticToken>,
+    last_line: u32,
+    last_start: u32,
+}
+
+impl Encoder {
+    fn push(&mut self, n: u32, text: &str, line: Line) {
+        // byte offset of every char index, and of the end
+        let bytes: Vec<usize> = text
+            .char_indices()
+            .map(|(byte, _)| byte)
+            .chain(std::iter::once(text.len()))
+            .collect();
+        let column = |i: usize| self.encoding.column(text, bytes[i.min(bytes.len() - 1)]);
+        for span in line.spans {
+            let (start, end) = (column(span.start), column(span.end));
+            if end <= start {
+                continue;
+            }
+            let delta_line = n - self.last_line;
+            self.tokens.push(SemanticToken {
+                delta_line,
+                delta_start: if delta_line == 0 {
+                    start - self.last_start
+                } else {
+                    start
+                },
+                length: end - start,
+                token_type: span.kind.0,
+                token_modifiers_bitset: span.kind.1,
+            });
+            (self.last_line, self.last_start) = (n, start);
+        }
+    }
+
+    /// Tokenizes and encodes the markup of `paragraph`, and empties it.
+    fn flush(&mut self, paragraph: &mut Vec<Pending<'_>>) {
+        paragraph_spans(paragraph);
+        for pending in paragraph.drain(..) {
+            self.push(pending.n, pending.text, pending.line);
+        }
+    }
+}
+
+/// Semantic tokens of `lines`, numbered and without line breaks, encoded
+/// relative to each other. Markup runs on across the lines of a paragraph,
+/// so `lines` should hold whole paragraphs.
+pub(crate) fn tokens<'a>(
+    lines: impl Iterator<Item = (u32, &'a str)>,
+    structure: &Structure,
+    encoding: PositionEncoding,
+) -> Vec<SemanticToken> {
+    let mut encoder = Encoder {
+        encoding,
+        tokens: Vec::new(),
+        last_line: 0,
+        last_start: 0,
+    };
+    let mut paragraph: Vec<Pending<'_>> = Vec::new();
+    for (n, text) in lines {
+        let (line, inline) = line_spans(structure, n, text);
+        let Some(inline) = inline else {
+            encoder.flush(&mut paragraph);
+            encoder.push(n, text, line);
+            continue;
+        };
+        let open = paragraph
+            .first()
+            .is_some_and(|first| first.inline.flow != Flow::Alone);
+        if !(open && inline.flow == Flow::Continues) {
+            encoder.flush(&mut paragraph);
+        }
+        paragraph.push(Pending {
+            n,
+            text,
+            line,
+            inline,
+        });
+    }
+    encoder.flush(&mut paragraph);
+    encoder.tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structure::parse;
+
+    /// Tokens of `text` as line, column, length, type and modifiers.
+    fn decoded(text: &str) -> Vec<(u32, u32, u32, &'static str, Vec<&'static str>)> {
+        let structure = parse(text, PositionEncoding::Utf16);
+        let lines = text.lines().enumerate().map(|(n, line)| (n as u32, line));
+        let (mut line, mut start) = (0, 0);
+        tokens(lines, &structure, PositionEncoding::Utf16)
+            .into_iter()
+            .map(|token| {
+                line += token.delta_line;
+                start = if token.delta_line == 0 {
+                    start + token.delta_start
+                } else {
+                    token.delta_start
+                };
+                let modifiers = (0..MODIFIERS.len())
+                    .filter(|i| token.token_modifiers_bitset & (1 << i) != 0)
+                    .map(|i| MODIFIERS[i])
+                    .collect();
+                let ty = TYPES[token.token_type as usize].trim_start_matches("neorg.");
+                (line, start, token.length, ty, modifiers)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lines_are_tokenized() {
+        let text = "** (_) Héading *bold*\n- see {* a}[b] and `c`\n@code\n*x*\n@end\n";
+        assert_eq!(
+            decoded(text),
+            [
+                (0, 0, 2, "heading2", vec!["level2"]),
+                (0, 3, 3, "todo", vec!["deprecated"]),
+                (0, 7, 8, "heading2", vec!["level2"]),
+                (0, 15, 6, "bold", vec![]),
+                (1, 0, 1, "unorderedList", vec!["level1"]),
+                (1, 6, 5, "linkTarget", vec![]),
+                (1, 11, 3, "link", vec![]),
+                (1, 19, 3, "inlineCode", vec![]),
+                (2, 0, 5, "tag", vec![]),
+                (3, 0, 3, "verbatim", vec![]),
+                (4, 0, 4, "tag", vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn markup_runs_on_across_a_paragraph() {
+        let text =
+            "some *bold\ntext* and\n- an /italic\n  item/\n\n*not\n\nbold*\ntext `a\n* b` c\n";
+        assert_eq!(
+            decoded(text),
+            [
+                (0, 5, 5, "bold", vec![]),
+                (1, 0, 5, "bold", vec![]),
+                (2, 0, 1, "unorderedList", vec!["level1"]),
+                (2, 5, 7, "italic", vec![]),
+                (3, 0, 7, "italic", vec![]),
+                // neither blank lines nor headings are run on to
+                (9, 0, 1, "heading1", vec!["level1"]),
+                (9, 2, 4, "heading1", vec!["level1"]),
+            ]
+        );
+    }
+}
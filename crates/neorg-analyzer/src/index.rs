@@ -0,0 +1,258 @@
+//! Workspace wide index of `.norg` files.
+//!
+//! Every file below a workspace folder is scanned into a [`Structure`] when the
+//! server starts, and kept up to date from watched file events. Open
+//! documents are indexed from their buffer instead of the disk, so the index
+//! always reflects what the user sees.
+
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+use dashmap::{DashMap, DashSet};
+use ropey::Rope;
+use tower_lsp::lsp_types::Url;
+
+use crate::span::PositionEncoding;
+use crate::structure::{self, Sections, Splice, Structure};
+
+pub(crate) const EXTENSION: &str = "norg";
+
+#[derive(Debug, Default)]
+pub(crate) struct WorkspaceIndex {
+    roots: DashSet<PathBuf>,
+    /// Documents open in the editor, which the disk must not overwrite, with
+    /// their sections for incremental updates.
+    open: DashMap<Url, Sections>,
+    files: DashMap<Url, Arc<Structure>>,
+    /// Encoding of the columns of every position in the index.
+    encoding: OnceLock<PositionEncoding>,
+}
+
+impl WorkspaceIndex {
+    /// Set once the client negotiated it, before anything is indexed.
+    pub(crate) fn set_encoding(&self, encoding: PositionEncoding) {
+        _ = self.encoding.set(encoding);
+    }
+
+    pub(crate) fn encoding(&self) -> PositionEncoding {
+        self.encoding.get().copied().unwrap_or_default()
+    }
+
+    pub(crate) fn add_root(&self, root: PathBuf) {
+        self.roots.insert(root);
+    }
+
+    pub(crate) fn roots(&self) -> Vec<PathBuf> {
+        self.roots.iter().map(|root| root.clone()).collect()
+    }
+
+    /// The workspace folder `path` is in.
+    pub(crate) fn root_of(&self, path: &Path) -> Option<PathBuf> {
+        self.roots
+            .iter()
+            .find(|root| path.starts_with(root.key()))
+            .map(|root| root.clone())
+    }
+
+    /// Forgets `root` and every file below it that is not open.
+    pub(crate) fn remove_root(&self, root: &Path) {
+        self.roots.remove(root);
+        self.files.retain(|uri, _| {
+            self.open.contains_key(uri)
+                || uri
+                    .to_file_path()
+                    .map_or(true, |path| !path.starts_with(root))
+        });
+    }
+
+    /// Scans every `.norg` file below `root`. Returns the number of files indexed.
+    pub(crate) fn index_root(&self, root: &Path) -> usize {
+        let mut count = 0;
+        let mut dirs = vec![root.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let Ok(file_type) = entry.file_type() else {
+                    continue;
+                };
+                let hidden = entry.file_name().to_string_lossy().starts_with('.');
+                if file_type.is_dir() && !hidden {
+                    dirs.push(path);
+                } else if file_type.is_file() && is_norg(&path) && self.index_file(&path) {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    /// Scans every root. Returns the number of files indexed.
+    pub(crate) fn index_roots(&self) -> usize {
+        let roots: Vec<PathBuf> = self.roots.iter().map(|root| root.clone()).collect();
+        roots.iter().map(|root| self.index_root(root)).sum()
+    }
+
+    /// (Re)indexes `path` from the disk, unless it is open.
+    pub(crate) fn index_file(&self, path: &Path) -> bool {
+        let Ok(uri) = Url::from_file_path(path) else {
+            return false;
+        };
+        if self.open.contains_key(&uri) {
+            return true;
+        }
+        match std::fs::read_to_string(path) {
+            Ok(text) => {
+                self.files
+                    .insert(uri, Arc::new(structure::parse(&text, self.encoding())));
+                true
+            }
+            Err(_) => {
+                self.files.remove(&uri);
+                false
+            }
+        }
+    }
+
+    /// Indexes an open document from its buffer.
+    pub(crate) fn update(&self, uri: Url, text: &str) {
+        let sections = Sections::parse(text, self.encoding());
+        self.files
+            .insert(uri.clone(), Arc::new(sections.structure()));
+        self.open.insert(uri, sections);
+    }
+
+    /// Reindexes an open document after lines `first..=old_last` were replaced
+    /// by lines `first..=new_last` of `rope`, rescanning only the sections
+    /// around them. Returns the sections it replaced.
+    pub(crate) fn edit(
+        &self,
+        uri: Url,
+        rope: &Rope,
+        first: usize,
+        old_last: usize,
+        new_last: usize,
+    ) -> Splice {
+        let Some(mut sections) = self.open.get_mut(&uri) else {
+            self.update(uri.clone(), &rope.to_string());
+            // replacing whatever sections the caller kept
+            return Splice {
+                at: 0,
+                removed: usize::MAX,
+                lens: self.section_lens(&uri),
+            };
+        };
+        let splice = sections.edit(rope, first, old_last, new_last);
+        let structure = Arc::new(sections.structure());
+        drop(sections);
+        self.files.insert(uri, structure);
+        splice
+    }
+
+    /// Length in lines of every top-level section of the open document `uri`.
+    pub(crate) fn section_lens(&self, uri: &Url) -> Vec<usize> {
+        self.open
+            .get(uri)
+            .map(|sections| sections.lens())
+            .unwrap_or_default()
+    }
+
+    /// Falls back to the disk once a document is closed.
+    pub(crate) fn close(&self, uri: &Url) {
+        self.open.remove(uri);
+        match uri.to_file_path() {
+            Ok(path) if self.is_indexed_path(&path) => _ = self.index_file(&path),
+            _ => _ = self.files.remove(uri),
+        }
+    }
+
+    pub(crate) fn remove(&self, uri: &Url) {
+        if !self.open.contains_key(uri) {
+            self.files.remove(uri);
+        }
+    }
+
+    /// Forgets the files that are neither open nor on the disk anymore.
+    pub(crate) fn prune(&self) {
+        self.files.retain(|uri, _| {
+            self.open.contains_key(uri) || uri.to_file_path().is_ok_and(|path| path.is_file())
+        });
+    }
+
+    pub(crate) fn get(&self, uri: &Url) -> Option<Arc<Structure>> {
+        self.files.get(uri).map(|file| Arc::clone(file.value()))
+    }
+
+    pub(crate) fn files(&self) -> Vec<(Url, Arc<Structure>)> {
+        self.files
+            .iter()
+            .map(|file| (file.key().clone(), Arc::clone(file.value())))
+            .collect()
+    }
+
+    /// Resolves the `path` of a `{:path:}` link found in `from`.
+    pub(crate) fn resolve_file(&self, from: &Url, file: &str) -> Option<Url> {
+        let mut path = self.resolve_path(from, file)?.into_os_string();
+        path.push(".");
+        path.push(EXTENSION);
+        Url::from_file_path(path).ok()
+    }
+
+    /// The `{:path:}` through which `from` links to `to`, the inverse of
+    /// [`Self::resolve_file`]. Files outside the directory of `from` are
+    /// written relative to their workspace folder.
+    pub(crate) fn link_path(&self, from: &Url, to: &Url) -> Option<String> {
+        let from = from.to_file_path().ok()?;
+        let to = to.to_file_path().ok()?.with_extension("");
+        if let Ok(relative) = to.strip_prefix(from.parent()?) {
+            return Some(relative.to_string_lossy().into_owned());
+        }
+        let root = self.roots.iter().find(|root| to.starts_with(root.key()))?;
+        let relative = to.strip_prefix(root.key()).ok()?;
+        Some(format!("$/{}", relative.to_string_lossy()))
+    }
+
+    /// Resolves a path written in `from`.
+    ///
+    /// `$/` is relative to the workspace folder of `from`, `~/` to the home
+    /// directory, and anything else but absolute paths to the directory of `from`.
+    pub(crate) fn resolve_path(&self, from: &Url, file: &str) -> Option<PathBuf> {
+        let from = from.to_file_path().ok()?;
+        let path = if let Some(rest) = file.strip_prefix("$/") {
+            let root = self
+                .roots
+                .iter()
+                .find(|root| from.starts_with(root.key()))
+                .map(|root| root.clone())
+                .or_else(|| self.roots.iter().next().map(|root| root.clone()))?;
+            root.join(rest)
+        } else if let Some(rest) = file.strip_prefix("~/") {
+            dirs::home_dir()?.join(rest)
+        } else if Path::new(file).is_absolute() {
+            PathBuf::from(file)
+        } else {
+            from.parent()?.join(file)
+        };
+
+        let mut normalized = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::ParentDir => _ = normalized.pop(),
+                Component::CurDir => {}
+                component => normalized.push(component),
+            }
+        }
+        Some(normalized)
+    }
+
+    /// Whether `path` is a `.norg` file below one of the roots.
+    pub(crate) fn is_indexed_path(&self, path: &Path) -> bool {
+        is_norg(path) && self.roots.iter().any(|root| path.starts_with(root.key()))
+    }
+}
+
+pub(crate) fn is_norg(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == EXTENSION)
+}
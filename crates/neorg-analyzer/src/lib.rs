@@ -1,6 +1,20 @@
+mod agenda;
 pub mod backend;
+mod config;
+mod dictionary;
+mod export;
+mod format;
 mod handle;
+mod highlight;
+mod index;
+mod link;
 pub mod span;
+mod spelling;
+mod structure;
+mod syntax;
+#[cfg(test)]
+mod testing;
+pub use agenda::TodoFilter;
 pub use neorg_syntax as neorg;
 pub mod types;
 
@@ -0,0 +1,378 @@
+//! Resolving Neorg links against the workspace index.
+//!
+//! A link and the element it points at are both described by a [`Target`]:
+//! the file, the kind of element and its normalised title. Headings, anchors,
+//! footnotes and definitions declare targets; links refer to them.
+
+use tower_lsp::lsp_types::{Location, Position, Range, TextEdit, Url};
+
+use crate::index::WorkspaceIndex;
+use crate::structure::{BlockKind, Link, LinkKind, Structure, normalize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TargetKind {
+    Heading(u8),
+    Anchor,
+    Footnote,
+    Definition,
+    /// `{# name}`, matches any titled element.
+    Generic,
+    /// The file itself.
+    File,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Target {
+    pub(crate) uri: Url,
+    pub(crate) kind: TargetKind,
+    /// Normalised title, empty for files.
+    pub(crate) name: String,
+}
+
+impl Target {
+    /// Whether a link to `self` points at the declaration `declaration`.
+    pub(crate) fn matches(&self, declaration: &Self) -> bool {
+        if self.uri != declaration.uri || self.name != declaration.name {
+            return false;
+        }
+        match (self.kind, declaration.kind) {
+            (TargetKind::Generic, kind) => kind != TargetKind::File,
+            // `[name]` falls back to footnotes when there is no anchor of that name
+            (TargetKind::Anchor, TargetKind::Footnote) => true,
+            (kind, other) => kind == other,
+        }
+    }
+}
+
+pub(crate) fn contains(range: Range, position: Position) -> bool {
+    range.start <= position && position <= range.end
+}
+
+/// What `link`, found in `from`, points at. External links have no target.
+pub(crate) fn link_target(index: &WorkspaceIndex, from: &Url, link: &Link) -> Option<Target> {
+    let kind = match link.kind {
+        LinkKind::Heading(level) => TargetKind::Heading(level),
+        LinkKind::Generic => TargetKind::Generic,
+        LinkKind::Footnote => TargetKind::Footnote,
+        LinkKind::Definition => TargetKind::Definition,
+        LinkKind::Anchor => TargetKind::Anchor,
+        LinkKind::File => TargetKind::File,
+        LinkKind::Path | LinkKind::Url | LinkKind::Wiki | LinkKind::Timestamp => return None,
+    };
+    let uri = match &link.file {
+        Some(file) => index.resolve_file(from, file)?,
+        None => from.clone(),
+    };
+    let name = match kind {
+        TargetKind::File => String::new(),
+        _ => normalize(&link.target),
+    };
+    Some(Target { uri, kind, name })
+}
+
+/// Every target declared in `structure`, with the range of its title.
+pub(crate) fn declarations(uri: &Url, structure: &Structure) -> Vec<(Target, Range)> {
+    let target = |kind, name: &str| Target {
+        uri: uri.clone(),
+        kind,
+        name: normalize(name),
+    };
+
+    let headings = structure.headings.iter().map(|heading| {
+        let kind = TargetKind::Heading(heading.level);
+        (target(kind, &heading.title), heading.title_range)
+    });
+    let anchors = structure
+        .anchors
+        .iter()
+        .map(|anchor| (target(TargetKind::Anchor, &anchor.name), anchor.name_range));
+    let blocks = structure.blocks.iter().filter_map(|block| {
+        let kind = match block.kind {
+            BlockKind::Footnote => TargetKind::Footnote,
+            BlockKind::Definition => TargetKind::Definition,
+            _ => return None,
+        };
+        Some((target(kind, &block.title), block.title_range))
+    });
+    let file = std::iter::once((target(TargetKind::File, ""), Range::default()));
+
+    headings.chain(anchors).chain(blocks).chain(file).collect()
+}
+
+/// The declaration or link under `position`, as the target it stands for.
+pub(crate) fn target_at(index: &WorkspaceIndex, uri: &Url, position: Position) -> Option<Target> {
+    name_at(index, uri, position).map(|(target, _)| target)
+}
+
+/// Like [`target_at`], together with the range of the name under `position`:
+/// the title of a declaration or the target of a link.
+pub(crate) fn name_at(
+    index: &WorkspaceIndex,
+    uri: &Url,
+    position: Position,
+) -> Option<(Target, Range)> {
+    let structure = index.get(uri)?;
+    if let Some(link) = structure
+        .links
+        .iter()
+        .find(|link| contains(link.range, position))
+    {
+        return Some((link_target(index, uri, link)?, link.target_range));
+    }
+    declarations(uri, &structure)
+        .into_iter()
+        .find(|(target, range)| target.kind != TargetKind::File && contains(*range, position))
+}
+
+/// Declarations `target` resolves to.
+pub(crate) fn resolve(index: &WorkspaceIndex, target: &Target) -> Vec<Location> {
+    let Some(structure) = index.get(&target.uri) else {
+        return Vec::new();
+    };
+    declarations(&target.uri, &structure)
+        .into_iter()
+        .filter(|(declaration, _)| target.matches(declaration))
+        .map(|(_, range)| Location::new(target.uri.clone(), range))
+        .collect()
+}
+
+/// Every link in the workspace pointing at `target`.
+pub(crate) fn references(index: &WorkspaceIndex, target: &Target) -> Vec<Location> {
+    referring_links(index, target)
+        .into_iter()
+        .map(|(uri, link)| Location::new(uri, link.range))
+        .collect()
+}
+
+/// Like [`references`], returning the links themselves.
+pub(crate) fn referring_links(index: &WorkspaceIndex, target: &Target) -> Vec<(Url, Link)> {
+    // a link stands for whatever it resolves to, or only for identical links
+    // when it is broken
+    let declarations: Vec<Target> = match index.get(&target.uri) {
+        Some(structure) => declarations(&target.uri, &structure)
+            .into_iter()
+            .map(|(declaration, _)| declaration)
+            .filter(|declaration| target.matches(declaration))
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let mut references = Vec::new();
+    for (uri, structure) in index.files() {
+        for link in &structure.links {
+            let Some(other) = link_target(index, &uri, link) else {
+                continue;
+            };
+            let found = if declarations.is_empty() {
+                other == *target
+            } else {
+                declarations
+                    .iter()
+                    .any(|declaration| other.matches(declaration))
+            };
+            if found {
+                references.push((uri.clone(), link.clone()));
+            }
+        }
+    }
+    references
+}
+
+/// A link whose target does not exist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BrokenLink {
+    pub(crate) range: Range,
+    pub(crate) message: String,
+    /// Rewrites the link to the closest existing target, if any is close.
+    pub(crate) fix: Option<TextEdit>,
+}
+
+/// Every link in `uri` pointing at a heading, anchor, footnote, definition,
+/// workspace file or local file that does not exist.
+/// Files outside the index are looked for on disk, so async callers run it
+/// on a blocking thread.
+pub(crate) fn broken_links(index: &WorkspaceIndex, uri: &Url) -> Vec<BrokenLink> {
+    let Some(structure) = index.get(uri) else {
+        return Vec::new();
+    };
+    structure
+        .links
+        .iter()
+        .filter_map(|link| broken_link(index, uri, link))
+        .collect()
+}
+
+fn broken_link(index: &WorkspaceIndex, from: &Url, link: &Link) -> Option<BrokenLink> {
+    if link.kind == LinkKind::Path {
+        return broken_path(index, from, link);
+    }
+    let target = link_target(index, from, link)?;
+
+    if let Some(file) = &link.file
+        && index.get(&target.uri).is_none()
+    {
+        // files outside the workspace are not indexed, but still exist
+        if target.uri.to_file_path().is_ok_and(|path| path.exists()) {
+            return None;
+        }
+        let range = link.file_range.unwrap_or(link.range);
+        let files: Vec<String> = index
+            .files()
+            .into_iter()
+            .filter(|(uri, _)| uri != from)
+            .filter_map(|(uri, _)| index.link_path(from, &uri))
+            .collect();
+        return Some(BrokenLink {
+            range,
+            message: format!("no file `{file}` in the workspace"),
+            fix: closest(file, files.iter().map(String::as_str))
+                .map(|name| TextEdit::new(range, name.to_owned())),
+        });
+    }
+
+    if target.kind == TargetKind::File || !resolve(index, &target).is_empty() {
+        return None;
+    }
+    let structure = index.get(&target.uri)?;
+    let what = match target.kind {
+        TargetKind::Heading(_) => "heading",
+        TargetKind::Anchor => "anchor",
+        TargetKind::Footnote => "footnote",
+        TargetKind::Definition => "definition",
+        TargetKind::Generic | TargetKind::File => "target",
+    };
+    Some(BrokenLink {
+        range: link.target_range,
+        message: format!("no {what} `{}`", link.target),
+        fix: closest(&link.target, titles(&structure, target.kind))
+            .map(|name| TextEdit::new(link.target_range, name.to_owned())),
+    })
+}
+
+/// `{/ path}` links to files that are not on disk.
+fn broken_path(index: &WorkspaceIndex, from: &Url, link: &Link) -> Option<BrokenLink> {
+    let path = index.resolve_path(from, &link.target)?;
+    if path.exists() {
+        return None;
+    }
+    let names: Vec<String> = path
+        .parent()
+        .and_then(|parent| std::fs::read_dir(parent).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .collect();
+    let name = path.file_name()?.to_string_lossy();
+    let fix = closest(&name, names.iter().map(String::as_str)).map(|name| {
+        let dir = link.target.rfind('/').map_or("", |i| &link.target[..=i]);
+        TextEdit::new(link.target_range, format!("{dir}{name}"))
+    });
+    Some(BrokenLink {
+        range: link.target_range,
+        message: format!("no file `{}`", link.target),
+        fix,
+    })
+}
+
+/// Titles a link of `kind` could point at in `structure`.
+fn titles(structure: &Structure, kind: TargetKind) -> Vec<&str> {
+    let headings = structure
+        .headings
+        .iter()
+        .filter(move |heading| match kind {
+            TargetKind::Heading(level) => heading.level == level,
+            _ => kind == TargetKind::Generic,
+        })
+        .map(|heading| heading.title.as_str());
+    let anchors = structure
+        .anchors
+        .iter()
+        .filter(|_| matches!(kind, TargetKind::Anchor | TargetKind::Generic))
+        .map(|anchor| anchor.name.as_str());
+    let blocks = structure
+        .blocks
+        .iter()
+        .filter(move |block| match block.kind {
+            BlockKind::Footnote => matches!(
+                kind,
+                TargetKind::Footnote | TargetKind::Anchor | TargetKind::Generic
+            ),
+            BlockKind::Definition => {
+                matches!(kind, TargetKind::Definition | TargetKind::Generic)
+            }
+            _ => false,
+        })
+        .map(|block| block.title.as_str());
+    headings.chain(anchors).chain(blocks).collect()
+}
+
+/// The candidate closest to `name` by edit distance, unless even that one
+/// has less than half of it in common.
+fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let name = normalize(name);
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(&name, &normalize(candidate)), candidate))
+        .filter(|(distance, candidate)| {
+            *distance * 2 <= name.chars().count().max(candidate.chars().count())
+        })
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between `a` and `b`, counted in chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broken_links_suggest_the_closest_target() {
+        let index = WorkspaceIndex::default();
+        let (Ok(a), Ok(b)) = (
+            Url::parse("file:///notes/a.norg"),
+            Url::parse("file:///notes/b.norg"),
+        ) else {
+            panic!("invalid uri");
+        };
+        index.update(b, "* Introduction\n** Setup\n");
+        index.update(
+            a.clone(),
+            "* Intro\n{* Intro}\n{* Introductoin}\n{:b:* Introductio}\n{:bb:}\n{:b:** Setup}\n",
+        );
+
+        let fixes: Vec<(u32, Option<String>)> = broken_links(&index, &a)
+            .into_iter()
+            .map(|broken| (broken.range.start.line, broken.fix.map(|fix| fix.new_text)))
+            .collect();
+        assert_eq!(
+            fixes,
+            [
+                (2, None),
+                (3, Some("Introduction".to_owned())),
+                (4, Some("b".to_owned())),
+            ]
+        );
+    }
+
+    #[test]
+    fn edit_distance_counts_chars() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("ക്ക", "ക"), 2);
+    }
+}
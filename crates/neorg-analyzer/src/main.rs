@@ -1,4 +1,3 @@
-use dashmap::DashMap;
 use neorg_analyzer::backend::Backend;
 use tower_lsp::{LspService, Server};
 
@@ -8,10 +7,8 @@ async fn main() {
 
     let (stdin, stdout) = (tokio::io::stdin(), tokio::io::stdout());
 
-    let (service, socket) = LspService::new(|client| Backend {
-        client,
-        document_map: DashMap::new(),
-        cst_map: DashMap::new(),
-    });
+    let (service, socket) = LspService::build(Backend::new)
+        .custom_method("neorg/todos", Backend::todos)
+        .finish();
     Server::new(stdin, stdout, socket).serve(service).await;
 }
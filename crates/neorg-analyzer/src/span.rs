@@ -1,21 +1,327 @@
-pub fn offset_to_position(
-    offset: usize,
-    rope: &ropey::Rope,
-) -> Option<tower_lsp::lsp_types::Position> {
-    let line = rope.try_char_to_line(offset).ok()?;
-    let first_char_of_line = rope.try_line_to_char(line).ok()?;
-    let column = offset - first_char_of_line;
-    Some(tower_lsp::lsp_types::Position::new(
-        line as u32,
-        column as u32,
-    ))
+//! Conversions between LSP positions and offsets into a document.
+//!
+//! LSP counts columns in the `positionEncoding` negotiated on initialization,
+//! UTF-16 code units unless the client offers another one, while ropes are
+//! indexed by char and strings by byte. Lines end at `\n`, `\r\n` or `\r`, as
+//! in LSP.
+
+use std::borrow::Cow;
+
+use ropey::Rope;
+use tower_lsp::lsp_types::{ClientCapabilities, Position, PositionEncodingKind};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PositionEncoding {
+    Utf8,
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// The first encoding offered by the client that is supported, UTF-16 if
+    /// it offers none.
+    pub fn negotiate(capabilities: &ClientCapabilities) -> Self {
+        capabilities
+            .general
+            .as_ref()
+            .and_then(|general| general.position_encodings.as_ref())
+            .and_then(|kinds| kinds.iter().find_map(Self::from_kind))
+            .unwrap_or_default()
+    }
+
+    fn from_kind(kind: &PositionEncodingKind) -> Option<Self> {
+        Some(match kind.as_str() {
+            "utf-8" => Self::Utf8,
+            "utf-16" => Self::Utf16,
+            "utf-32" => Self::Utf32,
+            _ => return None,
+        })
+    }
+
+    pub fn kind(self) -> PositionEncodingKind {
+        match self {
+            Self::Utf8 => PositionEncodingKind::UTF8,
+            Self::Utf16 => PositionEncodingKind::UTF16,
+            Self::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+
+    fn char_len(self, c: char) -> usize {
+        match self {
+            Self::Utf8 => c.len_utf8(),
+            Self::Utf16 => c.len_utf16(),
+            Self::Utf32 => 1,
+        }
+    }
+
+    /// Length of `text` in code units.
+    pub fn len(self, text: &str) -> usize {
+        match self {
+            Self::Utf8 => text.len(),
+            _ => text.chars().map(|c| self.char_len(c)).sum(),
+        }
+    }
+
+    /// Column of the byte offset `byte` into `line`, rounded down to a char
+    /// boundary.
+    pub fn column(self, line: &str, byte: usize) -> u32 {
+        let mut byte = byte.min(line.len());
+        while !line.is_char_boundary(byte) {
+            byte -= 1;
+        }
+        self.len(&line[..byte]) as u32
+    }
+
+    /// Byte offset of `column` into `line`. Columns inside a char round down
+    /// to its start, columns past the end to the end.
+    pub fn byte(self, line: &str, column: u32) -> usize {
+        let column = column as usize;
+        let mut units = 0;
+        for (byte, c) in line.char_indices() {
+            units += self.char_len(c);
+            if units > column {
+                return byte;
+            }
+        }
+        line.len()
+    }
+
+    /// Char index of `position` in `rope`. Columns past the end of the line
+    /// stop before its line break; `None` for lines past the end.
+    pub fn offset(self, rope: &Rope, position: Position) -> Option<usize> {
+        let line = position.line as usize;
+        let start = rope.try_line_to_char(line).ok()?;
+        let Some(text) = rope.get_line(line) else {
+            // the empty line after the last line break
+            return Some(start);
+        };
+        let text: Cow<'_, str> = text.into();
+        let text = text.trim_end_matches(['\n', '\r']);
+        let byte = self.byte(text, position.character);
+        Some(start + text[..byte].chars().count())
+    }
+
+    /// Position of the char index `offset` in `rope`.
+    pub fn position(self, rope: &Rope, offset: usize) -> Option<Position> {
+        let line = rope.try_char_to_line(offset).ok()?;
+        let start = rope.try_line_to_char(line).ok()?;
+        let column = match self {
+            Self::Utf8 => rope.char_to_byte(offset) - rope.char_to_byte(start),
+            Self::Utf16 => rope.char_to_utf16_cu(offset) - rope.char_to_utf16_cu(start),
+            Self::Utf32 => offset - start,
+        };
+        Some(Position::new(line as u32, column as u32))
+    }
+
+    /// `position` with its column counted in chars, as the CST counts them,
+    /// in this encoding. Columns past the end of the line stop before its
+    /// line break; lines past the end are left as they are.
+    pub fn from_chars(self, rope: &Rope, position: Position) -> Position {
+        let line = position.line as usize;
+        let Ok(start) = rope.try_line_to_char(line) else {
+            return position;
+        };
+        let len = rope.get_line(line).map_or(0, |text| {
+            let text: Cow<'_, str> = text.into();
+            text.trim_end_matches(['\n', '\r']).chars().count()
+        });
+        let offset = start + (position.character as usize).min(len);
+        self.position(rope, offset).unwrap_or(position)
+    }
+}
+
+/// Lines of `text` with their line break, broken where the rope breaks
+/// them. The last line has none, and is empty when `text` ends with one.
+pub(crate) fn lines_with_breaks(text: &str) -> Vec<&str> {
+    let bytes = text.as_bytes();
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let end = match bytes[i] {
+            b'\r' if bytes.get(i + 1) == Some(&b'\n') => i + 2,
+            b'\r' | b'\n' => i + 1,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        lines.push(&text[start..end]);
+        (start, i) = (end, end);
+    }
+    lines.push(&text[start..]);
+    lines
+}
+
+/// Lines of `text` without their line break, as many as the rope has.
+pub(crate) fn lines(text: &str) -> Vec<&str> {
+    lines_with_breaks(text)
+        .into_iter()
+        .map(|line| line.trim_end_matches(['\n', '\r']))
+        .collect()
 }
 
-pub fn position_to_offset(
-    position: tower_lsp::lsp_types::Position,
-    rope: &ropey::Rope,
-) -> Option<usize> {
-    let line_char_offset = rope.try_line_to_char(position.line as usize).ok()?;
-    let slice = rope.slice(0..line_char_offset + position.character as usize);
-    Some(slice.len_bytes())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Rng;
+
+    const ENCODINGS: [PositionEncoding; 3] = [
+        PositionEncoding::Utf8,
+        PositionEncoding::Utf16,
+        PositionEncoding::Utf32,
+    ];
+
+    /// ASCII, Malayalam with a combining vowel sign, CJK, an emoji outside the
+    /// BMP, a zero width joiner and every line break LSP knows about.
+    const CHARS: &[char] = &[
+        'a', 'z', ' ', '*', 'é', 'മ', 'ല', '\u{0d3e}', '漢', '😀', '\u{200d}', '\n', '\r',
+    ];
+
+    fn text(rng: &mut Rng) -> String {
+        let len = rng.below(40);
+        (0..len).map(|_| rng.pick(CHARS)).collect()
+    }
+
+    /// Whether char index `offset` falls between the `\r` and `\n` of a line
+    /// break, which no position can point at.
+    fn splits_crlf(chars: &[char], offset: usize) -> bool {
+        offset > 0 && chars.get(offset - 1) == Some(&'\r') && chars.get(offset) == Some(&'\n')
+    }
+
+    /// Position of char index `offset`, counted the slow way.
+    fn expected(encoding: PositionEncoding, chars: &[char], offset: usize) -> Position {
+        let (mut line, mut column) = (0, 0);
+        for (i, c) in chars[..offset].iter().enumerate() {
+            let breaks = *c == '\n' || (*c == '\r' && chars.get(i + 1) != Some(&'\n'));
+            if breaks {
+                line += 1;
+                column = 0;
+            } else if *c != '\r' {
+                column += match encoding {
+                    PositionEncoding::Utf8 => c.to_string().len(),
+                    PositionEncoding::Utf16 => c.to_string().encode_utf16().count(),
+                    PositionEncoding::Utf32 => 1,
+                };
+            }
+        }
+        Position::new(line, column as u32)
+    }
+
+    #[test]
+    fn positions_round_trip() {
+        for seed in 1..2000 {
+            let mut rng = Rng(seed);
+            let text = text(&mut rng);
+            let chars: Vec<char> = text.chars().collect();
+            let rope = Rope::from_str(&text);
+            for encoding in ENCODINGS {
+                for offset in (0..=chars.len()).filter(|offset| !splits_crlf(&chars, *offset)) {
+                    let position = encoding.position(&rope, offset);
+                    assert_eq!(
+                        position,
+                        Some(expected(encoding, &chars, offset)),
+                        "seed {seed}, {encoding:?}, offset {offset} of {text:?}"
+                    );
+                    let back = position.and_then(|position| encoding.offset(&rope, position));
+                    assert_eq!(back, Some(offset), "seed {seed}, {encoding:?}, {text:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn char_columns_convert() {
+        for seed in 1..2000 {
+            let mut rng = Rng(seed);
+            let text = text(&mut rng);
+            let chars: Vec<char> = text.chars().collect();
+            let rope = Rope::from_str(&text);
+            for encoding in ENCODINGS {
+                for offset in (0..=chars.len()).filter(|offset| !splits_crlf(&chars, *offset)) {
+                    let in_chars = expected(PositionEncoding::Utf32, &chars, offset);
+                    assert_eq!(
+                        encoding.from_chars(&rope, in_chars),
+                        expected(encoding, &chars, offset),
+                        "seed {seed}, {encoding:?}, offset {offset} of {text:?}"
+                    );
+                }
+            }
+        }
+        let rope = Rope::from_str("é😀 word\n");
+        let past_the_end = PositionEncoding::Utf16.from_chars(&rope, Position::new(0, 40));
+        assert_eq!(past_the_end, Position::new(0, 8));
+    }
+
+    #[test]
+    fn lines_break_like_the_rope() {
+        for seed in 1..2000 {
+            let mut rng = Rng(seed);
+            let text = text(&mut rng);
+            let rope = Rope::from_str(&text);
+            let expected: Vec<String> = rope.lines().map(String::from).collect();
+            assert_eq!(lines_with_breaks(&text), expected, "{text:?}");
+            assert_eq!(lines(&text).len(), rope.len_lines(), "{text:?}");
+        }
+        assert_eq!(lines("a\rb\r\nc\n\rd"), ["a", "b", "c", "", "d"]);
+    }
+
+    #[test]
+    fn columns_round_trip() {
+        for seed in 1..2000 {
+            let mut rng = Rng(seed);
+            let line = text(&mut rng).replace(['\n', '\r'], "");
+            for encoding in ENCODINGS {
+                for (byte, _) in line.char_indices().chain([(line.len(), ' ')]) {
+                    let column = encoding.column(&line, byte);
+                    assert_eq!(column as usize, encoding.len(&line[..byte]));
+                    assert_eq!(encoding.byte(&line, column), byte, "{encoding:?} {line:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn edits_apply_where_the_client_meant() {
+        for seed in 1..1000 {
+            let mut rng = Rng(seed);
+            let mut text = text(&mut rng);
+            let mut rope = Rope::from_str(&text);
+            let encoding = ENCODINGS[seed as usize % ENCODINGS.len()];
+            for _ in 0..20 {
+                let chars: Vec<char> = text.chars().collect();
+                let mut start = rng.below(chars.len() + 1);
+                let mut end = start + rng.below(chars.len() - start + 1);
+                if splits_crlf(&chars, start) {
+                    start -= 1;
+                }
+                if splits_crlf(&chars, end) {
+                    end += 1;
+                }
+                let insert = self::text(&mut rng);
+
+                // what the client sends
+                let range = (
+                    expected(encoding, &chars, start),
+                    expected(encoding, &chars, end),
+                );
+                // what the client ends up with
+                text = chars[..start].iter().collect();
+                text.push_str(&insert);
+                text.extend(&chars[end..]);
+
+                let from = encoding.offset(&rope, range.0);
+                let to = encoding.offset(&rope, range.1);
+                assert_eq!(
+                    (from, to),
+                    (Some(start), Some(end)),
+                    "seed {seed}, {encoding:?}"
+                );
+                rope.remove(start..end);
+                rope.insert(start, &insert);
+                assert_eq!(rope.to_string(), text, "seed {seed}, {encoding:?}");
+            }
+        }
+    }
 }
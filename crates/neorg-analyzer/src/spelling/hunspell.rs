@@ -0,0 +1,553 @@
+//! Hunspell `.aff` and `.dic` files, and checking words against them.
+//!
+//! Supports prefixes and suffixes, also combined, every flag type and flag
+//! aliases, `NEEDAFFIX`, `FORBIDDENWORD`, `KEEPCASE` and `ONLYINCOMPOUND`,
+//! and the `TRY` and `REP` tables for suggestions. Compounding is not, so
+//! words only valid inside a compound are unknown.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+type Flag = u32;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum FlagType {
+    /// One char per flag.
+    #[default]
+    Short,
+    /// Two chars per flag.
+    Long,
+    /// Comma separated numbers.
+    Numeric,
+}
+
+/// One char of an affix condition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CharClass {
+    Any,
+    Char(char),
+    Set(Vec<char>),
+    NotSet(Vec<char>),
+}
+
+impl CharClass {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Char(expected) => c == *expected,
+            Self::Set(chars) => chars.contains(&c),
+            Self::NotSet(chars) => !chars.contains(&c),
+        }
+    }
+}
+
+fn condition(pattern: &str) -> Vec<CharClass> {
+    let mut classes = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        classes.push(match c {
+            '.' => CharClass::Any,
+            '[' => {
+                let mut set = Vec::new();
+                let mut negated = false;
+                for c in chars.by_ref() {
+                    match c {
+                        ']' => break,
+                        '^' if set.is_empty() && !negated => negated = true,
+                        c => set.push(c),
+                    }
+                }
+                if negated {
+                    CharClass::NotSet(set)
+                } else {
+                    CharClass::Set(set)
+                }
+            }
+            c => CharClass::Char(c),
+        });
+    }
+    classes
+}
+
+#[derive(Debug, Clone)]
+struct Affix {
+    flag: Flag,
+    /// Whether it combines with affixes of the other kind.
+    cross: bool,
+    strip: String,
+    add: String,
+    condition: Vec<CharClass>,
+}
+
+impl Affix {
+    /// The stem `word` was built from, if this prefix built it.
+    fn strip_prefix(&self, word: &str) -> Option<String> {
+        let rest = word.strip_prefix(self.add.as_str())?;
+        if rest.is_empty() && self.strip.is_empty() {
+            return None;
+        }
+        let stem = format!("{}{rest}", self.strip);
+        let mut chars = stem.chars();
+        let matches = self
+            .condition
+            .iter()
+            .all(|class| chars.next().is_some_and(|c| class.matches(c)));
+        matches.then_some(stem)
+    }
+
+    /// The stem `word` was built from, if this suffix built it.
+    fn strip_suffix(&self, word: &str) -> Option<String> {
+        let rest = word.strip_suffix(self.add.as_str())?;
+        if rest.is_empty() && self.strip.is_empty() {
+            return None;
+        }
+        let stem = format!("{rest}{}", self.strip);
+        let mut chars = stem.chars().rev();
+        let matches = self
+            .condition
+            .iter()
+            .rev()
+            .all(|class| chars.next().is_some_and(|c| class.matches(c)));
+        matches.then_some(stem)
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Hunspell {
+    /// Flags of every homonym of a stem.
+    words: HashMap<String, Vec<Vec<Flag>>>,
+    prefixes: Vec<Affix>,
+    suffixes: Vec<Affix>,
+    need_affix: Option<Flag>,
+    forbidden: Option<Flag>,
+    keep_case: Option<Flag>,
+    only_in_compound: Option<Flag>,
+    /// Chars tried when suggesting, most frequent first.
+    try_chars: Vec<char>,
+    /// Common misspellings, as `(wrong, right)` parts of words.
+    replacements: Vec<(String, String)>,
+}
+
+/// Reads flags and flag aliases the way the `.aff` file declares them.
+#[derive(Debug, Default)]
+struct Flags {
+    kind: FlagType,
+    aliases: Vec<Vec<Flag>>,
+}
+
+impl Flags {
+    fn parse(&self, text: &str) -> Vec<Flag> {
+        if !self.aliases.is_empty()
+            && let Ok(alias) = text.parse::<usize>()
+        {
+            return alias
+                .checked_sub(1)
+                .and_then(|alias| self.aliases.get(alias))
+                .cloned()
+                .unwrap_or_default();
+        }
+        self.parse_plain(text)
+    }
+
+    fn parse_plain(&self, text: &str) -> Vec<Flag> {
+        match self.kind {
+            FlagType::Short => text.chars().map(Flag::from).collect(),
+            FlagType::Long => {
+                let chars: Vec<char> = text.chars().collect();
+                chars
+                    .chunks(2)
+                    .map(|pair| pair.iter().fold(0, |flag, c| flag << 16 | Flag::from(*c)))
+                    .collect()
+            }
+            FlagType::Numeric => text
+                .split(',')
+                .filter_map(|flag| flag.trim().parse().ok())
+                .collect(),
+        }
+    }
+
+    fn first(&self, text: &str) -> Option<Flag> {
+        self.parse_plain(text).first().copied()
+    }
+}
+
+/// Whether the `.aff` file declares a Latin-1 encoding rather than UTF-8.
+fn is_latin1(aff: &[u8]) -> bool {
+    String::from_utf8_lossy(aff)
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("SET "))
+        .is_some_and(|set| {
+            set.trim()
+                .to_uppercase()
+                .replace('-', "")
+                .starts_with("ISO8859")
+        })
+}
+
+fn decode(bytes: &[u8], latin1: bool) -> String {
+    if latin1 {
+        bytes.iter().map(|byte| char::from(*byte)).collect()
+    } else {
+        String::from_utf8_lossy(bytes).into_owned()
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    chars.next().map_or_else(String::new, |first| {
+        first.to_uppercase().chain(chars).collect()
+    })
+}
+
+impl Hunspell {
+    pub(crate) fn open(aff: &Path, dic: &Path) -> std::io::Result<Self> {
+        let aff = std::fs::read(aff)?;
+        let dic = std::fs::read(dic)?;
+        let latin1 = is_latin1(&aff);
+        Ok(Self::parse(&decode(&aff, latin1), &decode(&dic, latin1)))
+    }
+
+    pub(crate) fn parse(aff: &str, dic: &str) -> Self {
+        let mut hunspell = Self::default();
+        let mut flags = Flags::default();
+        let mut aliases_declared = false;
+        let mut cross: HashMap<(bool, Flag), bool> = HashMap::new();
+
+        for line in aff.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["FLAG", kind, ..] => {
+                    flags.kind = match *kind {
+                        "long" => FlagType::Long,
+                        "num" => FlagType::Numeric,
+                        _ => FlagType::Short,
+                    };
+                }
+                // the first `AF` line only counts the aliases
+                ["AF", alias, ..] => {
+                    if aliases_declared {
+                        let alias = flags.parse_plain(alias);
+                        flags.aliases.push(alias);
+                    }
+                    aliases_declared = true;
+                }
+                ["NEEDAFFIX" | "PSEUDOROOT", flag, ..] => hunspell.need_affix = flags.first(flag),
+                ["FORBIDDENWORD", flag, ..] => hunspell.forbidden = flags.first(flag),
+                ["KEEPCASE", flag, ..] => hunspell.keep_case = flags.first(flag),
+                ["ONLYINCOMPOUND", flag, ..] => hunspell.only_in_compound = flags.first(flag),
+                ["TRY", chars, ..] => hunspell.try_chars = chars.chars().collect(),
+                ["REP", from, to, ..] => {
+                    // `_` stands for a space, `^` and `$` anchor to the word
+                    let part = |text: &str| text.trim_matches(['^', '$']).replace('_', " ");
+                    hunspell.replacements.push((part(from), part(to)));
+                }
+                [kind @ ("PFX" | "SFX"), flag, combines @ ("Y" | "N"), count]
+                    if count.parse::<usize>().is_ok() =>
+                {
+                    if let Some(flag) = flags.first(flag) {
+                        cross.insert((*kind == "PFX", flag), *combines == "Y");
+                    }
+                }
+                [kind @ ("PFX" | "SFX"), flag, strip, add, rest @ ..] => {
+                    let Some(flag) = flags.first(flag) else {
+                        continue;
+                    };
+                    let prefix = *kind == "PFX";
+                    let zero = |text: &str| if text == "0" { "" } else { text }.to_owned();
+                    // continuation classes after `/` are not supported
+                    let add = add.split('/').next().unwrap_or_default();
+                    let affix = Affix {
+                        flag,
+                        cross: cross.get(&(prefix, flag)).copied().unwrap_or(false),
+                        strip: zero(strip),
+                        add: zero(add),
+                        condition: rest
+                            .first()
+                            .map(|pattern| condition(pattern))
+                            .unwrap_or_default(),
+                    };
+                    if prefix {
+                        hunspell.prefixes.push(affix);
+                    } else {
+                        hunspell.suffixes.push(affix);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut lines = dic.lines();
+        // the first line only counts the words
+        lines.next();
+        for line in lines {
+            // morphological fields follow after whitespace
+            let Some(entry) = line
+                .split(['\t', ' '])
+                .next()
+                .filter(|entry| !entry.is_empty())
+            else {
+                continue;
+            };
+            // `/` separates the flags, `\/` is part of the word
+            let split = entry
+                .char_indices()
+                .find(|(at, c)| *c == '/' && *at > 0 && !entry[..*at].ends_with('\\'))
+                .map(|(at, _)| at);
+            let (word, word_flags) = match split {
+                Some(at) => (&entry[..at], flags.parse(&entry[at + 1..])),
+                None => (entry, Vec::new()),
+            };
+            hunspell
+                .words
+                .entry(word.replace("\\/", "/"))
+                .or_default()
+                .push(word_flags);
+        }
+        hunspell
+    }
+
+    /// Whether `word` is spelled correctly. A capitalised or upper case word
+    /// is also correct when its lower case form is, like at the start of a
+    /// sentence.
+    pub(crate) fn check(&self, word: &str) -> bool {
+        let word = word.replace('’', "'");
+        if self.lookup(&word, false) {
+            return true;
+        }
+        let lower = word.to_lowercase();
+        if lower == word {
+            return false;
+        }
+        let upper = word
+            .chars()
+            .filter(|c| c.is_alphabetic())
+            .all(char::is_uppercase);
+        if upper && self.lookup(&capitalize(&lower), true) {
+            return true;
+        }
+        (upper || capitalize(&lower) == word) && self.lookup(&lower, true)
+    }
+
+    /// Correctly spelled words close to `word`, at most `limit` of them.
+    pub(crate) fn suggest(&self, word: &str, limit: usize) -> Vec<String> {
+        let word = word.replace('’', "'");
+        let chars: Vec<char> = word.chars().collect();
+        let try_chars: Vec<char> = if self.try_chars.is_empty() {
+            ('a'..='z').collect()
+        } else {
+            self.try_chars.clone()
+        };
+        let at = |chars: &[char], i: usize| -> (String, String) {
+            (chars[..i].iter().collect(), chars[i..].iter().collect())
+        };
+
+        let mut candidates = Vec::new();
+        for (from, to) in &self.replacements {
+            for (i, _) in word.match_indices(from.as_str()) {
+                candidates.push(format!("{}{to}{}", &word[..i], &word[i + from.len()..]));
+            }
+        }
+        for i in 1..chars.len() {
+            let mut swapped = chars.clone();
+            swapped.swap(i - 1, i);
+            candidates.push(swapped.into_iter().collect());
+        }
+        for i in 0..chars.len() {
+            let (head, tail) = at(&chars, i);
+            let tail: String = tail.chars().skip(1).collect();
+            for c in try_chars.iter().filter(|c| **c != chars[i]) {
+                candidates.push(format!("{head}{c}{tail}"));
+            }
+            candidates.push(format!("{head}{tail}"));
+        }
+        for i in 0..=chars.len() {
+            let (head, tail) = at(&chars, i);
+            for c in &try_chars {
+                candidates.push(format!("{head}{c}{tail}"));
+            }
+        }
+
+        let mut suggestions: Vec<String> = Vec::new();
+        for candidate in candidates {
+            if suggestions.len() == limit {
+                return suggestions;
+            }
+            if candidate != word
+                && !suggestions.contains(&candidate)
+                && self.check_words(&candidate)
+            {
+                suggestions.push(candidate);
+            }
+        }
+        // two words run together
+        for i in 1..chars.len() {
+            if suggestions.len() == limit {
+                break;
+            }
+            let (head, tail) = at(&chars, i);
+            if self.check(&head) && self.check(&tail) {
+                suggestions.push(format!("{head} {tail}"));
+            }
+        }
+        suggestions
+    }
+
+    /// Like [`Self::check`], for every word of `text`.
+    fn check_words(&self, text: &str) -> bool {
+        text.split(' ').all(|word| self.check(word))
+    }
+
+    fn has(flags: &[Flag], flag: Option<Flag>) -> bool {
+        flag.is_some_and(|flag| flags.contains(&flag))
+    }
+
+    /// Whether a stem with `flags` may stand in a word, after changing its
+    /// case if `recased`.
+    fn usable(&self, flags: &[Flag], recased: bool) -> bool {
+        let unusable = Self::has(flags, self.only_in_compound)
+            || Self::has(flags, self.forbidden)
+            || recased && Self::has(flags, self.keep_case);
+        !unusable
+    }
+
+    fn lookup(&self, word: &str, recased: bool) -> bool {
+        if let Some(homonyms) = self.words.get(word) {
+            if homonyms
+                .iter()
+                .any(|flags| Self::has(flags, self.forbidden))
+            {
+                return false;
+            }
+            if homonyms
+                .iter()
+                .any(|flags| self.usable(flags, recased) && !Self::has(flags, self.need_affix))
+            {
+                return true;
+            }
+        }
+        self.affixed(word, recased)
+    }
+
+    /// Whether `stem` takes the affix `flag`, and `other` too if given.
+    fn takes(&self, stem: &str, flag: Flag, other: Option<Flag>, recased: bool) -> bool {
+        self.words.get(stem).is_some_and(|homonyms| {
+            homonyms.iter().any(|flags| {
+                flags.contains(&flag)
+                    && other.is_none_or(|other| flags.contains(&other))
+                    && self.usable(flags, recased)
+            })
+        })
+    }
+
+    fn affixed(&self, word: &str, recased: bool) -> bool {
+        let suffixed = self.suffixes.iter().any(|suffix| {
+            suffix
+                .strip_suffix(word)
+                .is_some_and(|stem| self.takes(&stem, suffix.flag, None, recased))
+        });
+        if suffixed {
+            return true;
+        }
+        self.prefixes.iter().any(|prefix| {
+            let Some(stem) = prefix.strip_prefix(word) else {
+                return false;
+            };
+            if self.takes(&stem, prefix.flag, None, recased) {
+                return true;
+            }
+            prefix.cross
+                && self
+                    .suffixes
+                    .iter()
+                    .filter(|suffix| suffix.cross)
+                    .any(|suffix| {
+                        suffix.strip_suffix(&stem).is_some_and(|root| {
+                            self.takes(&root, suffix.flag, Some(prefix.flag), recased)
+                        })
+                    })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AFF: &str = "\
+SET UTF-8
+TRY esianrtolcdugmphbyfvkwz'
+KEEPCASE K
+FORBIDDENWORD !
+NEEDAFFIX N
+REP 1
+REP f ph
+
+PFX U Y 1
+PFX U 0 un .
+
+SFX S Y 3
+SFX S y ies [^aeiou]y
+SFX S 0 s [aeiou]y
+SFX S 0 s [^y]
+
+SFX D Y 4
+SFX D 0 d e
+SFX D y ied [^aeiou]y
+SFX D 0 ed [^ey]
+SFX D 0 ed [aeiou]y
+";
+
+    const DIC: &str = "\
+9
+the
+try/SD
+day/S
+lock/UDS
+tie/D
+Paris
+NASA/K
+walk/N
+stupid/!
+";
+
+    #[test]
+    fn checks_stems_and_affixes() {
+        let hunspell = Hunspell::parse(AFF, DIC);
+        for word in [
+            "the", "tries", "tried", "days", "locks", "unlocked", "unlocks", "tied", "Paris",
+            "The", "THE", "PARIS", "NASA",
+        ] {
+            assert!(hunspell.check(word), "{word} should be known");
+        }
+        for word in [
+            "trys", "dayies", "untie", "paris", "Nasa", "walk", "stupid", "teh", "",
+        ] {
+            assert!(!hunspell.check(word), "{word} should be unknown");
+        }
+    }
+
+    #[test]
+    fn suggests_close_words() {
+        let hunspell = Hunspell::parse(AFF, DIC);
+        assert_eq!(hunspell.suggest("teh", 3), ["the"]);
+        assert_eq!(hunspell.suggest("unlokced", 3), ["unlocked"]);
+        assert_eq!(hunspell.suggest("thetries", 3), ["the tries"]);
+    }
+
+    #[test]
+    fn reads_long_numeric_and_aliased_flags() {
+        let long = Hunspell::parse("FLAG long\nSFX Aa Y 1\nSFX Aa 0 s .\n", "1\ncat/AaBb\n");
+        assert!(long.check("cats"));
+
+        let numeric = Hunspell::parse("FLAG num\nSFX 12 Y 1\nSFX 12 0 s .\n", "1\ncat/3,12\n");
+        assert!(numeric.check("cats"));
+
+        let aliased = Hunspell::parse(
+            "AF 2\nAF S\nAF SD\nSFX S Y 1\nSFX S 0 s .\nSFX D Y 1\nSFX D 0 ed .\n",
+            "2\nwalk/2\ncat/1\n",
+        );
+        assert!(aliased.check("walked"));
+        assert!(aliased.check("cats"));
+        assert!(!aliased.check("cated"));
+    }
+}
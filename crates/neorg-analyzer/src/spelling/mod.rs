@@ -0,0 +1,233 @@
+//! Spell checking the prose of a document against a Hunspell dictionary.
+//!
+//! Dictionaries are the `.dic` and `.aff` pairs shipped for Hunspell,
+//! LibreOffice and Firefox, read from disk by [`Hunspell`].
+
+mod hunspell;
+mod words;
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use tower_lsp::lsp_types::{Position, Range};
+
+pub(crate) use hunspell::Hunspell;
+pub(crate) use words::{WORDS_FILE, WordList, append, user_words};
+
+use crate::config::SpellingSettings;
+use crate::span::{PositionEncoding, lines};
+use crate::structure::{Structure, TagKind};
+
+/// Environment variable Hunspell searches dictionaries in.
+const DICPATH_ENV: &str = "DICPATH";
+/// Where distributions install Hunspell dictionaries.
+const SYSTEM_DIRS: &[&str] = &[
+    "/usr/share/hunspell",
+    "/usr/local/share/hunspell",
+    "/usr/share/myspell",
+    "/usr/share/myspell/dicts",
+    "/Library/Spelling",
+];
+
+/// Loads the dictionary described by `settings`, if spell checking is on and
+/// the dictionary exists.
+pub(crate) fn load(settings: &SpellingSettings) -> Option<Hunspell> {
+    if !settings.enable {
+        return None;
+    }
+    let (aff, dic) = find(settings)?;
+    match Hunspell::open(&aff, &dic) {
+        Ok(hunspell) => Some(hunspell),
+        Err(err) => {
+            eprintln!("failed to load {}: {err}", dic.display());
+            None
+        }
+    }
+}
+
+/// The `.aff` and `.dic` files of the configured dictionary.
+fn find(settings: &SpellingSettings) -> Option<(PathBuf, PathBuf)> {
+    let pair = |base: PathBuf| {
+        let (aff, dic) = (base.with_extension("aff"), base.with_extension("dic"));
+        (aff.is_file() && dic.is_file()).then_some((aff, dic))
+    };
+    if let Some(path) = &settings.path {
+        if path.is_dir() {
+            return pair(path.join(&settings.language));
+        }
+        return pair(path.clone());
+    }
+
+    let mut dirs: Vec<PathBuf> = std::env::var_os(DICPATH_ENV)
+        .map(|paths| std::env::split_paths(&paths).collect())
+        .unwrap_or_default();
+    dirs.extend(dirs::data_dir().map(|dir| dir.join("hunspell")));
+    dirs.extend(dirs::home_dir().map(|dir| dir.join("Library/Spelling")));
+    dirs.extend(SYSTEM_DIRS.iter().map(PathBuf::from));
+    dirs.into_iter()
+        .find_map(|dir| pair(dir.join(&settings.language)))
+}
+
+/// Whether `c` separates words.
+fn is_separator(c: char) -> bool {
+    c.is_whitespace()
+        || c.is_ascii_punctuation()
+        || matches!(c, '‘' | '’' | '“' | '”' | '–' | '—' | '…' | '«' | '»' | '·')
+}
+
+fn is_apostrophe(c: char) -> bool {
+    matches!(c, '\'' | '’')
+}
+
+/// Byte ranges of the words of `line`, skipping inline verbatim, math,
+/// variables and comments.
+fn words(line: &str) -> Vec<(usize, usize)> {
+    let chars: Vec<(usize, char)> = line.char_indices().collect();
+    let mut words = Vec::new();
+    let mut i = 0;
+    while let Some(&(start, c)) = chars.get(i) {
+        if matches!(c, '`' | '$' | '&' | '%')
+            && chars
+                .get(i + 1)
+                .is_some_and(|(_, next)| !next.is_whitespace() && *next != c)
+            && let Some(close) = chars[i + 1..].iter().position(|(_, other)| *other == c)
+        {
+            i += close + 2;
+            continue;
+        }
+        if is_separator(c) {
+            i += 1;
+            continue;
+        }
+
+        // `don't`: an apostrophe between two letters stays in the word
+        let mut end = i + 1;
+        while let Some(&(_, c)) = chars.get(end) {
+            let inner = is_apostrophe(c)
+                && chars
+                    .get(end + 1)
+                    .is_some_and(|(_, next)| !is_separator(*next));
+            if is_separator(c) && !inner {
+                break;
+            }
+            end += 1;
+        }
+        let end_byte = chars.get(end).map_or(line.len(), |(at, _)| *at);
+        words.push((start, end_byte));
+        i = end;
+    }
+    words
+}
+
+/// Whether `word` reads as prose rather than a number, an acronym or an
+/// identifier.
+fn is_prose(word: &str) -> bool {
+    let mut chars = word.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+    let rest: Vec<char> = chars.collect();
+    first.is_alphabetic()
+        && !rest.is_empty()
+        && !word.chars().any(char::is_numeric)
+        && !rest.iter().any(|c| c.is_uppercase())
+}
+
+/// The words of `text` worth spell checking, with their ranges. Verbatim
+/// and macro tags, the lines of other tags, links, inline verbatim and math, urls,
+/// acronyms and words containing digits are skipped.
+pub(crate) fn prose_words(
+    text: &str,
+    structure: &Structure,
+    encoding: PositionEncoding,
+) -> Vec<(Range, String)> {
+    let mut skipped: HashSet<u32> = HashSet::new();
+    for tag in &structure.tags {
+        let (first, last) = (tag.range.start.line, tag.range.end.line);
+        if matches!(tag.kind, TagKind::Ranged | TagKind::Macro) {
+            skipped.extend(first..=last);
+        } else if tag.kind.is_ranged() {
+            skipped.extend([first, last]);
+        } else {
+            skipped.insert(first);
+        }
+    }
+
+    let mut prose = Vec::new();
+    for (n, line) in lines(text).into_iter().enumerate() {
+        let n = n as u32;
+        if skipped.contains(&n) {
+            continue;
+        }
+        for (start, end) in words(line) {
+            let word = &line[start..end];
+            if !is_prose(word) {
+                continue;
+            }
+            // urls and paths outside of links
+            let chunk_start = line[..start]
+                .rfind(char::is_whitespace)
+                .map_or(0, |at| at + 1);
+            let chunk_end = line[end..]
+                .find(char::is_whitespace)
+                .map_or(line.len(), |at| end + at);
+            let chunk = &line[chunk_start..chunk_end];
+            if chunk.contains("://") || chunk.starts_with("www.") {
+                continue;
+            }
+            let range = Range::new(
+                Position::new(n, encoding.column(line, start)),
+                Position::new(n, encoding.column(line, end)),
+            );
+            if structure
+                .links
+                .iter()
+                .any(|link| link.range.start <= range.start && range.end <= link.range.end)
+            {
+                continue;
+            }
+            prose.push((range, word.to_owned()));
+        }
+    }
+    prose
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structure::parse;
+
+    #[test]
+    fn only_prose_is_checked() {
+        let text = "* Heding with `verbatim` and $x + y$\n\
+                    A {* Heding}[descripton] to wiht [anchr], see https://exmaple.org\n\
+                    NASA v2 don't it’s\n\
+                    #tagg parameter\n\
+                    @code rust\n\
+                    fn mian() {}\n\
+                    @end\n\
+                    |example\n\
+                    Exampel\n\
+                    |end\n";
+        let structure = parse(text, PositionEncoding::Utf16);
+        let words: Vec<String> = prose_words(text, &structure, PositionEncoding::Utf16)
+            .into_iter()
+            .map(|(_, word)| word)
+            .collect();
+        assert_eq!(
+            words,
+            [
+                "Heding",
+                "with",
+                "and",
+                "descripton",
+                "to",
+                "wiht",
+                "see",
+                "don't",
+                "it’s",
+                "Exampel"
+            ]
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! Words accepted on top of the dictionary, read from `.neorg-words` files.
+//!
+//! A list holds one word per line and `#` starts a comment. A word ending in
+//! `*` accepts every word starting with the rest of it, like `neorg*`.
+
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Name of a word list, at the root of a workspace folder or in the user's
+/// config directory.
+pub(crate) const WORDS_FILE: &str = ".neorg-words";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct WordList {
+    words: HashSet<String>,
+    prefixes: Vec<String>,
+}
+
+impl WordList {
+    pub(crate) fn parse(text: &str) -> Self {
+        let mut list = Self::default();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if !line.is_empty() {
+                list.insert(line);
+            }
+        }
+        list
+    }
+
+    /// Reads the list at `path`, which is empty when there is none.
+    pub(crate) fn read(path: &Path) -> Self {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text),
+            Err(err) => {
+                if err.kind() != ErrorKind::NotFound {
+                    eprintln!("failed to read {}: {err}", path.display());
+                }
+                Self::default()
+            }
+        }
+    }
+
+    pub(crate) fn insert(&mut self, word: &str) {
+        match word.strip_suffix('*') {
+            Some(prefix) => self.prefixes.push(prefix.to_owned()),
+            None => _ = self.words.insert(word.to_owned()),
+        }
+    }
+
+    /// Whether the list accepts `word`. Lower case entries accept any case,
+    /// others only the case they are written in.
+    pub(crate) fn contains(&self, word: &str) -> bool {
+        let lower = word.to_lowercase();
+        self.words.contains(word)
+            || self.words.contains(&lower)
+            || self.prefixes.iter().any(|prefix| {
+                word.starts_with(prefix.as_str()) || lower.starts_with(prefix.as_str())
+            })
+    }
+}
+
+/// The list shared by every workspace.
+pub(crate) fn user_words() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("neorg").join(WORDS_FILE))
+}
+
+/// Adds `word` to the list at `path`, creating it if needed.
+pub(crate) fn append(path: &Path, word: &str) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text.push_str(word);
+    text.push('\n');
+    std::fs::write(path, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_accept_words_and_prefixes() {
+        let list = WordList::parse("# project jargon\nneorg\nTreesitter # the parser\nnvim*\n\n");
+        for word in [
+            "neorg",
+            "Neorg",
+            "NEORG",
+            "Treesitter",
+            "nvim",
+            "nvim-cmp",
+            "Nvimtree",
+        ] {
+            assert!(list.contains(word), "{word} should be accepted");
+        }
+        for word in ["treesitter", "neorgs", "vim", "#"] {
+            assert!(!list.contains(word), "{word} should not be accepted");
+        }
+    }
+}
@@ -0,0 +1,1363 @@
+//! Line oriented outline of a Neorg document.
+//!
+//! Headings, detached modifiers, ranged tags, links and todo extensions are
+//! all recognisable from the start of a line or a single line of text, so they
+//! are collected here in one pass. This is what the workspace index stores and
+//! what navigation features (symbols, folding, links, rename, ...) work from;
+//! syntax errors still come from `neorg_syntax`'s CST.
+//!
+//! The index comes from this scanner rather than from `neorg_syntax` nodes.
+//! The CST knows headings and quotes, its semantic tokens mark them, but the
+//! analyzer reads only word nodes, diagnostics and tokens from it so far,
+//! while the index needs heading levels, link parts, tag parameters and todo
+//! states for every file of the workspace, most of them never opened. The
+//! two parsers read the same lines and can disagree: where the CST reports a
+//! syntax error the outline may still list what it could make out of the
+//! line.
+
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::{Position, Range};
+
+use crate::span::{PositionEncoding, lines};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Structure {
+    pub(crate) headings: Vec<Heading>,
+    pub(crate) blocks: Vec<Block>,
+    pub(crate) tags: Vec<Tag>,
+    pub(crate) links: Vec<Link>,
+    pub(crate) anchors: Vec<Anchor>,
+    pub(crate) todos: Vec<Todo>,
+    /// The `title:` in `@document.meta`.
+    pub(crate) title: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Heading {
+    pub(crate) level: u8,
+    pub(crate) title: String,
+    /// The heading line, from the first `*` to the end of the title.
+    pub(crate) range: Range,
+    pub(crate) title_range: Range,
+    /// The heading and everything up to the next heading of equal or higher level.
+    pub(crate) section: Range,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BlockKind {
+    UnorderedList,
+    OrderedList,
+    Quote,
+    Definition,
+    Footnote,
+    Table,
+}
+
+/// A detached modifier other than a heading, with everything it spans.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Block {
+    pub(crate) kind: BlockKind,
+    pub(crate) level: u8,
+    pub(crate) title: String,
+    /// The modifier characters and the space after them.
+    pub(crate) marker_range: Range,
+    pub(crate) title_range: Range,
+    /// The item and its continuation lines, nested items, or ranged body.
+    pub(crate) range: Range,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TagKind {
+    /// `@name` ... `@end`, verbatim content.
+    Ranged,
+    /// `|name` ... `|end`.
+    Standard,
+    /// `=name` ... `=end`, verbatim content.
+    Macro,
+    /// `#name`, applies to the next element.
+    Weak,
+    /// `+name`, applies to the next element and its children.
+    Strong,
+}
+
+impl TagKind {
+    pub(crate) fn prefix(self) -> char {
+        match self {
+            Self::Ranged => '@',
+            Self::Standard => '|',
+            Self::Macro => '=',
+            Self::Weak => '#',
+            Self::Strong => '+',
+        }
+    }
+
+    pub(crate) fn is_ranged(self) -> bool {
+        matches!(self, Self::Ranged | Self::Standard | Self::Macro)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Tag {
+    pub(crate) kind: TagKind,
+    pub(crate) name: String,
+    pub(crate) parameters: Vec<String>,
+    pub(crate) name_range: Range,
+    /// Ranged tags span up to and including their `end` line.
+    pub(crate) range: Range,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LinkKind {
+    /// `{* Heading}`, with the heading level.
+    Heading(u8),
+    /// `{# name}`, any element with that title.
+    Generic,
+    /// `{^ footnote}`
+    Footnote,
+    /// `{$ definition}`
+    Definition,
+    /// `[name]` on its own, referring to an anchor declaration.
+    Anchor,
+    /// `{:file:}` with no target inside the file.
+    File,
+    /// `{/ path}`
+    Path,
+    /// `{https://...}` or anything without a known prefix.
+    Url,
+    /// `{? page}`
+    Wiki,
+    /// `{@ date}`
+    Timestamp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Link {
+    pub(crate) kind: LinkKind,
+    /// The `:file:` part, without colons or extension.
+    pub(crate) file: Option<String>,
+    pub(crate) file_range: Option<Range>,
+    pub(crate) target: String,
+    /// From `{` to `}` (or `[` to `]` for anchor references).
+    pub(crate) range: Range,
+    pub(crate) target_range: Range,
+}
+
+/// `[name]{link}` anchor declaration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Anchor {
+    pub(crate) name: String,
+    /// The `[name]` part.
+    pub(crate) range: Range,
+    pub(crate) name_range: Range,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum TodoStatus {
+    Undone,
+    Done,
+    Pending,
+    OnHold,
+    Cancelled,
+    Urgent,
+    Uncertain,
+    Recurring,
+}
+
+impl TodoStatus {
+    pub(crate) const ALL: [Self; 8] = [
+        Self::Undone,
+        Self::Done,
+        Self::Pending,
+        Self::OnHold,
+        Self::Cancelled,
+        Self::Urgent,
+        Self::Uncertain,
+        Self::Recurring,
+    ];
+
+    pub(crate) fn from_char(c: char) -> Option<Self> {
+        Some(match c {
+            ' ' => Self::Undone,
+            'x' => Self::Done,
+            '-' => Self::Pending,
+            '=' => Self::OnHold,
+            '_' => Self::Cancelled,
+            '!' => Self::Urgent,
+            '?' => Self::Uncertain,
+            '+' => Self::Recurring,
+            _ => return None,
+        })
+    }
+
+    pub(crate) fn as_char(self) -> char {
+        match self {
+            Self::Undone => ' ',
+            Self::Done => 'x',
+            Self::Pending => '-',
+            Self::OnHold => '=',
+            Self::Cancelled => '_',
+            Self::Urgent => '!',
+            Self::Uncertain => '?',
+            Self::Recurring => '+',
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Undone => "undone",
+            Self::Done => "done",
+            Self::Pending => "pending",
+            Self::OnHold => "on hold",
+            Self::Cancelled => "cancelled",
+            Self::Urgent => "urgent",
+            Self::Uncertain => "uncertain",
+            Self::Recurring => "recurring",
+        }
+    }
+}
+
+/// A heading or list item carrying a todo extension such as `(x)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Todo {
+    pub(crate) status: TodoStatus,
+    /// Whether the item is a heading rather than a list item.
+    pub(crate) heading: bool,
+    pub(crate) level: u8,
+    pub(crate) title: String,
+    /// The extension, parentheses included.
+    pub(crate) range: Range,
+    /// The status char inside the extension.
+    pub(crate) status_range: Range,
+    /// Due date from a `(< date)` extension.
+    pub(crate) due: Option<String>,
+    /// Start date from a `(> date)` extension.
+    pub(crate) start: Option<String>,
+}
+
+/// Case and whitespace insensitive form of a title, as Neorg compares link targets.
+pub(crate) fn normalize(title: &str) -> String {
+    title
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Columns are counted in `encoding`.
+pub(crate) fn parse(text: &str, encoding: PositionEncoding) -> Structure {
+    let lines = lines(text);
+    let mut scanner = Scanner::new(&lines, encoding);
+    scanner.scan(false);
+    scanner.structure
+}
+
+/// Scans the top-level section at the start of `lines`. Returns its length in
+/// lines and its structure, with lines counted from the section start.
+fn parse_section(lines: &[&str], encoding: PositionEncoding) -> (usize, Structure) {
+    let mut scanner = Scanner::new(lines, encoding);
+    let len = scanner.scan(true);
+    (len, scanner.structure)
+}
+
+/// Scans the top-level section starting at `line` of `rope`, reading lines
+/// from it in growing windows until one holds the whole section.
+fn parse_section_at(rope: &Rope, line: usize, encoding: PositionEncoding) -> (usize, Structure) {
+    let total = rope.len_lines();
+    let mut window = 64;
+    loop {
+        let end = line.saturating_add(window).min(total);
+        let owned = rope_lines(rope, line, end);
+        let lines: Vec<&str> = owned.iter().map(String::as_str).collect();
+        let (len, structure) = parse_section(&lines, encoding);
+        // a section filling the window may go on past it, one ending earlier
+        // was cut by a heading that nothing before it reaches past
+        if len < lines.len() || end == total {
+            return (len, structure);
+        }
+        window *= 2;
+    }
+}
+
+/// Lines `start..end` of `rope` without their line break.
+fn rope_lines(rope: &Rope, start: usize, end: usize) -> Vec<String> {
+    (start..end)
+        .filter_map(|line| rope.get_line(line))
+        .map(|line| String::from(line).trim_end_matches(['\n', '\r']).to_owned())
+        .collect()
+}
+
+/// The sections an edit replaced: `removed` of them from index `at` on, by
+/// sections of `lens` lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Splice {
+    pub(crate) at: usize,
+    pub(crate) removed: usize,
+    pub(crate) lens: Vec<usize>,
+}
+
+/// [`Structure`] of an open document, kept per top-level section so that an
+/// edit only rescans the sections it touches.
+///
+/// A section starts at a level one heading and ends before the next one,
+/// unless a ranged tag or block reaching past that heading joins them. Nothing
+/// in a section then depends on the lines of another, except that its end
+/// depends on the heading starting the next one.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Sections {
+    encoding: PositionEncoding,
+    /// Length in lines and structure of every section, each with lines
+    /// counted from its own start.
+    sections: Vec<(usize, Structure)>,
+}
+
+impl Sections {
+    pub(crate) fn parse(text: &str, encoding: PositionEncoding) -> Self {
+        Self::parse_lines(&lines(text), encoding)
+    }
+
+    fn parse_lines(lines: &[&str], encoding: PositionEncoding) -> Self {
+        let mut sections = Vec::new();
+        let mut line = 0;
+        while line < lines.len() {
+            let (len, structure) = parse_section(&lines[line..], encoding);
+            sections.push((len, structure));
+            line += len;
+        }
+        Self { encoding, sections }
+    }
+
+    /// Updates the sections to `rope`, where lines `first..=new_last` replaced
+    /// lines `first..=old_last` of the previous text. Only the lines of the
+    /// rescanned sections are read from the rope.
+    pub(crate) fn edit(
+        &mut self,
+        rope: &Rope,
+        first: usize,
+        old_last: usize,
+        new_last: usize,
+    ) -> Splice {
+        let total = rope.len_lines();
+        let starts: Vec<usize> = self
+            .sections
+            .iter()
+            .scan(0, |start, (len, _)| {
+                let section = *start;
+                *start += len;
+                Some(section)
+            })
+            .collect();
+        let old_len: usize = self.sections.iter().map(|(len, _)| len).sum();
+        let old_count = self.sections.len();
+        if first > old_last || first > new_last || old_last >= old_len || new_last >= total {
+            let owned = rope_lines(rope, 0, total);
+            let lines: Vec<&str> = owned.iter().map(String::as_str).collect();
+            *self = Self::parse_lines(&lines, self.encoding);
+            return Splice {
+                at: 0,
+                removed: old_count,
+                lens: self.lens(),
+            };
+        }
+
+        // the section holding `first`, or the one before when `first` starts
+        // a section, as that section ends depending on it
+        let mut from = starts.partition_point(|start| *start <= first) - 1;
+        if starts[from] == first && from > 0 {
+            from -= 1;
+        }
+        let to = starts.partition_point(|start| *start <= old_last) - 1;
+
+        // sections after the edit, with their start in `rope`
+        let delta = new_last as isize - old_last as isize;
+        let mut after = starts[to + 1..]
+            .iter()
+            .map(|start| start.saturating_add_signed(delta))
+            .zip(self.sections.drain(to + 1..).collect::<Vec<_>>())
+            .peekable();
+        self.sections.truncate(from);
+
+        // rescan until a section boundary lines up with an untouched section
+        let mut removed = to + 1 - from;
+        let mut line = starts[from];
+        while line < total {
+            while after.next_if(|(start, _)| *start < line).is_some() {
+                removed += 1;
+            }
+            if after.peek().is_some_and(|(start, _)| *start == line) {
+                break;
+            }
+            let (len, structure) = parse_section_at(rope, line, self.encoding);
+            self.sections.push((len, structure));
+            line += len;
+        }
+        let lens = self.sections[from..].iter().map(|(len, _)| *len).collect();
+        if line < total {
+            self.sections.extend(after.map(|(_, section)| section));
+        } else {
+            removed = old_count - from;
+        }
+        Splice {
+            at: from,
+            removed,
+            lens,
+        }
+    }
+
+    /// Length in lines of every section.
+    pub(crate) fn lens(&self) -> Vec<usize> {
+        self.sections.iter().map(|(len, _)| *len).collect()
+    }
+
+    pub(crate) fn structure(&self) -> Structure {
+        let mut structure = Structure::default();
+        let mut offset = 0;
+        for (len, section) in &self.sections {
+            structure.append(section, offset as u32);
+            offset += len;
+        }
+        structure
+    }
+}
+
+impl Structure {
+    /// What the item carrying `todo` spans: the section of a heading, or a
+    /// list item with its nested items.
+    pub(crate) fn todo_extent(&self, todo: &Todo) -> Option<Range> {
+        let line = todo.range.start.line;
+        if todo.heading {
+            let heading = self.headings.iter().find(|h| h.range.start.line == line)?;
+            return Some(heading.section);
+        }
+        self.blocks
+            .iter()
+            .find(|block| {
+                matches!(
+                    block.kind,
+                    BlockKind::UnorderedList | BlockKind::OrderedList
+                ) && block.marker_range.start.line == line
+            })
+            .map(|block| block.range)
+    }
+
+    /// The todos nested below `todo`, at any depth.
+    pub(crate) fn subtodos<'a>(&'a self, todo: &Todo) -> impl Iterator<Item = &'a Todo> {
+        let line = todo.range.start.line;
+        let extent = self.todo_extent(todo);
+        self.todos.iter().filter(move |sub| {
+            let start = sub.range.start;
+            start.line > line
+                && extent.is_some_and(|extent| extent.start <= start && start <= extent.end)
+        })
+    }
+
+    /// Appends `other`, whose lines start at line `offset` of `self`.
+    fn append(&mut self, other: &Self, offset: u32) {
+        let shift = |range: Range| {
+            Range::new(
+                Position::new(range.start.line + offset, range.start.character),
+                Position::new(range.end.line + offset, range.end.character),
+            )
+        };
+        self.headings
+            .extend(other.headings.iter().map(|heading| Heading {
+                range: shift(heading.range),
+                title_range: shift(heading.title_range),
+                section: shift(heading.section),
+                ..heading.clone()
+            }));
+        self.blocks.extend(other.blocks.iter().map(|block| Block {
+            marker_range: shift(block.marker_range),
+            title_range: shift(block.title_range),
+            range: shift(block.range),
+            ..block.clone()
+        }));
+        self.tags.extend(other.tags.iter().map(|tag| Tag {
+            name_range: shift(tag.name_range),
+            range: shift(tag.range),
+            ..tag.clone()
+        }));
+        self.links.extend(other.links.iter().map(|link| Link {
+            range: shift(link.range),
+            file_range: link.file_range.map(shift),
+            target_range: shift(link.target_range),
+            ..link.clone()
+        }));
+        self.anchors
+            .extend(other.anchors.iter().map(|anchor| Anchor {
+                range: shift(anchor.range),
+                name_range: shift(anchor.name_range),
+                ..anchor.clone()
+            }));
+        self.todos.extend(other.todos.iter().map(|todo| Todo {
+            range: shift(todo.range),
+            status_range: shift(todo.status_range),
+            ..todo.clone()
+        }));
+        if self.title.is_none() {
+            self.title.clone_from(&other.title);
+        }
+    }
+}
+
+struct Scanner<'a> {
+    lines: &'a [&'a str],
+    structure: Structure,
+    encoding: PositionEncoding,
+    /// Last line a ranged tag or block looked at to find its end.
+    reach: usize,
+}
+
+/// Leading run of a detached modifier character followed by whitespace.
+pub(crate) struct Prefix {
+    pub(crate) char: char,
+    pub(crate) level: usize,
+    pub(crate) indent: usize,
+    /// Byte length of indentation, modifier and the whitespace after it.
+    pub(crate) len: usize,
+}
+
+pub(crate) fn detached_prefix(line: &str) -> Option<Prefix> {
+    let trimmed = line.trim_start();
+    let indent = line.len() - trimmed.len();
+    let char = trimmed.chars().next()?;
+    if !matches!(char, '*' | '-' | '~' | '>' | '$' | '^' | ':') {
+        return None;
+    }
+    let level = trimmed.chars().take_while(|c| *c == char).count();
+    let rest = &trimmed[level..];
+    let space = rest.chars().next().filter(|c| *c == ' ' || *c == '\t')?;
+    Some(Prefix {
+        char,
+        level,
+        indent,
+        len: indent + level + space.len_utf8(),
+    })
+}
+
+/// Whether `line` is a level one heading.
+fn starts_section(line: &str) -> bool {
+    detached_prefix(line).is_some_and(|prefix| prefix.char == '*' && prefix.level == 1)
+}
+
+/// `@name params`, `|name`, `=name`, `#name` or `+name` at the start of a line.
+pub(crate) fn tag_line(line: &str) -> Option<(TagKind, usize, &str)> {
+    let trimmed = line.trim_start();
+    let indent = line.len() - trimmed.len();
+    let kind = match trimmed.chars().next()? {
+        '@' => TagKind::Ranged,
+        '|' => TagKind::Standard,
+        '=' => TagKind::Macro,
+        '#' => TagKind::Weak,
+        '+' => TagKind::Strong,
+        _ => return None,
+    };
+    let rest = &trimmed[1..];
+    rest.chars()
+        .next()
+        .filter(|c| c.is_alphanumeric())
+        .map(|_| (kind, indent, rest))
+}
+
+impl<'a> Scanner<'a> {
+    fn position(&self, line: u32, text: &str, byte: usize) -> Position {
+        Position::new(line, self.encoding.column(text, byte))
+    }
+
+    fn span(&self, line: u32, text: &str, start: usize, end: usize) -> Range {
+        Range::new(
+            self.position(line, text, start),
+            self.position(line, text, end),
+        )
+    }
+
+    fn end_of(&self, line: usize) -> Position {
+        let text = self.lines.get(line).copied().unwrap_or_default();
+        self.position(line as u32, text, text.len())
+    }
+
+    fn new(lines: &'a [&'a str], encoding: PositionEncoding) -> Self {
+        Self {
+            lines,
+            structure: Structure::default(),
+            encoding,
+            reach: 0,
+        }
+    }
+
+    /// Scans every line, or with `split` only up to the next top-level
+    /// section. Returns the number of lines scanned.
+    fn scan(&mut self, split: bool) -> usize {
+        let mut line = 0;
+        while line < self.lines.len() {
+            if split && line > 0 && self.reach < line && starts_section(self.lines[line]) {
+                break;
+            }
+            line = self.scan_line(line);
+        }
+        let len = line.min(self.lines.len());
+        self.close_sections(len.saturating_sub(1));
+        len
+    }
+
+    /// Handles `line` and returns the next line to look at.
+    fn scan_line(&mut self, n: usize) -> usize {
+        let text = self.lines[n];
+
+        if let Some((kind, indent, rest)) = tag_line(text) {
+            return self.scan_tag(n, kind, indent, rest);
+        }
+
+        if let Some(prefix) = detached_prefix(text) {
+            let title_start = prefix.len;
+            match prefix.char {
+                '*' => self.push_heading(n, &prefix),
+                '$' | '^' | ':' if prefix.level == 2 => {
+                    return self.scan_ranged_block(n, &prefix);
+                }
+                _ => self.push_block(n, &prefix),
+            }
+            self.scan_inline(n, title_start);
+            return n + 1;
+        }
+
+        self.scan_inline(n, 0);
+        n + 1
+    }
+
+    fn scan_tag(&mut self, n: usize, kind: TagKind, indent: usize, rest: &str) -> usize {
+        let text = self.lines[n];
+        let mut words = rest.split_whitespace();
+        let name = words.next().unwrap_or_default().to_owned();
+        let parameters = words.map(str::to_owned).collect();
+        let name_start = indent + 1;
+        let name_range = self.span(n as u32, text, name_start, name_start + name.len());
+
+        if !kind.is_ranged() {
+            self.structure.tags.push(Tag {
+                kind,
+                name,
+                parameters,
+                name_range,
+                range: self.span(n as u32, text, indent, text.len()),
+            });
+            return n + 1;
+        }
+
+        if name == "end" {
+            // a stray `end`, nothing to close
+            return n + 1;
+        }
+
+        let end_marker = format!("{}end", kind.prefix());
+        let verbatim = kind != TagKind::Standard;
+        let mut depth = 0;
+        let mut end = self.lines.len() - 1;
+        let mut line = n + 1;
+        while line < self.lines.len() {
+            let trimmed = self.lines[line].trim();
+            if trimmed == end_marker {
+                if depth == 0 {
+                    end = line;
+                    break;
+                }
+                depth -= 1;
+            } else if !verbatim && let Some((TagKind::Standard, ..)) = tag_line(self.lines[line]) {
+                depth += 1;
+            }
+            line += 1;
+        }
+        self.reach = self.reach.max(end);
+
+        if name == "document.meta" && self.structure.title.is_none() {
+            self.structure.title = self.lines[n + 1..=end].iter().find_map(|line| {
+                let title = line.trim().strip_prefix("title:")?.trim();
+                (!title.is_empty()).then(|| title.to_owned())
+            });
+        }
+
+        self.structure.tags.push(Tag {
+            kind,
+            name,
+            parameters,
+            name_range,
+            range: Range::new(self.position(n as u32, text, indent), self.end_of(end)),
+        });
+
+        if verbatim {
+            end + 1
+        } else {
+            // the content of standard tags is regular markup
+            n + 1
+        }
+    }
+
+    fn push_heading(&mut self, n: usize, prefix: &Prefix) {
+        let text = self.lines[n];
+        let (title_start, todo) = self.scan_todo(n, prefix, true);
+        let title = text[title_start..].trim_end();
+        self.structure.headings.push(Heading {
+            level: prefix.level.min(u8::MAX as usize) as u8,
+            title: title.to_owned(),
+            range: self.span(n as u32, text, prefix.indent, text.trim_end().len()),
+            title_range: self.span(n as u32, text, title_start, title_start + title.len()),
+            // fixed up in `close_sections`
+            section: Range::default(),
+        });
+        if let Some(todo) = todo {
+            self.structure.todos.push(todo);
+        }
+    }
+
+    fn push_block(&mut self, n: usize, prefix: &Prefix) {
+        let text = self.lines[n];
+        let kind = match prefix.char {
+            '-' => BlockKind::UnorderedList,
+            '~' => BlockKind::OrderedList,
+            '>' => BlockKind::Quote,
+            '$' => BlockKind::Definition,
+            '^' => BlockKind::Footnote,
+            _ => BlockKind::Table,
+        };
+        let (title_start, todo) = match kind {
+            BlockKind::UnorderedList | BlockKind::OrderedList => self.scan_todo(n, prefix, false),
+            _ => (prefix.len, None),
+        };
+        let end = self.block_end(n, prefix);
+        let title = text[title_start..].trim_end();
+        self.structure.blocks.push(Block {
+            kind,
+            level: prefix.level.min(u8::MAX as usize) as u8,
+            title: title.to_owned(),
+            marker_range: self.span(n as u32, text, prefix.indent, prefix.len),
+            title_range: self.span(n as u32, text, title_start, title_start + title.len()),
+            range: Range::new(
+                self.position(n as u32, text, prefix.indent),
+                self.end_of(end),
+            ),
+        });
+        if let Some(todo) = todo {
+            self.structure.todos.push(todo);
+        }
+    }
+
+    /// Last line belonging to the item on line `n`: its paragraph and any
+    /// deeper nested items of the same family.
+    fn block_end(&self, n: usize, prefix: &Prefix) -> usize {
+        let nests = |other: &Prefix| match prefix.char {
+            '-' | '~' => matches!(other.char, '-' | '~') && other.level > prefix.level,
+            '>' => other.char == '>' && other.level > prefix.level,
+            _ => false,
+        };
+        let mut end = n;
+        for (line, text) in self.lines.iter().enumerate().skip(n + 1) {
+            if text.trim().is_empty() || tag_line(text).is_some() {
+                break;
+            }
+            match detached_prefix(text) {
+                Some(other) if !nests(&other) => break,
+                _ => end = line,
+            }
+        }
+        end
+    }
+
+    fn scan_ranged_block(&mut self, n: usize, prefix: &Prefix) -> usize {
+        let text = self.lines[n];
+        let kind = match prefix.char {
+            '$' => BlockKind::Definition,
+            '^' => BlockKind::Footnote,
+            _ => BlockKind::Table,
+        };
+        let closing: String = std::iter::repeat_n(prefix.char, 2).collect();
+        let end = (n + 1..self.lines.len())
+            .find(|line| self.lines[*line].trim() == closing)
+            .unwrap_or(self.lines.len() - 1);
+        self.reach = self.reach.max(end);
+        let title = text[prefix.len..].trim_end();
+        self.structure.blocks.push(Block {
+            kind,
+            level: 1,
+            title: title.to_owned(),
+            marker_range: self.span(n as u32, text, prefix.indent, prefix.len),
+            title_range: self.span(n as u32, text, prefix.len, prefix.len + title.len()),
+            range: Range::new(
+                self.position(n as u32, text, prefix.indent),
+                self.end_of(end),
+            ),
+        });
+        self.scan_inline(n, prefix.len);
+        let mut line = n + 1;
+        while line < end {
+            line = self.scan_line(line);
+        }
+        line.max(end + 1)
+    }
+
+    /// Parses a `(x)` style extension after the modifier on line `n`. Returns
+    /// where the title starts and the todo, if any.
+    fn scan_todo(&self, n: usize, prefix: &Prefix, heading: bool) -> (usize, Option<Todo>) {
+        let text = self.lines[n];
+        let rest = &text[prefix.len..];
+        let Some(inner) = rest.strip_prefix('(') else {
+            return (prefix.len, None);
+        };
+        let Some(close) = inner.find(')') else {
+            return (prefix.len, None);
+        };
+
+        let mut status = None;
+        let (mut due, mut start) = (None, None);
+        let mut offset = prefix.len + 1;
+        for item in inner[..close].split('|') {
+            let mut chars = item.chars();
+            match (chars.next(), chars.as_str().trim()) {
+                (Some('<'), date) => due = Some(date.to_owned()),
+                (Some('>'), date) => start = Some(date.to_owned()),
+                (Some(c), "") => {
+                    if let Some(found) = TodoStatus::from_char(c) {
+                        status = Some((found, offset, offset + c.len_utf8()));
+                    }
+                }
+                _ => {}
+            }
+            offset += item.len() + 1;
+        }
+        let Some((status, status_start, status_end)) = status else {
+            return (prefix.len, None);
+        };
+
+        let end = prefix.len + 1 + close + 1;
+        let title_start = end + (text[end..].len() - text[end..].trim_start().len());
+        let todo = Todo {
+            status,
+            heading,
+            level: prefix.level.min(u8::MAX as usize) as u8,
+            title: text[title_start..].trim_end().to_owned(),
+            range: self.span(n as u32, text, prefix.len, end),
+            status_range: self.span(n as u32, text, status_start, status_end),
+            due,
+            start,
+        };
+        (title_start, Some(todo))
+    }
+
+    /// Links and anchors on line `n`, starting at byte `from`.
+    fn scan_inline(&mut self, n: usize, from: usize) {
+        let text = self.lines[n];
+        let line = n as u32;
+        let bytes = text.as_bytes();
+        let mut i = from;
+        let mut verbatim = false;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' => i += 1,
+                b'`' => verbatim = !verbatim,
+                b'{' if !verbatim => {
+                    if let Some(close) = text[i..].find('}') {
+                        let content = &text[i + 1..i + close];
+                        if let Some(link) = self.parse_link(line, text, i + 1, content) {
+                            self.structure.links.push(Link {
+                                range: self.span(line, text, i, i + close + 1),
+                                ..link
+                            });
+                        }
+                        i += close;
+                        // a description belongs to the link, not an anchor
+                        if text[i + 1..].starts_with('[')
+                            && let Some(end) = text[i + 1..].find(']')
+                        {
+                            i += end + 1;
+                        }
+                    }
+                }
+                b'[' if !verbatim => {
+                    if let Some(close) = text[i..].find(']') {
+                        let name = &text[i + 1..i + close];
+                        let trimmed = name.trim();
+                        let name_start = i + 1 + (name.len() - name.trim_start().len());
+                        let name_range =
+                            self.span(line, text, name_start, name_start + trimmed.len());
+                        let range = self.span(line, text, i, i + close + 1);
+                        if !trimmed.is_empty() {
+                            if text[i + close + 1..].starts_with('{') {
+                                self.structure.anchors.push(Anchor {
+                                    name: trimmed.to_owned(),
+                                    range,
+                                    name_range,
+                                });
+                            } else {
+                                self.structure.links.push(Link {
+                                    kind: LinkKind::Anchor,
+                                    file: None,
+                                    file_range: None,
+                                    target: trimmed.to_owned(),
+                                    range,
+                                    target_range: name_range,
+                                });
+                            }
+                        }
+                        i += close;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+
+    /// Extends every heading's section to the line before the next heading of
+    /// equal or higher level, or to `last`.
+    fn close_sections(&mut self, last: usize) {
+        let starts: Vec<(u32, u8)> = self
+            .structure
+            .headings
+            .iter()
+            .map(|heading| (heading.range.start.line, heading.level))
+            .collect();
+        for (i, heading) in self.structure.headings.iter_mut().enumerate() {
+            let end = starts[i + 1..]
+                .iter()
+                .find(|(_, level)| *level <= heading.level)
+                .map_or(last, |(line, _)| (*line as usize).saturating_sub(1));
+            let text = self.lines.get(end).copied().unwrap_or_default();
+            heading.section = Range::new(
+                Position::new(heading.range.start.line, 0),
+                Position::new(end as u32, self.encoding.column(text, text.len())),
+            );
+        }
+    }
+
+    /// Parses the inside of `{...}`; `start` is the byte offset of `content` in `text`.
+    fn parse_link(&self, line: u32, text: &str, start: usize, content: &str) -> Option<Link> {
+        let mut offset = start;
+        let mut rest = content;
+        let mut file = None;
+        let mut file_range = None;
+        if let Some(after) = rest.strip_prefix(':') {
+            let end = after.find(':')?;
+            let name = after[..end].trim();
+            let file_start = start + 1 + (end - after[..end].trim_start().len());
+            file = Some(name.to_owned());
+            file_range = Some(self.span(line, text, file_start, file_start + name.len()));
+            offset += 1 + end + 1;
+            rest = &after[end + 1..];
+        }
+
+        let trimmed = rest.trim_start();
+        offset += rest.len() - trimmed.len();
+
+        let (kind, skip) = match trimmed.chars().next() {
+            None if file.is_some() => (LinkKind::File, 0),
+            None => return None,
+            Some('*') => {
+                let level = trimmed.chars().take_while(|c| *c == '*').count();
+                (LinkKind::Heading(level.min(u8::MAX as usize) as u8), level)
+            }
+            Some('#') => (LinkKind::Generic, 1),
+            Some('^') => (LinkKind::Footnote, 1),
+            Some('$') => (LinkKind::Definition, 1),
+            Some('/') => (LinkKind::Path, 1),
+            Some('?') => (LinkKind::Wiki, 1),
+            Some('@') => (LinkKind::Timestamp, 1),
+            Some(_) => (LinkKind::Url, 0),
+        };
+
+        let after = &trimmed[skip..];
+        let target = after.trim();
+        let target_start = offset + skip + (after.len() - after.trim_start().len());
+        Some(Link {
+            kind,
+            file,
+            file_range,
+            target: target.to_owned(),
+            range: Range::default(),
+            target_range: self.span(line, text, target_start, target_start + target.len()),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::testing::Rng;
+
+    pub(crate) const LINES: &[&str] = &[
+        "* Heading",
+        "** Sub heading",
+        "*** ( ) Task",
+        "* (x|< 2024-01-01) Done",
+        "- item",
+        "-- nested {* heading}",
+        "~ ordered",
+        "> quote",
+        "$ term",
+        "$$ long term",
+        "$$",
+        "^ note",
+        "^^ footnote",
+        "::",
+        ":: table",
+        "@code rust",
+        "* inside code",
+        "@end",
+        "|example",
+        "|end",
+        "#comment",
+        "+name x",
+        "see {:file:* Heading} and [anchor]{# sub heading}",
+        "[anchor] again",
+        "`{* not a link}`",
+        "plain text",
+        "",
+        "   ",
+    ];
+
+    /// Snippets inserted by edits, to break and join lines and markup.
+    pub(crate) const INSERTS: &[&str] = &[
+        "",
+        "\n",
+        "\n\n",
+        "* ",
+        "** ",
+        "- ",
+        "@code",
+        "@end",
+        "|end",
+        "$$",
+        "{",
+        "}",
+        "[",
+        "]",
+        "(x) ",
+        "word",
+        "\n* New\n",
+        "\n@code\n",
+        "\n@end\n",
+    ];
+
+    pub(crate) fn document(rng: &mut Rng) -> String {
+        let lines = rng.below(60);
+        (0..lines)
+            .map(|_| rng.pick(LINES))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub(crate) fn line_of(text: &str, byte: usize) -> usize {
+        text[..byte].matches('\n').count()
+    }
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+        Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+    }
+
+    #[test]
+    fn headings_span_their_sections() {
+        let text = "* One\n text\n** Two \n*** Three\n* Four\n";
+        let structure = parse(text, PositionEncoding::Utf16);
+        let headings: Vec<_> = structure
+            .headings
+            .iter()
+            .map(|h| (h.level, h.title.as_str(), h.range, h.title_range, h.section))
+            .collect();
+        assert_eq!(
+            headings,
+            [
+                (
+                    1,
+                    "One",
+                    range((0, 0), (0, 5)),
+                    range((0, 2), (0, 5)),
+                    range((0, 0), (3, 9))
+                ),
+                (
+                    2,
+                    "Two",
+                    range((2, 0), (2, 6)),
+                    range((2, 3), (2, 6)),
+                    range((2, 0), (3, 9))
+                ),
+                (
+                    3,
+                    "Three",
+                    range((3, 0), (3, 9)),
+                    range((3, 4), (3, 9)),
+                    range((3, 0), (3, 9))
+                ),
+                (
+                    1,
+                    "Four",
+                    range((4, 0), (4, 6)),
+                    range((4, 2), (4, 6)),
+                    range((4, 0), (5, 0))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn blocks_span_nested_items_and_ranged_bodies() {
+        let text = "- a\n  wrapped\n-- b\n~ c\n> d\n>> e\n$$ term\nbody\n$$\n^ note\n";
+        let structure = parse(text, PositionEncoding::Utf16);
+        let blocks: Vec<_> = structure
+            .blocks
+            .iter()
+            .map(|block| (block.kind, block.level, block.title.as_str(), block.range))
+            .collect();
+        assert_eq!(
+            blocks,
+            [
+                (BlockKind::UnorderedList, 1, "a", range((0, 0), (2, 4))),
+                (BlockKind::UnorderedList, 2, "b", range((2, 0), (2, 4))),
+                (BlockKind::OrderedList, 1, "c", range((3, 0), (3, 3))),
+                (BlockKind::Quote, 1, "d", range((4, 0), (5, 4))),
+                (BlockKind::Quote, 2, "e", range((5, 0), (5, 4))),
+                (BlockKind::Definition, 1, "term", range((6, 0), (8, 2))),
+                (BlockKind::Footnote, 1, "note", range((9, 0), (9, 6))),
+            ]
+        );
+        assert_eq!(structure.blocks[0].marker_range, range((0, 0), (0, 2)));
+    }
+
+    #[test]
+    fn tags_span_up_to_their_end() {
+        let text = "#comment\n+name a b\n@code rust\n* not a heading\n@end\n|example\n|nested\n|end\n|end\n@math\nx\n";
+        let structure = parse(text, PositionEncoding::Utf16);
+        let tags: Vec<_> = structure
+            .tags
+            .iter()
+            .map(|tag| {
+                (
+                    tag.kind,
+                    tag.name.as_str(),
+                    tag.parameters.join(" "),
+                    tag.range,
+                )
+            })
+            .collect();
+        assert_eq!(
+            tags,
+            [
+                (
+                    TagKind::Weak,
+                    "comment",
+                    String::new(),
+                    range((0, 0), (0, 8))
+                ),
+                (
+                    TagKind::Strong,
+                    "name",
+                    "a b".to_owned(),
+                    range((1, 0), (1, 9))
+                ),
+                (
+                    TagKind::Ranged,
+                    "code",
+                    "rust".to_owned(),
+                    range((2, 0), (4, 4))
+                ),
+                (
+                    TagKind::Standard,
+                    "example",
+                    String::new(),
+                    range((5, 0), (8, 4))
+                ),
+                (
+                    TagKind::Standard,
+                    "nested",
+                    String::new(),
+                    range((6, 0), (7, 4))
+                ),
+                // unterminated, up to the end of the document
+                (
+                    TagKind::Ranged,
+                    "math",
+                    String::new(),
+                    range((9, 0), (11, 0))
+                ),
+            ]
+        );
+        assert_eq!(structure.tags[2].name_range, range((2, 1), (2, 5)));
+        assert!(structure.headings.is_empty());
+    }
+
+    #[test]
+    fn titles_come_from_the_metadata() {
+        let title = |text: &str| parse(text, PositionEncoding::Utf16).title;
+        let meta = "@document.meta\ntitle:  A title \n@end\n* Heading\n";
+        assert_eq!(title(meta), Some("A title".to_owned()));
+        assert_eq!(title("* Heading\ntitle: no meta\n"), None);
+        assert_eq!(title("@document.meta\ntitle:\n@end\n"), None);
+        // unterminated, at the end of the document
+        assert_eq!(title("@document.meta"), None);
+        let sections = Sections::parse(meta, PositionEncoding::Utf16);
+        assert_eq!(sections.structure().title, Some("A title".to_owned()));
+    }
+
+    #[test]
+    fn links_anchors_and_todos() {
+        let text = "- ( |< 5th Feb 2024) Écrire {:notes:** Plan} [site]{https://a.b}\n[site] `{* code}` {/ ./x.txt} {$ term}\n";
+        let structure = parse(text, PositionEncoding::Utf16);
+        let links: Vec<_> = structure
+            .links
+            .iter()
+            .map(|link| {
+                let file = link.file.as_deref();
+                (
+                    link.kind,
+                    file,
+                    link.target.as_str(),
+                    link.range,
+                    link.target_range,
+                )
+            })
+            .collect();
+        assert_eq!(
+            links,
+            [
+                (
+                    LinkKind::Heading(2),
+                    Some("notes"),
+                    "Plan",
+                    range((0, 28), (0, 44)),
+                    range((0, 39), (0, 43))
+                ),
+                (
+                    LinkKind::Url,
+                    None,
+                    "https://a.b",
+                    range((0, 51), (0, 64)),
+                    range((0, 52), (0, 63))
+                ),
+                (
+                    LinkKind::Anchor,
+                    None,
+                    "site",
+                    range((1, 0), (1, 6)),
+                    range((1, 1), (1, 5))
+                ),
+                (
+                    LinkKind::Path,
+                    None,
+                    "./x.txt",
+                    range((1, 18), (1, 29)),
+                    range((1, 21), (1, 28))
+                ),
+                (
+                    LinkKind::Definition,
+                    None,
+                    "term",
+                    range((1, 30), (1, 38)),
+                    range((1, 33), (1, 37))
+                ),
+            ]
+        );
+        assert_eq!(structure.links[0].file_range, Some(range((0, 30), (0, 35))));
+
+        let anchors: Vec<_> = structure
+            .anchors
+            .iter()
+            .map(|a| (a.name.as_str(), a.range))
+            .collect();
+        assert_eq!(anchors, [("site", range((0, 45), (0, 51)))]);
+
+        let todo = &structure.todos[0];
+        assert_eq!(
+            (
+                todo.status,
+                todo.heading,
+                todo.title.as_str(),
+                todo.due.as_deref()
+            ),
+            (
+                TodoStatus::Undone,
+                false,
+                "Écrire {:notes:** Plan} [site]{https://a.b}",
+                Some("5th Feb 2024")
+            )
+        );
+        assert_eq!(
+            (todo.range, todo.status_range),
+            (range((0, 2), (0, 20)), range((0, 3), (0, 4)))
+        );
+    }
+
+    #[test]
+    fn columns_follow_the_encoding() {
+        let text = "* 𝄞é {* 𝄞}\n";
+        let title = |encoding| parse(text, encoding).links[0].target_range;
+        assert_eq!(title(PositionEncoding::Utf8), range((0, 12), (0, 16)));
+        assert_eq!(title(PositionEncoding::Utf16), range((0, 9), (0, 11)));
+        assert_eq!(title(PositionEncoding::Utf32), range((0, 8), (0, 9)));
+    }
+
+    #[test]
+    fn subtodos_are_nested_below_the_item() {
+        let text =
+            "* ( ) Plan\n- (x) first\n-- ( ) nested\n- (?) second\n* (-) Next\n- ( ) other\n";
+        let structure = parse(text, PositionEncoding::Utf16);
+        let titles = |todo: &Todo| {
+            structure
+                .subtodos(todo)
+                .map(|sub| sub.title.as_str())
+                .collect::<Vec<_>>()
+        };
+        let todos = &structure.todos;
+        assert_eq!(titles(&todos[0]), ["first", "nested", "second"]);
+        assert_eq!(titles(&todos[1]), ["nested"]);
+        assert!(titles(&todos[2]).is_empty());
+        assert_eq!(titles(&todos[4]), ["other"]);
+    }
+
+    #[test]
+    fn edits_read_long_sections_past_the_first_window() {
+        let body = "- item\n".repeat(100);
+        let before = format!("* One\n{body}* Two\n{body}* Three\n");
+        let after = format!("* One\n@code\n{body}* Two\n{body}@end\n* Three\n");
+        let mut sections = Sections::parse(&before, PositionEncoding::Utf16);
+        sections.edit(&Rope::from_str(&after), 1, 1, 2);
+        assert_eq!(sections.structure(), parse(&after, PositionEncoding::Utf16));
+        assert_eq!(sections.lens(), [204, 2]);
+    }
+
+    /// Random edits, each rescanning only the sections around it, leave the
+    /// same outline as scanning the edited text from scratch.
+    #[test]
+    fn incremental_matches_full_parse() {
+        for seed in 1..300 {
+            let mut rng = Rng(seed);
+            let mut text = document(&mut rng);
+            let mut sections = Sections::parse(&text, PositionEncoding::Utf16);
+            assert_eq!(
+                sections.structure(),
+                parse(&text, PositionEncoding::Utf16),
+                "seed {seed}: {text:?}"
+            );
+
+            for _ in 0..40 {
+                // every snippet is ascii, so any byte offset is a char boundary
+                let start = rng.below(text.len() + 1);
+                let end = start + rng.below((text.len() - start).min(30) + 1);
+                let insert = if rng.below(3) == 0 {
+                    format!("\n{}", rng.pick(LINES))
+                } else {
+                    rng.pick(INSERTS).to_owned()
+                };
+
+                let first = line_of(&text, start);
+                let old_last = line_of(&text, end);
+                text.replace_range(start..end, &insert);
+                let new_last = line_of(&text, start + insert.len());
+
+                sections.edit(&Rope::from_str(&text), first, old_last, new_last);
+                assert_eq!(
+                    sections.structure(),
+                    parse(&text, PositionEncoding::Utf16),
+                    "seed {seed}: {text:?}"
+                );
+            }
+        }
+    }
+}
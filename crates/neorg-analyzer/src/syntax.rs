@@ -0,0 +1,242 @@
+//! CST of an open document, kept per top-level section so that an edit only
+//! reparses the sections it touches.
+//!
+//! The sections are those the index splits the document into, see
+//! [`Sections`](crate::structure::Sections): each starts at a level one
+//! heading, unless a ranged tag or block reaching past the heading joins it to
+//! the one before. `neorg_syntax` parses every section as a document of its
+//! own, with lines counted from the section's first line and columns in
+//! chars; [`Syntax`] moves what it reads from the trees to the lines of the
+//! document and the negotiated encoding.
+
+use neorg_syntax::{SyntaxKind, SyntaxNode, get_diagnostics, get_kinds};
+use ropey::Rope;
+use tower_lsp::lsp_types::{Diagnostic, Position, Range};
+
+use crate::span::PositionEncoding;
+use crate::structure::Splice;
+
+#[derive(Debug, Default)]
+pub(crate) struct Syntax {
+    /// Length in lines and tree of every section, `None` from the edit that
+    /// replaced it until the next [`Syntax::parse`].
+    sections: Vec<(usize, Option<SyntaxNode>)>,
+}
+
+/// A word node of the CST.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Word {
+    pub(crate) text: String,
+    pub(crate) range: Range,
+}
+
+impl Syntax {
+    /// Sections of `lens` lines, none of them parsed yet.
+    pub(crate) fn new(lens: Vec<usize>) -> Self {
+        Self {
+            sections: lens.into_iter().map(|len| (len, None)).collect(),
+        }
+    }
+
+    /// Drops the trees of the sections an edit replaced.
+    pub(crate) fn splice(&mut self, splice: Splice) {
+        let at = splice.at.min(self.sections.len());
+        let end = at.saturating_add(splice.removed).min(self.sections.len());
+        let added = splice.lens.into_iter().map(|len| (len, None));
+        self.sections.splice(at..end, added);
+    }
+
+    /// Parses the sections of `rope` that have no tree, and only those.
+    /// Returns how many it parsed.
+    pub(crate) fn parse(&mut self, rope: &Rope) -> usize {
+        let mut parsed = 0;
+        let mut start = 0;
+        for (len, node) in &mut self.sections {
+            if node.is_none() {
+                let text = section_text(rope, start, *len);
+                *node = Some(neorg_syntax::cst!(&text));
+                parsed += 1;
+            }
+            start += *len;
+        }
+        parsed
+    }
+
+    /// Parsed sections, with their first line.
+    fn trees(&self) -> impl Iterator<Item = (u32, &SyntaxNode)> {
+        let mut start = 0;
+        self.sections.iter().filter_map(move |(len, node)| {
+            let first = start as u32;
+            start += len;
+            Some((first, node.as_ref()?))
+        })
+    }
+
+    /// Word nodes, with ranges in `rope`, the text the trees were parsed
+    /// from.
+    pub(crate) fn words(&self, rope: &Rope, encoding: PositionEncoding) -> Vec<Word> {
+        self.trees()
+            .flat_map(|(first, node)| {
+                get_kinds(SyntaxKind::Word, node.clone())
+                    .into_iter()
+                    .map(move |word| Word {
+                        text: word.text().clone(),
+                        range: locate(rope, first, word.range(), encoding),
+                    })
+            })
+            .collect()
+    }
+
+    /// Syntax errors, with ranges in `rope`.
+    pub(crate) fn diagnostics(&self, rope: &Rope, encoding: PositionEncoding) -> Vec<Diagnostic> {
+        self.trees()
+            .flat_map(|(first, node)| {
+                get_diagnostics(node.clone())
+                    .into_iter()
+                    .map(move |diagnostic| Diagnostic {
+                        range: locate(rope, first, diagnostic.range, encoding),
+                        ..diagnostic
+                    })
+            })
+            .collect()
+    }
+}
+
+/// Lines `start..start + len` of `rope`, with their line breaks.
+fn section_text(rope: &Rope, start: usize, len: usize) -> String {
+    let end = start.saturating_add(len).min(rope.len_lines());
+    let start = start.min(end);
+    rope.slice(rope.line_to_char(start)..rope.line_to_char(end))
+        .into()
+}
+
+/// `range` of a tree parsed from line `first` of `rope` on, in `rope` and
+/// `encoding`.
+pub(crate) fn locate(rope: &Rope, first: u32, range: Range, encoding: PositionEncoding) -> Range {
+    let locate = |position: Position| {
+        let line = position.line + first;
+        encoding.from_chars(rope, Position::new(line, position.character))
+    };
+    Range::new(locate(range.start), locate(range.end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structure::Sections;
+    use crate::structure::tests::{INSERTS, LINES, document, line_of};
+    use crate::testing::Rng;
+
+    /// What the analyzer reads from a CST, with ranges in the document.
+    fn observed(syntax: &Syntax, rope: &Rope) -> (String, Vec<Word>, Vec<Diagnostic>) {
+        let text = syntax
+            .trees()
+            .map(|(_, node)| node.text().as_str())
+            .collect();
+        let encoding = PositionEncoding::Utf16;
+        (
+            text,
+            syntax.words(rope, encoding),
+            syntax.diagnostics(rope, encoding),
+        )
+    }
+
+    fn parsed_whole(text: &str) -> (String, Vec<Word>, Vec<Diagnostic>) {
+        let rope = Rope::from_str(text);
+        let locate = |range| locate(&rope, 0, range, PositionEncoding::Utf16);
+        let node = neorg_syntax::document(&mut neorg_syntax::Parser::new(text));
+        let words = get_kinds(SyntaxKind::Word, node.clone())
+            .into_iter()
+            .map(|word| Word {
+                text: word.text().clone(),
+                range: locate(word.range()),
+            })
+            .collect();
+        let diagnostics = get_diagnostics(node.clone())
+            .into_iter()
+            .map(|diagnostic| Diagnostic {
+                range: locate(diagnostic.range),
+                ..diagnostic
+            })
+            .collect();
+        (node.text().clone(), words, diagnostics)
+    }
+
+    /// Random edits, each reparsing only the sections the index rescanned,
+    /// leave the CST reading as parsing the edited text as a whole.
+    #[test]
+    fn spliced_tree_matches_full_parse() {
+        for seed in 1..200 {
+            let mut rng = Rng(seed);
+            let mut text = document(&mut rng);
+            let mut sections = Sections::parse(&text, PositionEncoding::Utf16);
+            let mut syntax = Syntax::new(sections.lens());
+            let rope = Rope::from_str(&text);
+            syntax.parse(&rope);
+            assert_eq!(observed(&syntax, &rope), parsed_whole(&text), "seed {seed}");
+
+            for _ in 0..30 {
+                // every snippet is ascii, so any byte offset is a char boundary
+                let start = rng.below(text.len() + 1);
+                let end = start + rng.below((text.len() - start).min(30) + 1);
+                let insert = if rng.below(3) == 0 {
+                    format!("\n{}", rng.pick(LINES))
+                } else {
+                    rng.pick(INSERTS).to_owned()
+                };
+
+                let first = line_of(&text, start);
+                let old_last = line_of(&text, end);
+                text.replace_range(start..end, &insert);
+                let new_last = line_of(&text, start + insert.len());
+
+                let rope = Rope::from_str(&text);
+                let splice = sections.edit(&rope, first, old_last, new_last);
+                let replaced = splice.lens.len();
+                syntax.splice(splice);
+                assert_eq!(syntax.parse(&rope), replaced, "seed {seed}");
+                assert_eq!(
+                    observed(&syntax, &rope),
+                    parsed_whole(&text),
+                    "seed {seed}: {text:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn edits_reparse_only_their_section() {
+        let text = "intro\n* One\ntext\n* Two\nmore\n";
+        let mut sections = Sections::parse(text, PositionEncoding::Utf16);
+        let mut syntax = Syntax::new(sections.lens());
+        assert_eq!(syntax.parse(&Rope::from_str(text)), 3);
+
+        let text = "intro\n* One\ntext\n* Two\nmore words\n";
+        let rope = Rope::from_str(text);
+        let splice = sections.edit(&rope, 4, 4, 4);
+        assert_eq!(splice.at, 2);
+        syntax.splice(splice);
+        assert_eq!(syntax.parse(&rope), 1);
+        assert_eq!(observed(&syntax, &rope), parsed_whole(text));
+    }
+
+    #[test]
+    fn ranges_move_to_the_section_and_encoding() {
+        let rope = Rope::from_str("* Café\nnaïve 😀 word\n");
+        // `word` on the first line of a section starting at line 1
+        let range = Range::new(Position::new(0, 8), Position::new(0, 12));
+        let expected = |start, end| Range::new(Position::new(1, start), Position::new(1, end));
+        assert_eq!(
+            locate(&rope, 1, range, PositionEncoding::Utf8),
+            expected(12, 16)
+        );
+        assert_eq!(
+            locate(&rope, 1, range, PositionEncoding::Utf16),
+            expected(9, 13)
+        );
+        assert_eq!(
+            locate(&rope, 1, range, PositionEncoding::Utf32),
+            expected(8, 12)
+        );
+    }
+}
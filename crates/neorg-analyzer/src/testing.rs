@@ -0,0 +1,120 @@
+//! Helpers shared by the tests.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tower_lsp::lsp_types::{
+    DidChangeTextDocumentParams, DidOpenTextDocumentParams, TextDocumentContentChangeEvent,
+    TextDocumentItem, Url, VersionedTextDocumentIdentifier,
+};
+use tower_lsp::{LanguageServer, LspService};
+
+use crate::backend::Backend;
+
+/// xorshift64, so that a failing case reproduces from its seed.
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Uniform enough in `0..n`, `n` must not be zero.
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    pub(crate) fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len())]
+    }
+}
+
+/// A fresh directory below the system's temporary one, removed on drop.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub(crate) fn new() -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "neorg-analyzer-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        );
+        let dir = std::env::temp_dir().join(name);
+        _ = std::fs::remove_dir_all(&dir);
+        _ = std::fs::create_dir_all(&dir);
+        Self(dir)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Writes `text` to `name`, creating its parents, and returns its path.
+    pub(crate) fn write(&self, name: &str, text: &str) -> PathBuf {
+        let path = self.0.join(name);
+        if let Some(parent) = path.parent() {
+            _ = std::fs::create_dir_all(parent);
+        }
+        _ = std::fs::write(&path, text);
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A backend without a client, whose workspace is a temporary directory.
+pub(crate) struct Server {
+    service: LspService<Backend>,
+    pub(crate) root: TempDir,
+}
+
+impl Server {
+    pub(crate) fn new() -> Self {
+        let (service, _) = LspService::new(Backend::new);
+        let root = TempDir::new();
+        service.inner().index.add_root(root.path().to_owned());
+        Self { service, root }
+    }
+
+    pub(crate) fn backend(&self) -> &Backend {
+        self.service.inner()
+    }
+
+    /// Uri of `name` in the workspace.
+    pub(crate) fn uri(&self, name: &str) -> Url {
+        Url::from_file_path(self.root.path().join(name)).unwrap_or_else(|()| panic!("{name}"))
+    }
+
+    /// Opens `name` with `text`, at version 1.
+    pub(crate) async fn open(&self, name: &str, text: &str) -> Url {
+        let uri = self.uri(name);
+        let text_document =
+            TextDocumentItem::new(uri.clone(), "norg".to_owned(), 1, text.to_owned());
+        self.backend()
+            .did_open(DidOpenTextDocumentParams { text_document })
+            .await;
+        uri
+    }
+
+    /// Replaces the text of the open `uri`.
+    pub(crate) async fn change(&self, uri: &Url, version: i32, text: &str) {
+        let change = TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: text.to_owned(),
+        };
+        let params = DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new(uri.clone(), version),
+            content_changes: vec![change],
+        };
+        self.backend().did_change(params).await;
+    }
+}
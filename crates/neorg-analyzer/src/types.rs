@@ -2,7 +2,7 @@
 
 use std::ops::Deref;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 pub struct PlainText {
     ctx: ecow::EcoString,
@@ -32,33 +32,37 @@ impl PlainText {
 
 pub type Synonym<'a> = &'a str;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub(crate) struct DictionaryEntry {
     pub(crate) word: String,
     pub(crate) phonetic: Option<String>,
+    #[serde(default)]
     pub(crate) phonetics: Vec<Phonetic>,
     pub(crate) origin: Option<String>,
     pub(crate) meanings: Vec<Meaning>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub(crate) struct Phonetic {
     pub(crate) text: Option<String>,
     pub(crate) audio: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub(crate) struct Meaning {
     #[serde(rename = "partOfSpeech")]
     pub(crate) part_of_speech: String,
     pub(crate) definitions: Vec<Definition>,
+    #[serde(default)]
     pub(crate) synonyms: Vec<String>,
 }
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub(crate) struct Definition {
     pub(crate) definition: String,
     pub(crate) example: Option<String>,
+    #[serde(default)]
     pub(crate) synonyms: Vec<String>,
+    #[serde(default)]
     pub(crate) antonyms: Vec<String>,
 }
 
@@ -83,6 +87,7 @@ impl Deref for Neorg {
 }
 
 impl From<&Meaning> for MarkDown {
+    #[allow(clippy::explicit_counter_loop)]
     fn from(value: &Meaning) -> Self {
         let mut md = String::new();
         let heading = format!("## {}\n\n", value.part_of_speech);
@@ -142,6 +147,7 @@ impl From<&DictionaryEntry> for MarkDown {
 }
 
 impl From<&Meaning> for Neorg {
+    #[allow(clippy::explicit_counter_loop)]
     fn from(value: &Meaning) -> Self {
         let mut md = String::new();
         let heading = format!("** {}\n\n", value.part_of_speech);